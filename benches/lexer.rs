@@ -0,0 +1,51 @@
+// Compares the `char` iterator lexer against the `&str` one on a large
+// generated source, run with `cargo bench`. Both intern every word, the
+// difference is only the `String` the `char` one builds for each word
+// before looking it up.
+
+#[allow(unused_imports)]
+#[path = "../src/chars_input.rs"]
+mod chars_input;
+#[allow(dead_code, unused_imports)]
+#[path = "../src/lexer.rs"]
+mod lexer;
+#[allow(dead_code, unused_imports)]
+#[path = "../src/symbol.rs"]
+mod symbol;
+
+use lexer::*;
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const ITERATIONS: u32 = 20;
+
+fn generate_source() -> String {
+    let mut source = String::new();
+    for i in 0..100_000 {
+        source.push_str(&format!("print!(({} + foo{}) * 35 % bar, {} ** 2 != baz && qux >= 10)\n", i, i % 100, i));
+    }
+    source
+}
+
+fn bench(name: &str, source: &str, lex: impl Fn(&str) -> usize) {
+    let mut total = Duration::ZERO;
+    let mut tokens = 0;
+    for _ in 0..ITERATIONS {
+        let now = Instant::now();
+        tokens = black_box(lex(black_box(source)));
+        total += now.elapsed();
+    }
+
+    let average = total / ITERATIONS;
+    let throughput = source.len() as f64 / average.as_secs_f64() / (1024.0 * 1024.0);
+    println!("{:<12} {:>8} tokens  {:>10.3?}/iter  {:>8.1} MiB/s", name, tokens, average, throughput);
+}
+
+fn main() {
+    let source = generate_source();
+    println!("lexing {} bytes, {} iterations", source.len(), ITERATIONS);
+
+    bench("from_chars", &source, |source| Lexer::from_chars(source.chars()).count());
+    bench("from_source", &source, |source| Lexer::from_source(source).count());
+}
@@ -1,28 +1,34 @@
 use crate::ir::*;
 use crate::lexer::*;
-use crate::lexer_type;
-use crate::LexerTokenKind::*;
+use crate::symbol::*;
+use crate::types::*;
 
-use std::iter::Peekable;
 use std::fmt::*;
 
 // DangName
-type DangName = String;
+pub type DangName = Symbol;
 
 // DangNumber
-type DangNumber = i64;
+pub type DangNumber = u64;
 
 // -=-=-=-= begin DangOperation =-=-=-=-
 
-#[allow(dead_code)]
-#[derive(Clone)]
+#[derive(Clone, Copy, PartialEq)]
 pub enum DangBinaryOperationType {
     Plus,
     Minus,
     Division,
     Multiplication,
     Mod,
-    Power
+    Power,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    And,
+    Or
 }
 
 impl DangBinaryOperationType {
@@ -34,62 +40,217 @@ impl DangBinaryOperationType {
             Division       => "/".to_string(),
             Multiplication => "*".to_string(),
             Mod            => "%".to_string(),
-            Power          => "^".to_string()
+            Power          => "**".to_string(),
+            Equal          => "==".to_string(),
+            NotEqual       => "!=".to_string(),
+            Less           => "<".to_string(),
+            LessEqual      => "<=".to_string(),
+            Greater        => ">".to_string(),
+            GreaterEqual   => ">=".to_string(),
+            And            => "&&".to_string(),
+            Or             => "||".to_string()
         }
     }
 
-    pub fn as_ir_instruction(&self) -> IrInstruction {
+    // `signed` is whether the operands are signed integers, it picks between
+    // `idiv` and `div`, between signed and unsigned comparisons and whether a
+    // negative exponent is an error
+    pub fn as_ir_instruction(&self, signed: bool) -> IrInstruction {
         use DangBinaryOperationType::*;
-        match self {
-            Plus =>           IrInstruction { instruction_type: IrInstructionType::Plus, operand: 0 },
-            Minus =>          IrInstruction { instruction_type: IrInstructionType::Minus, operand: 0 },
-            Division =>       IrInstruction { instruction_type: IrInstructionType::Division, operand: 0 },
-            Multiplication => IrInstruction { instruction_type: IrInstructionType::Multiplication, operand: 0 },
-            Mod =>            IrInstruction { instruction_type: IrInstructionType::Mod, operand: 0 },
-            Power => todo!()
+        match (self, signed) {
+            (Plus, _) =>            IrInstruction { instruction_type: IrInstructionType::Plus, operand: 0 },
+            (Minus, _) =>           IrInstruction { instruction_type: IrInstructionType::Minus, operand: 0 },
+            (Division, true) =>     IrInstruction { instruction_type: IrInstructionType::Division, operand: 0 },
+            (Division, false) =>    IrInstruction { instruction_type: IrInstructionType::UnsignedDivision, operand: 0 },
+            (Multiplication, _) =>  IrInstruction { instruction_type: IrInstructionType::Multiplication, operand: 0 },
+            (Mod, true) =>          IrInstruction { instruction_type: IrInstructionType::Mod, operand: 0 },
+            (Mod, false) =>         IrInstruction { instruction_type: IrInstructionType::UnsignedMod, operand: 0 },
+            (Equal, _) =>           IrInstruction { instruction_type: IrInstructionType::Equal, operand: 0 },
+            (NotEqual, _) =>        IrInstruction { instruction_type: IrInstructionType::NotEqual, operand: 0 },
+            (Less, true) =>         IrInstruction { instruction_type: IrInstructionType::Less, operand: 0 },
+            (Less, false) =>        IrInstruction { instruction_type: IrInstructionType::UnsignedLess, operand: 0 },
+            (LessEqual, true) =>    IrInstruction { instruction_type: IrInstructionType::LessEqual, operand: 0 },
+            (LessEqual, false) =>   IrInstruction { instruction_type: IrInstructionType::UnsignedLessEqual, operand: 0 },
+            (Greater, true) =>      IrInstruction { instruction_type: IrInstructionType::Greater, operand: 0 },
+            (Greater, false) =>     IrInstruction { instruction_type: IrInstructionType::UnsignedGreater, operand: 0 },
+            (GreaterEqual, true) => IrInstruction { instruction_type: IrInstructionType::GreaterEqual, operand: 0 },
+            (GreaterEqual, false) => IrInstruction { instruction_type: IrInstructionType::UnsignedGreaterEqual, operand: 0 },
+            (Power, true) =>        IrInstruction { instruction_type: IrInstructionType::Power, operand: 0 },
+            (Power, false) =>       IrInstruction { instruction_type: IrInstructionType::UnsignedPower, operand: 0 },
+            (And | Or, _) => panic!("unreachable: `&&` and `||` short-circuit through jumps")
         }
     }
+
+    pub fn is_comparison(&self) -> bool {
+        use DangBinaryOperationType::*;
+        matches!(self, Equal | NotEqual | Less | LessEqual | Greater | GreaterEqual)
+    }
+
+    pub fn is_logical(&self) -> bool {
+        use DangBinaryOperationType::*;
+        matches!(self, And | Or)
+    }
 }
 
 #[derive(Clone)]
 pub struct DangOperation {
     pub binary_operation_type: DangBinaryOperationType,
-    pub first_operand: Vec<DangStatement>,
-    pub second_operand: Vec<DangStatement>
+    pub first_operand: Box<DangStatement>,
+    pub second_operand: Box<DangStatement>
 }
 
 impl Display for DangOperation {
     fn fmt(&self, f: &mut Formatter) -> Result {
         write!(f, "(")?;
-        write!(f, "{} ", self.first_operand[0])?;
+        write!(f, "{} ", self.first_operand)?;
         write!(f, "{}", self.binary_operation_type.as_string())?;
-        write!(f, " {}", self.second_operand[0])?;
+        write!(f, " {}", self.second_operand)?;
         write!(f, ")")?;
         Ok(())
     }
 }
 
 impl DangOperation {
-    pub fn new() -> DangOperation {
-        DangOperation {
-            binary_operation_type: DangBinaryOperationType::Plus,
-            first_operand: vec![],
-            second_operand: vec![]
+    pub fn parse_into_operantions(&self, ir: &mut Ir) {
+        use DangBinaryOperationType::*;
+        self.first_operand.parse_into_operantions(ir);
+
+        match self.binary_operation_type {
+            // the second operand is only evaluated when the first one did not
+            // already decide the result
+            And | Or => {
+                if self.binary_operation_type == Or {
+                    ir.push(IrInstruction { instruction_type: IrInstructionType::Not, operand: 0 });
+                }
+                let short_circuit = ir.push_jump(IrInstructionType::JumpIfFalse);
+                self.second_operand.parse_into_operantions(ir);
+                let end = ir.push_jump(IrInstructionType::Jump);
+                ir.patch_jump(short_circuit);
+                let result = if self.binary_operation_type == Or { 1 } else { 0 };
+                ir.push(IrInstruction { instruction_type: IrInstructionType::PushInt, operand: result });
+                ir.patch_jump(end);
+            }
+            _ => parse_second_operand_into_operantions(ir, self.binary_operation_type, self.first_operand.ty, &self.second_operand)
+        }
+    }
+}
+
+// pushes `second` and applies `binary_operation_type` to the value of type
+// `first` under it, for everything but `&&` and `||`
+fn parse_second_operand_into_operantions(ir: &mut Ir, binary_operation_type: DangBinaryOperationType, first: DangType, second: &DangStatement) {
+    use DangBinaryOperationType::*;
+    match binary_operation_type {
+        // pointers move by whole pointees, like in C
+        Plus | Minus if matches!(first, DangType::Pointer(_)) => {
+            let DangType::Pointer(pointee) = first else { unreachable!() };
+            let size = pointee.size().max(1);
+
+            second.parse_into_operantions(ir);
+            if second.ty.as_integer().is_some() {
+                ir.push(IrInstruction { instruction_type: IrInstructionType::PushInt, operand: size });
+                ir.push(IrInstruction { instruction_type: IrInstructionType::Multiplication, operand: 0 });
+                ir.push(binary_operation_type.as_ir_instruction(false));
+            } else {
+                ir.push(binary_operation_type.as_ir_instruction(false));
+                ir.push(IrInstruction { instruction_type: IrInstructionType::PushInt, operand: size });
+                ir.push(IrInstruction { instruction_type: IrInstructionType::Division, operand: 0 });
+            }
+        }
+        _ => {
+            second.parse_into_operantions(ir);
+
+            // bools and pointers compare as unsigned
+            let integer_type = first.as_integer();
+            let signed = integer_type.is_some_and(|x| x.is_signed());
+            ir.push(binary_operation_type.as_ir_instruction(signed));
+
+            if let Some(integer_type) = integer_type.filter(|_| !binary_operation_type.is_comparison()) {
+                ir.push_extend(integer_type);
+            }
+        }
+    }
+}
+
+// -=-=-=-= end DangOperation =-=-=-=-
+
+// -=-=-=-= begin DangUnaryOperation =-=-=-=-
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum DangUnaryOperationType {
+    Negate,
+    Not
+}
+
+impl DangUnaryOperationType {
+    pub fn as_string(&self) -> String {
+        use DangUnaryOperationType::*;
+        match self {
+            Negate => "-".to_string(),
+            Not    => "!".to_string()
+        }
+    }
+
+    pub fn as_ir_instruction(&self) -> IrInstruction {
+        use DangUnaryOperationType::*;
+        match self {
+            Negate => IrInstruction { instruction_type: IrInstructionType::Negate, operand: 0 },
+            Not    => IrInstruction { instruction_type: IrInstructionType::Not, operand: 0 }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct DangUnaryOperation {
+    pub unary_operation_type: DangUnaryOperationType,
+    pub operand: Box<DangStatement>
+}
+
+impl Display for DangUnaryOperation {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        write!(f, "{}{}", self.unary_operation_type.as_string(), self.operand)
+    }
+}
+
+impl DangUnaryOperation {
+    pub fn parse_into_operantions(&self, ir: &mut Ir) {
+        self.operand.parse_into_operantions(ir);
+        ir.push(self.unary_operation_type.as_ir_instruction());
+
+        if let Some(integer_type) = self.operand.ty.as_integer() {
+            ir.push_extend(integer_type);
         }
     }
+}
+
+// -=-=-=-= end DangUnaryOperation =-=-=-=-
+
+// -=-=-=-= begin DangCast =-=-=-=-
+
+#[derive(Clone)]
+pub struct DangCast {
+    pub operand: Box<DangStatement>,
+    pub target: DangType
+}
 
-    pub fn is_empty(&self) -> bool {
-        self.first_operand.len() == 0 && self.second_operand.len() == 0
+impl Display for DangCast {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        write!(f, "({} as {})", self.operand, self.target)
     }
+}
 
+impl DangCast {
+    // every integer is kept sign or zero extended to 64 bits, so a cast is
+    // just truncating to the target and extending back the target's way
     pub fn parse_into_operantions(&self, ir: &mut Ir) {
-        self.first_operand[0].parse_into_operantions(ir);
-        self.second_operand[0].parse_into_operantions(ir);
-        ir.push(self.binary_operation_type.as_ir_instruction());
+        self.operand.parse_into_operantions(ir);
+
+        if let Some(integer_type) = self.target.as_integer() {
+            ir.push_extend(integer_type);
+        }
     }
 }
 
-// -=-=-=-= end DangOperation =-=-=-=-
+// -=-=-=-= end DangCast =-=-=-=-
 
 // -=-=-=-= begin DangExpression =-=-=-=-
 
@@ -113,16 +274,6 @@ impl Display for DangExpression {
 }
 
 impl DangExpression {
-    pub fn new() -> DangExpression {
-        DangExpression {
-            symbols: vec![]
-        }
-    }
-
-    pub fn is_empty(&self) -> bool {
-        self.symbols.len() == 0
-    }
-
     pub fn parse_into_operantions(&self, ir: &mut Ir) {
         self.symbols[0].parse_into_operantions(ir)
     }
@@ -143,7 +294,7 @@ impl Display for DangBlock {
         for symbol in 0..self.symbols.len() {
             write!(f, "{}", self.symbols[symbol])?;
             if (symbol + 1) != self.symbols.len() {
-                write!(f, ", ")?;
+                write!(f, "; ")?;
             }
         }
         write!(f, "}}")?;
@@ -152,34 +303,97 @@ impl Display for DangBlock {
 }
 
 impl DangBlock {
-    pub fn new() -> DangBlock {
-        DangBlock {
-            symbols: vec![]
+    pub fn parse_into_operantions(&self, ir: &mut Ir) {
+        for symbol in &self.symbols {
+            symbol.parse_into_operantions_unused(ir)
         }
     }
+}
+
+// -=-=-=-= end DangBlock =-=-=-=-
 
-    pub fn is_empty(&self) -> bool {
-        self.symbols.len() == 0
+// -=-=-=-= begin DangIf =-=-=-=-
+
+#[derive(Clone)]
+pub struct DangIf {
+    pub condition: Box<DangStatement>,
+    pub then_block: DangBlock,
+    pub else_block: Option<DangBlock>
+}
+
+impl Display for DangIf {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        write!(f, "if {} {}", self.condition, self.then_block)?;
+        if let Some(else_block) = &self.else_block {
+            write!(f, " else {}", else_block)?;
+        }
+        Ok(())
     }
 }
 
-// -=-=-=-= end DangBlock =-=-=-=-
+impl DangIf {
+    pub fn parse_into_operantions(&self, ir: &mut Ir) {
+        self.condition.parse_into_operantions(ir);
+        let skip_then = ir.push_jump(IrInstructionType::JumpIfFalse);
+        self.then_block.parse_into_operantions(ir);
+
+        if let Some(else_block) = &self.else_block {
+            let skip_else = ir.push_jump(IrInstructionType::Jump);
+            ir.patch_jump(skip_then);
+            else_block.parse_into_operantions(ir);
+            ir.patch_jump(skip_else);
+        } else {
+            ir.patch_jump(skip_then);
+        }
+    }
+}
+
+// -=-=-=-= end DangIf =-=-=-=-
 
 // -=-=-=-= begin DangBuiltIn =-=-=-=-
 
 pub enum DangBuiltIn {
     Print,
+    // `load!(address, bytes)` and `store!(address, value, bytes)` reach
+    // memory at any address, `bytes` is a literal 1, 2, 4 or 8
+    Load,
+    Store,
+    // `syscall!(number, arguments...)` with up to 6 arguments, evaluates to
+    // what the kernel returned
+    Syscall,
+    // `exit!(status)` ends the program right away
+    Exit,
+    // `read_int!()` skips whitespace and reads a decimal integer from stdin,
+    // `read_line!(buffer, capacity)` reads a line into a buffer and
+    // evaluates to its length, or -1 at the end of the input
+    ReadInt,
+    ReadLine,
+    // `argc!()` is the number of command-line arguments, `arg!(i)` and
+    // `env!(i)` point to the nul terminated argument or environment
+    // variable `i`, or are null past the last one
+    Argc,
+    Arg,
+    Env,
     Count,
     Unknown
 }
 
 impl DangBuiltIn {
-    fn from_string(name: &str) -> DangBuiltIn {
-        assert_eq!(DangBuiltIn::Count as i64, 1);
+    pub fn from_symbol(name: DangName) -> DangBuiltIn {
+        assert_eq!(DangBuiltIn::Count as i64, 10);
 
         match name {
-            "print" => DangBuiltIn::Print,
-            &_      => DangBuiltIn::Unknown
+            Symbol::PRINT     => DangBuiltIn::Print,
+            Symbol::LOAD      => DangBuiltIn::Load,
+            Symbol::STORE     => DangBuiltIn::Store,
+            Symbol::SYSCALL   => DangBuiltIn::Syscall,
+            Symbol::EXIT      => DangBuiltIn::Exit,
+            Symbol::READ_INT  => DangBuiltIn::ReadInt,
+            Symbol::READ_LINE => DangBuiltIn::ReadLine,
+            Symbol::ARGC      => DangBuiltIn::Argc,
+            Symbol::ARG       => DangBuiltIn::Arg,
+            Symbol::ENV       => DangBuiltIn::Env,
+            _                 => DangBuiltIn::Unknown
         }
     }
 }
@@ -192,7 +406,9 @@ impl DangBuiltIn {
 pub struct DangFunctionCall {
     pub name: DangName,
     pub parameters: Vec<DangStatement>,
-    pub is_built_in: bool
+    pub is_built_in: bool,
+    // index of the called `DangFunction`, filled in by the `TypeChecker`
+    pub function: Option<usize>
 }
 
 impl Display for DangFunctionCall {
@@ -216,191 +432,649 @@ impl Display for DangFunctionCall {
 }
 
 impl DangFunctionCall {
-    pub fn new() -> DangFunctionCall {
-        DangFunctionCall {
-            name: "".to_string(),
-            parameters: vec![],
-            is_built_in: false
+    // arity and types of the parameters were already checked by the
+    // `TypeChecker`
+    pub fn parse_into_operantions(&self, ir: &mut Ir) {
+        if self.is_built_in && self.name == Symbol::EXIT {
+            ir.push_exit(|ir| self.parameters[0].parse_into_operantions(ir));
+            return;
         }
+
+        // the width of `load!` and `store!` is not a value
+        let width = match DangBuiltIn::from_symbol(self.name) {
+            DangBuiltIn::Load | DangBuiltIn::Store if self.is_built_in => self.parameters.last().map(|x| match x.kind {
+                DangStatementKind::Number(number) => number as i64,
+                _ => panic!("unreachable: widths that are not literals are reported by the type checker")
+            }),
+            _ => None
+        };
+        let values = if width.is_some() { &self.parameters[..self.parameters.len() - 1] } else { &self.parameters[..] };
+        for param in values {
+            param.parse_into_operantions(ir)
+        }
+
+        if self.is_built_in {
+            match DangBuiltIn::from_symbol(self.name) {
+                DangBuiltIn::Load => ir.push(IrInstruction { instruction_type: IrInstructionType::Load, operand: width.unwrap() }),
+                DangBuiltIn::Store => ir.push(IrInstruction { instruction_type: IrInstructionType::Store, operand: width.unwrap() }),
+                DangBuiltIn::Syscall => {
+                    let arguments = self.parameters.len() as i64 - 1;
+                    ir.push(IrInstruction { instruction_type: IrInstructionType::Syscall, operand: arguments })
+                }
+                DangBuiltIn::Print => {
+                    let signed = self.parameters[0].ty.as_integer().is_some_and(|x| x.is_signed());
+                    let instruction_type = if signed { IrInstructionType::PrintSigned } else { IrInstructionType::Print };
+                    ir.push(IrInstruction { instruction_type, operand: 0 })
+                },
+                DangBuiltIn::ReadInt => ir.push(IrInstruction { instruction_type: IrInstructionType::ReadInt, operand: 0 }),
+                DangBuiltIn::ReadLine => ir.push(IrInstruction { instruction_type: IrInstructionType::ReadLine, operand: 0 }),
+                DangBuiltIn::Argc => ir.push(IrInstruction { instruction_type: IrInstructionType::Argc, operand: 0 }),
+                DangBuiltIn::Arg => ir.push(IrInstruction { instruction_type: IrInstructionType::Arg, operand: 0 }),
+                DangBuiltIn::Env => ir.push(IrInstruction { instruction_type: IrInstructionType::Env, operand: 0 }),
+                DangBuiltIn::Exit => panic!("unreachable"),
+                DangBuiltIn::Unknown => panic!("unreachable: unknown built-ins are reported by the type checker"),
+                DangBuiltIn::Count => panic!("unreachable")
+            }
+        } else {
+            ir.push(IrInstruction { instruction_type: IrInstructionType::Call, operand: self.function.unwrap() as i64 })
+        }
+    }
+}
+
+// -=-=-=-= end DangFunctionCall =-=-=-=-
+
+// -=-=-=-= begin DangVariable =-=-=-=-
+
+#[derive(Clone, Copy)]
+pub struct DangVariable {
+    pub name: DangName,
+    // where the variable lives relative to the frame pointer, filled in by
+    // the `TypeChecker`
+    pub offset: i64
+}
+
+impl DangVariable {
+    pub fn new(name: DangName) -> DangVariable {
+        DangVariable { name, offset: 0 }
     }
+}
 
-    pub fn is_empty(&self) -> bool {
-        self.name == "" && self.parameters.len() == 0
+impl Display for DangVariable {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        write!(f, "{}", self.name)
     }
+}
+
+// -=-=-=-= end DangVariable =-=-=-=-
 
-    pub fn parse_function_call(&mut self, mut lexer: lexer_type!()) {
-        let name = lexer.next().unwrap().value.string;
-        let mut built_in = false;
-        let mut parameters: Vec<DangStatement> = Vec::new();
+// -=-=-=-= begin DangLet =-=-=-=-
 
-        if lexer.peek().unwrap().kind == ExclamationMark {
-            lexer.next();
-            built_in = true;
+#[derive(Clone)]
+pub struct DangLet {
+    pub variable: DangVariable,
+    pub annotation: Option<DangType>,
+    pub value: Box<DangStatement>
+}
+
+impl Display for DangLet {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        write!(f, "let {}", self.variable)?;
+        if let Some(annotation) = self.annotation {
+            write!(f, ": {}", annotation)?;
         }
+        write!(f, " = {}", self.value)
+    }
+}
 
-        assert!(lexer.next().unwrap().kind == OpenParen);
+impl DangLet {
+    pub fn parse_into_operantions(&self, ir: &mut Ir) {
+        if self.value.ty.is_aggregate() {
+            ir.push(IrInstruction { instruction_type: IrInstructionType::LocalAddress, operand: self.variable.offset });
+            self.value.parse_into_operantions(ir);
+            ir.push(IrInstruction { instruction_type: IrInstructionType::Copy, operand: self.value.ty.size() });
+        } else {
+            self.value.parse_into_operantions(ir);
+            ir.push(IrInstruction { instruction_type: IrInstructionType::StoreLocal, operand: self.variable.offset });
+        }
+    }
+}
 
-        let mut statement = DangStatement::new();
+// -=-=-=-= end DangLet =-=-=-=-
 
-        let mut stack: Vec<LexerToken> = Vec::new();
-        while let Some(token) = lexer.next_if(|x| x.kind != CloseParen) {
-            match token.kind {
-                Integer => stack.push(token),
-                Mod => {
-                    let a = stack.pop();
-                    statement.operation.binary_operation_type = DangBinaryOperationType::Mod;
-                    statement.operation.first_operand.push(DangStatement::new());
-                    statement.operation.second_operand.push(DangStatement::new());
-                    statement.operation.first_operand[0].number = Some(a.unwrap().value.integer);
-                    statement.operation.second_operand[0].number = Some(lexer.next().unwrap().value.integer);
-                    parameters.push(statement.clone());
-                }
-                Division => {
-                    let a = stack.pop();
-                    statement.operation.binary_operation_type = DangBinaryOperationType::Division;
-                    statement.operation.first_operand.push(DangStatement::new());
-                    statement.operation.second_operand.push(DangStatement::new());
-                    statement.operation.first_operand[0].number = Some(a.unwrap().value.integer);
-                    statement.operation.second_operand[0].number = Some(lexer.next().unwrap().value.integer);
-                    parameters.push(statement.clone());
-                }
-                Multiplication => {
-                    let a = stack.pop();
-                    statement.operation.binary_operation_type = DangBinaryOperationType::Multiplication;
-                    statement.operation.first_operand.push(DangStatement::new());
-                    statement.operation.second_operand.push(DangStatement::new());
-                    statement.operation.first_operand[0].number = Some(a.unwrap().value.integer);
-                    statement.operation.second_operand[0].number = Some(lexer.next().unwrap().value.integer);
-                    parameters.push(statement.clone());
-                }
-                Minus => {
-                    let a = stack.pop();
-                    statement.operation.binary_operation_type = DangBinaryOperationType::Minus;
-                    statement.operation.first_operand.push(DangStatement::new());
-                    statement.operation.second_operand.push(DangStatement::new());
-                    statement.operation.first_operand[0].number = Some(a.unwrap().value.integer);
-                    statement.operation.second_operand[0].number = Some(lexer.next().unwrap().value.integer);
-                    parameters.push(statement.clone());
-                }
-                Plus => {
-                    let a = stack.pop();
-                    statement.operation.binary_operation_type = DangBinaryOperationType::Plus;
-                    statement.operation.first_operand.push(DangStatement::new());
-                    statement.operation.second_operand.push(DangStatement::new());
-                    statement.operation.first_operand[0].number = Some(a.unwrap().value.integer);
-                    statement.operation.second_operand[0].number = Some(lexer.next().unwrap().value.integer);
-                    parameters.push(statement.clone());
-                }
-                Comma => {
-                    continue
-                }
-                _ => todo!("report: invalid syntax")
+// -=-=-=-= begin DangAssignment =-=-=-=-
+
+#[derive(Clone)]
+pub struct DangAssignment {
+    pub target: Box<DangStatement>,
+    pub value: Box<DangStatement>,
+    // `target += value` is `target = target + value`
+    pub operation: Option<DangBinaryOperationType>,
+    // where the address of a target that is not a variable is kept while
+    // `value` is evaluated, filled in by the `TypeChecker` for the ones with
+    // an operation
+    pub offset: i64
+}
+
+impl Display for DangAssignment {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self.operation {
+            Some(operation) => write!(f, "{} {}= {}", self.target, operation.as_string(), self.value),
+            None            => write!(f, "{} = {}", self.target, self.value)
+        }
+    }
+}
+
+impl DangAssignment {
+    pub fn parse_into_operantions(&self, ir: &mut Ir) {
+        let ty = self.target.ty;
+
+        let Some(operation) = self.operation else {
+            return self.parse_plain_into_operantions(ir);
+        };
+        match &self.target.kind {
+            DangStatementKind::Name(variable) => {
+                ir.push_load_local(variable.offset, ty);
+                parse_second_operand_into_operantions(ir, operation, ty, &self.value);
+                ir.push(IrInstruction { instruction_type: IrInstructionType::StoreLocal, operand: variable.offset })
+            }
+            _ => {
+                self.target.parse_address_into_operantions(ir);
+                ir.push(IrInstruction { instruction_type: IrInstructionType::StoreLocal, operand: self.offset });
+                ir.push(IrInstruction { instruction_type: IrInstructionType::LoadLocal, operand: self.offset });
+                ir.push(IrInstruction { instruction_type: IrInstructionType::LoadLocal, operand: self.offset });
+                ir.push_load(ty);
+                parse_second_operand_into_operantions(ir, operation, ty, &self.value);
+                ir.push(IrInstruction { instruction_type: IrInstructionType::Store, operand: ty.size() })
             }
         }
+    }
+
+    fn parse_plain_into_operantions(&self, ir: &mut Ir) {
+        let ty = self.target.ty;
 
-        if stack.len() == 1 {
-            let mut statement = DangStatement::new();
-            statement.number = Some(stack.pop().unwrap().value.integer);
-            parameters.push(statement.clone());
+        match &self.target.kind {
+            DangStatementKind::Name(variable) if !ty.is_aggregate() => {
+                self.value.parse_into_operantions(ir);
+                ir.push(IrInstruction { instruction_type: IrInstructionType::StoreLocal, operand: variable.offset })
+            }
+            _ => {
+                self.target.parse_address_into_operantions(ir);
+                self.value.parse_into_operantions(ir);
+                let instruction_type = if ty.is_aggregate() { IrInstructionType::Copy } else { IrInstructionType::Store };
+                ir.push(IrInstruction { instruction_type, operand: ty.size() })
+            }
+        }
+    }
+}
+
+// -=-=-=-= end DangAssignment =-=-=-=-
+
+// -=-=-=-= begin DangArray =-=-=-=-
+
+#[derive(Clone)]
+pub struct DangArray {
+    pub elements: Vec<DangStatement>,
+    // `[value; repeat]` has a single element
+    pub repeat: Option<u64>,
+    // where the array is built in the frame, filled in by the `TypeChecker`
+    pub offset: i64
+}
+
+impl Display for DangArray {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        write!(f, "[")?;
+        for element in 0..self.elements.len() {
+            write!(f, "{}", self.elements[element])?;
+            if (element + 1) != self.elements.len() {
+                write!(f, ", ")?;
+            }
+        }
+        if let Some(repeat) = self.repeat {
+            write!(f, "; {}", repeat)?;
+        }
+        write!(f, "]")
+    }
+}
+
+// stores `value` `offset` bytes from the frame pointer, used to build
+// aggregates in place
+fn store_in_frame(ir: &mut Ir, value: &DangStatement, offset: i64) {
+    ir.push(IrInstruction { instruction_type: IrInstructionType::LocalAddress, operand: offset });
+    value.parse_into_operantions(ir);
+    let instruction_type = if value.ty.is_aggregate() { IrInstructionType::Copy } else { IrInstructionType::Store };
+    ir.push(IrInstruction { instruction_type, operand: value.ty.size() });
+}
+
+impl DangArray {
+    // builds the array in its place in the frame and evaluates to its address
+    pub fn parse_into_operantions(&self, ir: &mut Ir) {
+        // `[value; 0]` takes no room in the frame, `value` is still
+        // evaluated but has nowhere to go
+        if self.repeat == Some(0) {
+            self.elements[0].parse_into_operantions_unused(ir);
         } else {
-            todo!("report: invalid syntax")
+            for (i, element) in self.elements.iter().enumerate() {
+                store_in_frame(ir, element, self.offset + i as i64 * element.ty.size());
+            }
         }
 
-        self.name = name;
-        self.is_built_in = built_in;
-        self.parameters = parameters;
+        // the first element is copied forward over the rest of the array
+        if let Some(repeat) = self.repeat.filter(|x| *x > 1) {
+            let size = self.elements[0].ty.size();
+            ir.push(IrInstruction { instruction_type: IrInstructionType::LocalAddress, operand: self.offset + size });
+            ir.push(IrInstruction { instruction_type: IrInstructionType::LocalAddress, operand: self.offset });
+            ir.push(IrInstruction { instruction_type: IrInstructionType::Copy, operand: (repeat as i64 - 1) * size });
+        }
+
+        ir.push(IrInstruction { instruction_type: IrInstructionType::LocalAddress, operand: self.offset });
     }
+}
 
-    pub fn parse_into_operantions(&self, ir: &mut Ir, used_return: bool) {
-        for param in &self.parameters {
-            param.parse_into_operantions(ir)
+// -=-=-=-= end DangArray =-=-=-=-
+
+// -=-=-=-= begin DangIndex =-=-=-=-
+
+#[derive(Clone)]
+pub struct DangIndex {
+    pub base: Box<DangStatement>,
+    pub index: Box<DangStatement>
+}
+
+impl Display for DangIndex {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        write!(f, "{}[{}]", self.base, self.index)
+    }
+}
+
+impl DangIndex {
+    // arrays evaluate to their address already, so the element is just an
+    // offset away from it
+    pub fn parse_address_into_operantions(&self, ir: &mut Ir) {
+        let DangType::Array(element, length) = self.base.ty else {
+            panic!("unreachable: indexing into something that is not an array is reported by the type checker")
+        };
+
+        self.base.parse_into_operantions(ir);
+        self.index.parse_into_operantions(ir);
+        if ir.bounds_checks {
+            ir.push(IrInstruction { instruction_type: IrInstructionType::BoundsCheck, operand: length as i64 });
         }
+        ir.push(IrInstruction { instruction_type: IrInstructionType::PushInt, operand: element.size() });
+        ir.push(IrInstruction { instruction_type: IrInstructionType::Multiplication, operand: 0 });
+        ir.push(IrInstruction { instruction_type: IrInstructionType::Plus, operand: 0 });
+    }
+}
 
-        if self.is_built_in {
-            match DangBuiltIn::from_string(self.name.as_str()) {
-                DangBuiltIn::Print => {
-                    if used_return {
-                        todo!("report `print` does not return anything")
-                    }
-                    if self.parameters.len() > 1 {
-                        todo!("report `print` does not accept more than 1 arg")
-                    }
-                    ir.push(IrInstruction { instruction_type: IrInstructionType::Print, operand: 0 })
-                },
-                DangBuiltIn::Unknown => todo!("report unknown built-in"),
-                DangBuiltIn::Count => panic!("unreachable")
+// -=-=-=-= end DangIndex =-=-=-=-
+
+// -=-=-=-= begin DangStruct =-=-=-=-
+
+#[derive(Clone)]
+pub struct DangStructDeclarationField {
+    pub name: DangName,
+    pub annotation: DangType,
+    pub span: LexerSpan
+}
+
+#[derive(Clone)]
+pub struct DangStructDeclaration {
+    pub name: DangName,
+    pub fields: Vec<DangStructDeclarationField>,
+    pub span: LexerSpan
+}
+
+impl Display for DangStructDeclaration {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        write!(f, "struct {} {{", self.name)?;
+        for field in 0..self.fields.len() {
+            write!(f, "{}: {}", self.fields[field].name, self.fields[field].annotation)?;
+            if (field + 1) != self.fields.len() {
+                write!(f, ", ")?;
             }
-        } else {
-            todo!("you dont even have function definitions, how do you want to call another function???")
         }
+        write!(f, "}}")
     }
 }
 
-// -=-=-=-= end DangFunctionCall =-=-=-=-
+#[derive(Clone)]
+pub struct DangStructLiteralField {
+    pub name: DangName,
+    pub value: DangStatement,
+    // bytes from the start of the struct, filled in by the `TypeChecker`
+    pub offset: i64
+}
+
+#[derive(Clone)]
+pub struct DangStructLiteral {
+    pub name: DangName,
+    pub fields: Vec<DangStructLiteralField>,
+    // where the struct is built in the frame, filled in by the `TypeChecker`
+    pub offset: i64
+}
+
+impl Display for DangStructLiteral {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        write!(f, "{} {{", self.name)?;
+        for field in 0..self.fields.len() {
+            write!(f, "{}: {}", self.fields[field].name, self.fields[field].value)?;
+            if (field + 1) != self.fields.len() {
+                write!(f, ", ")?;
+            }
+        }
+        write!(f, "}}")
+    }
+}
+
+impl DangStructLiteral {
+    // builds the struct in its place in the frame and evaluates to its
+    // address
+    pub fn parse_into_operantions(&self, ir: &mut Ir) {
+        for field in &self.fields {
+            store_in_frame(ir, &field.value, self.offset + field.offset);
+        }
+        ir.push(IrInstruction { instruction_type: IrInstructionType::LocalAddress, operand: self.offset });
+    }
+}
+
+#[derive(Clone)]
+pub struct DangFieldAccess {
+    // a struct or a pointer to one
+    pub base: Box<DangStatement>,
+    pub field: DangName,
+    // bytes from the start of the struct, filled in by the `TypeChecker`
+    pub offset: i64
+}
+
+impl Display for DangFieldAccess {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        write!(f, "{}.{}", self.base, self.field)
+    }
+}
+
+impl DangFieldAccess {
+    // structs evaluate to their address and pointers are one, either way
+    // the field is just an offset away
+    pub fn parse_address_into_operantions(&self, ir: &mut Ir) {
+        self.base.parse_into_operantions(ir);
+        ir.push(IrInstruction { instruction_type: IrInstructionType::Offset, operand: self.offset });
+    }
+}
+
+// -=-=-=-= end DangStruct =-=-=-=-
+
+// -=-=-=-= begin DangAddressOf =-=-=-=-
+
+#[derive(Clone)]
+pub struct DangAddressOf {
+    pub operand: Box<DangStatement>
+}
+
+impl Display for DangAddressOf {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        write!(f, "&{}", self.operand)
+    }
+}
+
+// -=-=-=-= end DangAddressOf =-=-=-=-
+
+// -=-=-=-= begin DangDereference =-=-=-=-
+
+#[derive(Clone)]
+pub struct DangDereference {
+    pub operand: Box<DangStatement>
+}
+
+impl Display for DangDereference {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        write!(f, "*{}", self.operand)
+    }
+}
+
+// -=-=-=-= end DangDereference =-=-=-=-
+
+// -=-=-=-= begin DangReturn =-=-=-=-
+
+#[derive(Clone)]
+pub struct DangReturn {
+    pub value: Option<Box<DangStatement>>
+}
+
+impl Display for DangReturn {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        write!(f, "return")?;
+        if let Some(value) = &self.value {
+            write!(f, " {}", value)?;
+        }
+        Ok(())
+    }
+}
+
+impl DangReturn {
+    // every function hands exactly one value back, `()` ones a dummy 0
+    pub fn parse_into_operantions(&self, ir: &mut Ir) {
+        match &self.value {
+            Some(value) => value.parse_into_operantions(ir),
+            None        => ir.push(IrInstruction { instruction_type: IrInstructionType::PushInt, operand: 0 })
+        }
+        ir.push(IrInstruction { instruction_type: IrInstructionType::Return, operand: 0 });
+    }
+}
+
+// -=-=-=-= end DangReturn =-=-=-=-
+
+// -=-=-=-= begin DangFunction =-=-=-=-
+
+#[derive(Clone)]
+pub struct DangParameter {
+    pub variable: DangVariable,
+    pub annotation: Option<DangType>,
+    pub span: LexerSpan,
+    // where the caller put the argument, for aggregates that is their
+    // address and `variable` is the copy the function works on, filled in by
+    // the `TypeChecker`
+    pub argument_offset: i64,
+    pub ty: DangType
+}
+
+impl Display for DangParameter {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        write!(f, "{}", self.variable)?;
+        if let Some(annotation) = self.annotation {
+            write!(f, ": {}", annotation)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct DangFunction {
+    pub name: DangName,
+    pub parameters: Vec<DangParameter>,
+    pub return_type: Option<DangType>,
+    pub body: DangBlock,
+    pub span: LexerSpan,
+    // from `#[inline]` or `#[noinline]` before it
+    pub inline: IrInline,
+    // bytes of locals, filled in by the `TypeChecker`
+    pub frame_size: i64
+}
+
+impl Display for DangFunction {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self.inline {
+            IrInline::WhenSmall => {}
+            IrInline::Always    => write!(f, "#[inline] ")?,
+            IrInline::Never     => write!(f, "#[noinline] ")?
+        }
+        write!(f, "fn {}(", self.name)?;
+        for param in 0..self.parameters.len() {
+            write!(f, "{}", self.parameters[param])?;
+            if (param + 1) != self.parameters.len() {
+                write!(f, ", ")?;
+            }
+        }
+        write!(f, ")")?;
+        if let Some(return_type) = self.return_type {
+            write!(f, " -> {}", return_type)?;
+        }
+        write!(f, " {}", self.body)
+    }
+}
+
+impl DangFunction {
+    pub fn parse_into_operantions(&self, ir: &mut Ir) {
+        ir.functions.push(IrFunction {
+            name: self.name,
+            address: ir.operations.len(),
+            parameters: self.parameters.len(),
+            inline: self.inline
+        });
+
+        ir.push(IrInstruction { instruction_type: IrInstructionType::Enter, operand: self.frame_size });
+
+        // aggregates are passed by value, the caller only hands over where
+        // they are
+        for param in self.parameters.iter().filter(|x| x.ty.is_aggregate()) {
+            ir.push(IrInstruction { instruction_type: IrInstructionType::LocalAddress, operand: param.variable.offset });
+            ir.push(IrInstruction { instruction_type: IrInstructionType::LoadLocal, operand: param.argument_offset });
+            ir.push(IrInstruction { instruction_type: IrInstructionType::Copy, operand: param.ty.size() });
+        }
+
+        self.body.parse_into_operantions(ir);
+
+        // falling off the end of a function returns `()`, the `TypeChecker`
+        // makes sure only `()` functions can get here
+        ir.push(IrInstruction { instruction_type: IrInstructionType::PushInt, operand: 0 });
+        ir.push(IrInstruction { instruction_type: IrInstructionType::Return, operand: 0 });
+    }
+}
+
+// -=-=-=-= end DangFunction =-=-=-=-
 
 // -=-=-=-= begin DangStatement =-=-=-=-
 
+#[derive(Clone)]
+pub enum DangStatementKind {
+    Name(DangVariable),
+    Let(DangLet),
+    Assignment(DangAssignment),
+    Return(DangReturn),
+    Expression(DangExpression),
+    FunctionCall(DangFunctionCall),
+    Block(DangBlock),
+    If(DangIf),
+    Operation(DangOperation),
+    UnaryOperation(DangUnaryOperation),
+    Cast(DangCast),
+    Array(DangArray),
+    Index(DangIndex),
+    StructLiteral(DangStructLiteral),
+    FieldAccess(DangFieldAccess),
+    AddressOf(DangAddressOf),
+    Dereference(DangDereference),
+    Number(DangNumber),
+    Bool(bool)
+}
+
 #[derive(Clone)]
 pub struct DangStatement {
-    pub name: DangName,
-    pub expression: DangExpression,
-    pub function_call: DangFunctionCall,
-    pub block: DangBlock,
-    pub operation: DangOperation,
-    pub number: Option<DangNumber>
+    pub kind: DangStatementKind,
+    pub span: LexerSpan,
+    // filled in by the `TypeChecker`
+    pub ty: DangType
 }
 
 impl DangStatement {
-    pub fn new() -> DangStatement {
-        DangStatement {
-            name: "".to_string(),
-            expression: DangExpression::new(),
-            function_call: DangFunctionCall::new(),
-            block: DangBlock::new(),
-            operation: DangOperation::new(),
-            number: None
+    pub fn new(kind: DangStatementKind, span: LexerSpan) -> DangStatement {
+        DangStatement { kind, span, ty: DangType::Unknown }
+    }
+
+    // for statements whose value is thrown away, the `TypeChecker` only lets
+    // function calls have one
+    pub fn parse_into_operantions_unused(&self, ir: &mut Ir) {
+        self.parse_into_operantions(ir);
+
+        if !matches!(self.ty, DangType::Unit | DangType::Unknown) {
+            ir.push(IrInstruction { instruction_type: IrInstructionType::Drop, operand: 0 });
+        }
+    }
+
+    // pushes the address of a place, the `TypeChecker` makes sure that
+    // `self` is one
+    pub fn parse_address_into_operantions(&self, ir: &mut Ir) {
+        use DangStatementKind::*;
+        match &self.kind {
+            Name(variable) => ir.push(IrInstruction { instruction_type: IrInstructionType::LocalAddress, operand: variable.offset }),
+            Expression(expression) => expression.symbols[0].parse_address_into_operantions(ir),
+            Index(index) => index.parse_address_into_operantions(ir),
+            FieldAccess(field_access) => field_access.parse_address_into_operantions(ir),
+            // the pointer is the address
+            Dereference(dereference) => dereference.operand.parse_into_operantions(ir),
+            _ => panic!("unreachable: only places have an address")
         }
     }
 
     pub fn parse_into_operantions(&self, ir: &mut Ir) {
-        if self.name != "".to_string() {
-            todo!()
-        } else if !self.expression.is_empty() {
-            self.expression.parse_into_operantions(ir)
-        } else if !self.function_call.is_empty() {
-            // when a function call is encountered inside another statement
-            // its return value is being used
-            self.function_call.parse_into_operantions(ir, true)
-        } else if !self.block.is_empty() {
-            todo!("report blocks not allowed")
-        } else if !self.operation.is_empty() {
-            self.operation.parse_into_operantions(ir)
-        } else if !self.number.is_none() {
-            ir.push(IrInstruction { instruction_type: IrInstructionType::PushInt, operand: self.number.unwrap() })
-        } else {
-            panic!("unreachable")
+        use DangStatementKind::*;
+        match &self.kind {
+            Name(_) if self.ty.is_aggregate() => self.parse_address_into_operantions(ir),
+            Name(variable) => ir.push_load_local(variable.offset, self.ty),
+            Let(let_statement) => let_statement.parse_into_operantions(ir),
+            Assignment(assignment) => assignment.parse_into_operantions(ir),
+            Return(return_statement) => return_statement.parse_into_operantions(ir),
+            Expression(expression) => expression.parse_into_operantions(ir),
+            FunctionCall(function_call) => {
+                function_call.parse_into_operantions(ir);
+
+                // user functions always return something, even when it is `()`
+                if !function_call.is_built_in && self.ty == DangType::Unit {
+                    ir.push(IrInstruction { instruction_type: IrInstructionType::Drop, operand: 0 });
+                }
+            }
+            Block(block) => block.parse_into_operantions(ir),
+            If(if_statement) => if_statement.parse_into_operantions(ir),
+            Operation(operation) => operation.parse_into_operantions(ir),
+            UnaryOperation(unary_operation) => unary_operation.parse_into_operantions(ir),
+            Cast(cast) => cast.parse_into_operantions(ir),
+            Array(array) => array.parse_into_operantions(ir),
+            Index(_) | FieldAccess(_) | Dereference(_) => {
+                self.parse_address_into_operantions(ir);
+                if !self.ty.is_aggregate() {
+                    ir.push_load(self.ty);
+                }
+            }
+            StructLiteral(struct_literal) => struct_literal.parse_into_operantions(ir),
+            AddressOf(address_of) => address_of.operand.parse_address_into_operantions(ir),
+            Number(number) => ir.push(IrInstruction { instruction_type: IrInstructionType::PushInt, operand: *number as i64 }),
+            Bool(value) => ir.push(IrInstruction { instruction_type: IrInstructionType::PushInt, operand: *value as i64 })
         }
     }
 }
 
 impl Display for DangStatement {
     fn fmt(&self, f: &mut Formatter) -> Result {
-        if self.name != "".to_string() {
-            write!(f, "{}", self.name)?;
-        } else if !self.expression.is_empty() {
-            write!(f, "{}", self.expression)?;
-        } else if !self.function_call.is_empty() {
-            write!(f, "{}", self.function_call)?;
-        } else if !self.block.is_empty() {
-            write!(f, "{}", self.block)?;
-        } else if !self.operation.is_empty() {
-            write!(f, "{}", self.operation)?;
-        } else if !self.number.is_none() {
-            write!(f, "{}", self.number.unwrap())?;
-        } else {
-            write!(f, "?")?;
+        use DangStatementKind::*;
+        match &self.kind {
+            Name(variable)                  => write!(f, "{}", variable),
+            Let(let_statement)              => write!(f, "{}", let_statement),
+            Assignment(assignment)          => write!(f, "{}", assignment),
+            Return(return_statement)        => write!(f, "{}", return_statement),
+            Expression(expression)          => write!(f, "{}", expression),
+            FunctionCall(function_call)     => write!(f, "{}", function_call),
+            Block(block)                    => write!(f, "{}", block),
+            If(if_statement)                => write!(f, "{}", if_statement),
+            Operation(operation)            => write!(f, "{}", operation),
+            UnaryOperation(unary_operation) => write!(f, "{}", unary_operation),
+            Cast(cast)                      => write!(f, "{}", cast),
+            Array(array)                    => write!(f, "{}", array),
+            Index(index)                    => write!(f, "{}", index),
+            StructLiteral(struct_literal)   => write!(f, "{}", struct_literal),
+            FieldAccess(field_access)       => write!(f, "{}", field_access),
+            AddressOf(address_of)           => write!(f, "{}", address_of),
+            Dereference(dereference)        => write!(f, "{}", dereference),
+            Number(number)                  => write!(f, "{}", number),
+            Bool(value)                     => write!(f, "{}", value)
         }
-        Ok(())
     }
 }
 
@@ -409,18 +1083,40 @@ impl Display for DangStatement {
 // -=-=-=-= begin DangAst =-=-=-=-
 
 pub struct DangAst {
-    pub ast: Vec<DangStatement>
+    pub structs: Vec<DangStructDeclaration>,
+    pub functions: Vec<DangFunction>,
+    pub ast: Vec<DangStatement>,
+    // bytes of top level locals, filled in by the `TypeChecker`
+    pub frame_size: i64,
+    // where the exit status comes from, filled in by the `TypeChecker`
+    pub exit_status: DangExitStatus
+}
+
+// a program that runs to its end exits with the value of `main`, which is
+// called after the top level statements, or else with the value of the last
+// top level statement if it is an integer
+#[derive(Clone, Copy, PartialEq)]
+pub enum DangExitStatus {
+    Zero,
+    Main(usize),
+    LastStatement
 }
 
 impl Display for DangAst {
     fn fmt(&self, f: &mut Formatter) -> Result {
-        write!(f, "{{\n")?;
+        for struct_declaration in &self.structs {
+            writeln!(f, "{}", struct_declaration)?;
+        }
+        for function in &self.functions {
+            writeln!(f, "{}", function)?;
+        }
+        writeln!(f, "{{")?;
         for node in 0..self.ast.len() {
             write!(f, "    {}", self.ast[node])?;
             if (node + 1) != self.ast.len() {
                 write!(f, ";")?;
             }
-            write!(f, "\n")?;
+            writeln!(f)?;
         }
         write!(f, "}}")?;
         Ok(())
@@ -431,58 +1127,49 @@ impl Display for DangAst {
 impl DangAst {
     pub fn new() -> DangAst {
         DangAst {
-            ast: vec![]
+            structs: vec![],
+            functions: vec![],
+            ast: vec![],
+            frame_size: 0,
+            exit_status: DangExitStatus::Zero
         }
     }
 
-    pub fn from_tokens(mut lexer: lexer_type!()) -> DangAst {
-        use LexerTokenKind::*;
-        let mut ast = DangAst::new();
-
-        if let Some(name) = lexer.peek() {
-            match name.kind {
-                Word => {
-                    let mut function_call = DangFunctionCall::new();
-                    function_call.parse_function_call(lexer);
-
-                    let mut statement = DangStatement::new();
-                    statement.function_call = function_call;
-
-                    ast.ast.push(statement);
-                }
-                Integer => {
-                    let mut statement = DangStatement::new();
-                    statement.number = Some(name.value.integer);
+    pub fn parse_into_operantions(&self, bounds_checks: bool) -> Ir {
+        let mut ir = Ir::new();
+        ir.bounds_checks = bounds_checks;
 
-                    ast.ast.push(statement);
-                }
-                _ => todo!("report: invalid syntax")
-            }
-        } else {
-            todo!("report: reached EOF")
+        // functions come first, so execution has to jump over them to reach
+        // the top level statements
+        let skip_functions = (!self.functions.is_empty()).then(|| ir.push_jump(IrInstructionType::Jump));
+        for function in &self.functions {
+            function.parse_into_operantions(&mut ir);
+        }
+        if let Some(skip_functions) = skip_functions {
+            ir.patch_jump(skip_functions);
         }
 
-        ast
-    }
+        ir.push(IrInstruction { instruction_type: IrInstructionType::Enter, operand: self.frame_size });
+        let (last, statements) = match self.exit_status {
+            DangExitStatus::LastStatement => (self.ast.last(), &self.ast[..self.ast.len() - 1]),
+            _                             => (None, &self.ast[..])
+        };
+        for node in statements {
+            node.parse_into_operantions_unused(&mut ir)
+        }
 
-    pub fn parse_into_operantions(&self) -> Ir {
-        let mut ir = Ir::new();
+        match self.exit_status {
+            DangExitStatus::Zero => {}
+            // `()` functions return 0 as well
+            DangExitStatus::Main(main) => ir.push_exit(|ir| {
+                ir.push(IrInstruction { instruction_type: IrInstructionType::Call, operand: main as i64 })
+            }),
+            DangExitStatus::LastStatement => ir.push_exit(|ir| last.unwrap().parse_into_operantions(ir))
+        }
 
-        for node in &self.ast {
-            if node.name != "".to_string() {
-                todo!("name")
-            } else if !node.expression.is_empty() {
-                todo!("report: you cant just drop a expression randomly in the code")
-            } else if !node.function_call.is_empty() {
-                node.function_call.parse_into_operantions(&mut ir, false)
-            } else if !node.block.is_empty() {
-                todo!("report: you cant just drop a block randomly in the code")
-            } else if !node.operation.is_empty() {
-                todo!("report: you cant just drop a operation randomly in the code")
-            } else if !node.number.is_none() {
-                todo!("report: you cant just drop a number randomly in the code")
-            } else {
-                panic!("unreachable")
+        if cfg!(debug_assertions) {
+            if let Err(error) = ir.verify() {
+                panic!("unreachable: the generated IR is invalid: {}\n{}", error, ir);
             }
         }
 
@@ -0,0 +1,258 @@
+use crate::ir::*;
+use crate::symbol::*;
+
+// A `.dangc` file, every number is little endian:
+//
+//     header       magic, version, flags, and how many constants, functions
+//                  and instructions follow
+//     constants    a tag byte, then an i64 or a u32 length and the bytes of a
+//                  string
+//     functions    constant index of the name, parameters, address, all u32,
+//                  and a byte for its inlining attribute
+//     instructions an opcode byte whose top two bits say where the operand is
+//
+// Operands that fit in a byte or in 32 bits follow the opcode, bigger ones
+// are in the constant pool, so most instructions take one or two bytes.
+
+pub const BYTECODE_MAGIC: [u8; 4] = *b"DNGC";
+// has to change whenever old files would not be read the same way anymore
+pub const BYTECODE_VERSION: u16 = 3;
+
+const FLAG_BOUNDS_CHECKS: u8 = 1;
+
+const INLINE_WHEN_SMALL: u8 = 0;
+const INLINE_ALWAYS: u8 = 1;
+const INLINE_NEVER: u8 = 2;
+
+const CONSTANT_INTEGER: u8 = 0;
+const CONSTANT_STRING: u8 = 1;
+
+const OPERAND_NONE: u8 = 0b00 << 6;
+const OPERAND_I8: u8 = 0b01 << 6;
+const OPERAND_I32: u8 = 0b10 << 6;
+const OPERAND_CONSTANT: u8 = 0b11 << 6;
+const OPERAND_MASK: u8 = 0b11 << 6;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Constant {
+    Integer(i64),
+    String(Symbol)
+}
+
+#[derive(Default)]
+struct ConstantPool {
+    constants: Vec<Constant>
+}
+
+impl ConstantPool {
+    // equal constants are only stored once
+    fn add(&mut self, constant: Constant) -> u32 {
+        match self.constants.iter().position(|x| *x == constant) {
+            Some(index) => index as u32,
+            None => {
+                self.constants.push(constant);
+                self.constants.len() as u32 - 1
+            }
+        }
+    }
+}
+
+fn opcode(instruction_type: IrInstructionType) -> u8 {
+    INSTRUCTION_NAMES.iter().position(|x| x.0 == instruction_type).unwrap() as u8
+}
+
+impl Ir {
+    pub fn to_bytecode(&self) -> Vec<u8> {
+        let mut pool = ConstantPool::default();
+        let mut functions = vec![];
+        for function in &self.functions {
+            functions.extend(pool.add(Constant::String(function.name)).to_le_bytes());
+            functions.extend((function.parameters as u32).to_le_bytes());
+            functions.extend((function.address as u32).to_le_bytes());
+            functions.push(match function.inline {
+                IrInline::WhenSmall => INLINE_WHEN_SMALL,
+                IrInline::Always    => INLINE_ALWAYS,
+                IrInline::Never     => INLINE_NEVER
+            });
+        }
+
+        let mut instructions = vec![];
+        for operation in &self.operations {
+            let opcode = opcode(operation.instruction_type);
+            let operand = operation.operand;
+            if operand == 0 && !operation.instruction_type.has_operand() {
+                instructions.push(opcode | OPERAND_NONE);
+            } else if let Ok(operand) = i8::try_from(operand) {
+                instructions.push(opcode | OPERAND_I8);
+                instructions.extend(operand.to_le_bytes());
+            } else if let Ok(operand) = i32::try_from(operand) {
+                instructions.push(opcode | OPERAND_I32);
+                instructions.extend(operand.to_le_bytes());
+            } else {
+                instructions.push(opcode | OPERAND_CONSTANT);
+                instructions.extend(pool.add(Constant::Integer(operand)).to_le_bytes());
+            }
+        }
+
+        let mut bytes = vec![];
+        bytes.extend(BYTECODE_MAGIC);
+        bytes.extend(BYTECODE_VERSION.to_le_bytes());
+        bytes.push(if self.bounds_checks { FLAG_BOUNDS_CHECKS } else { 0 });
+        bytes.push(0);
+        bytes.extend((pool.constants.len() as u32).to_le_bytes());
+        bytes.extend((self.functions.len() as u32).to_le_bytes());
+        bytes.extend((self.operations.len() as u32).to_le_bytes());
+
+        for constant in &pool.constants {
+            match constant {
+                Constant::Integer(value) => {
+                    bytes.push(CONSTANT_INTEGER);
+                    bytes.extend(value.to_le_bytes());
+                }
+                Constant::String(symbol) => {
+                    bytes.push(CONSTANT_STRING);
+                    bytes.extend((symbol.as_str().len() as u32).to_le_bytes());
+                    bytes.extend(symbol.as_str().as_bytes());
+                }
+            }
+        }
+        bytes.extend(functions);
+        bytes.extend(instructions);
+        bytes
+    }
+
+    // like `Ir::parse` the result does not have to be valid `Ir`, only the
+    // encoding is checked
+    pub fn from_bytecode(bytes: &[u8]) -> Result<Ir, String> {
+        let mut reader = BytecodeReader { bytes, position: 0 };
+
+        if reader.take(4).ok() != Some(&BYTECODE_MAGIC[..]) {
+            return Err("not a dang bytecode file".to_string());
+        }
+        let version = u16::from_le_bytes(reader.array()?);
+        if version != BYTECODE_VERSION {
+            return Err(format!("bytecode version {} is not supported, expected {}", version, BYTECODE_VERSION));
+        }
+        let flags = reader.u8()?;
+        reader.u8()?;
+        let constant_count = reader.u32()?;
+        let function_count = reader.u32()?;
+        let instruction_count = reader.u32()?;
+
+        let mut constants = vec![];
+        for _ in 0..constant_count {
+            let constant = match reader.u8()? {
+                CONSTANT_INTEGER => Constant::Integer(i64::from_le_bytes(reader.array()?)),
+                CONSTANT_STRING  => {
+                    let length = reader.u32()? as usize;
+                    let name = std::str::from_utf8(reader.take(length)?)
+                        .map_err(|_| format!("string constant {} is not valid UTF-8", constants.len()))?;
+                    Constant::String(Symbol::intern(name))
+                }
+                tag => return Err(format!("unknown constant tag {}", tag))
+            };
+            constants.push(constant);
+        }
+        let constant = |index: u32| constants.get(index as usize).copied().ok_or_else(|| format!("constant {} is out of range", index));
+
+        let mut ir = Ir::new();
+        ir.bounds_checks = flags & FLAG_BOUNDS_CHECKS != 0;
+
+        for _ in 0..function_count {
+            let Constant::String(name) = constant(reader.u32()?)? else {
+                return Err("function name is not a string constant".to_string());
+            };
+            let parameters = reader.u32()? as usize;
+            let address = reader.u32()? as usize;
+            let inline = match reader.u8()? {
+                INLINE_WHEN_SMALL => IrInline::WhenSmall,
+                INLINE_ALWAYS     => IrInline::Always,
+                INLINE_NEVER      => IrInline::Never,
+                attribute         => return Err(format!("unknown inlining attribute {}", attribute))
+            };
+            ir.functions.push(IrFunction { name, address, parameters, inline });
+        }
+
+        for _ in 0..instruction_count {
+            let byte = reader.u8()?;
+            let (instruction_type, _, _) = INSTRUCTION_NAMES.get((byte & !OPERAND_MASK) as usize)
+                .ok_or_else(|| format!("unknown opcode {:#04x}", byte))?;
+            let operand = match byte & OPERAND_MASK {
+                OPERAND_NONE => 0,
+                OPERAND_I8   => i8::from_le_bytes(reader.array()?) as i64,
+                OPERAND_I32  => i32::from_le_bytes(reader.array()?) as i64,
+                _            => match constant(reader.u32()?)? {
+                    Constant::Integer(value) => value,
+                    Constant::String(_)      => return Err("operand is not an integer constant".to_string())
+                }
+            };
+            ir.push(IrInstruction { instruction_type: *instruction_type, operand });
+        }
+
+        if reader.position != bytes.len() {
+            return Err(format!("{} unexpected bytes at the end", bytes.len() - reader.position));
+        }
+        Ok(ir)
+    }
+}
+
+struct BytecodeReader<'a> {
+    bytes: &'a [u8],
+    position: usize
+}
+
+impl<'a> BytecodeReader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+        let bytes = self.bytes.get(self.position..self.position.saturating_add(count))
+            .ok_or_else(|| "unexpected end of file".to_string())?;
+        self.position += count;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile;
+    use crate::ir::tests::*;
+
+    #[test]
+    fn bytecode_round_trips() {
+        let mut ir = compile(PROGRAM);
+        ir.bounds_checks = false;
+        for ir in [every_instruction(), ir] {
+            assert_eq!(Ir::from_bytecode(&ir.to_bytecode()).unwrap(), ir);
+        }
+    }
+
+    #[test]
+    fn bytecode_rejects_truncated_files() {
+        let bytes = compile(PROGRAM).to_bytecode();
+        for length in 0..bytes.len() {
+            assert!(Ir::from_bytecode(&bytes[..length]).is_err(), "{} bytes", length);
+        }
+        let mut extra = bytes.clone();
+        extra.push(0);
+        assert_eq!(Ir::from_bytecode(&extra).unwrap_err(), "1 unexpected bytes at the end");
+    }
+
+    #[test]
+    fn bytecode_rejects_other_versions() {
+        let mut bytes = compile(PROGRAM).to_bytecode();
+        bytes[4..6].copy_from_slice(&(BYTECODE_VERSION + 1).to_le_bytes());
+        assert_eq!(Ir::from_bytecode(&bytes).unwrap_err(), format!("bytecode version {} is not supported, expected {}", BYTECODE_VERSION + 1, BYTECODE_VERSION));
+        assert_eq!(Ir::from_bytecode(b"\x7fELF").unwrap_err(), "not a dang bytecode file");
+    }
+}
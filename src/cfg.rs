@@ -0,0 +1,259 @@
+use crate::ir::*;
+
+use std::fmt;
+
+/// A basic block of `Ir`, the instructions from `start` up to but not
+/// including `end`, control flow only enters it at `start`.
+#[derive(Debug, Clone)]
+pub struct CfgBlock {
+    pub start: usize,
+    pub end: usize,
+    // indices in `Cfg::blocks`, in no particular order
+    pub predecessors: Vec<usize>,
+    pub successors: Vec<usize>
+}
+
+/// The control flow graph of the top level statements or of a function, only
+/// with the blocks that can be reached from its entry, which is the first
+/// one. Control flow that reaches the end of the `Ir` leaves the graph.
+#[derive(Debug, Clone)]
+pub struct Cfg {
+    // index in `Ir::functions`, `None` for the top level statements
+    pub function: Option<usize>,
+    pub blocks: Vec<CfgBlock>,
+    // the immediate dominator of every block, the entry is its own
+    pub dominators: Vec<usize>
+}
+
+// where execution can go after the instruction at `address`, the end of the
+// `Ir` included
+pub fn successors(ir: &Ir, address: usize) -> Vec<usize> {
+    let operation = &ir.operations[address];
+    match operation.instruction_type {
+        IrInstructionType::Jump        => vec![operation.operand as usize],
+        IrInstructionType::JumpIfFalse => vec![address + 1, operation.operand as usize],
+        kind if kind.leaves()          => vec![],
+        _                              => vec![address + 1]
+    }
+}
+
+impl Cfg {
+    // `ir` has to be valid, see `Ir::verify`
+    pub fn new(ir: &Ir, function: Option<usize>) -> Cfg {
+        let length = ir.operations.len();
+        let entry = function.map_or(0, |x| ir.functions[x].address);
+
+        let mut reachable = vec![false; length + 1];
+        let mut work = vec![entry];
+        while let Some(address) = work.pop() {
+            if !reachable[address] {
+                reachable[address] = true;
+                if address < length {
+                    work.extend(successors(ir, address));
+                }
+            }
+        }
+
+        // blocks start wherever control flow can come from somewhere else
+        // than the instruction before
+        let mut leaders = vec![false; length + 1];
+        leaders[entry] = true;
+        for address in (0..length).filter(|x| reachable[*x]) {
+            let operation = &ir.operations[address];
+            if operation.instruction_type.is_jump() || operation.instruction_type.leaves() {
+                leaders[address + 1] = true;
+            }
+            if operation.instruction_type.is_jump() {
+                leaders[operation.operand as usize] = true;
+            }
+        }
+
+        let starts: Vec<usize> = std::iter::once(entry).chain((0..length).filter(|x| *x != entry && reachable[*x] && leaders[*x])).collect();
+        let mut blocks: Vec<CfgBlock> = starts.iter().map(|&start| {
+            let end = (start + 1..=length).find(|x| leaders[*x] || *x == length).unwrap();
+            CfgBlock { start, end, predecessors: vec![], successors: vec![] }
+        }).collect();
+
+        for index in 0..blocks.len() {
+            let mut targets = successors(ir, blocks[index].end - 1);
+            targets.dedup();
+            for target in targets.into_iter().filter(|x| *x < length) {
+                let successor = starts.iter().position(|x| *x == target).unwrap();
+                blocks[index].successors.push(successor);
+                blocks[successor].predecessors.push(index);
+            }
+        }
+
+        let dominators = dominators(&blocks);
+        Cfg { function, blocks, dominators }
+    }
+
+    // the graphs of the top level statements and then of every function
+    pub fn from_ir(ir: &Ir) -> Vec<Cfg> {
+        std::iter::once(None).chain((0..ir.functions.len()).map(Some)).map(|x| Cfg::new(ir, x)).collect()
+    }
+
+    // whether every path from the entry to `block` goes through `dominator`
+    pub fn dominates(&self, dominator: usize, mut block: usize) -> bool {
+        loop {
+            if block == dominator {
+                return true;
+            }
+            if block == self.dominators[block] {
+                return false;
+            }
+            block = self.dominators[block];
+        }
+    }
+}
+
+// blocks in the order a depth first search leaves them, reversed
+fn reverse_postorder(blocks: &[CfgBlock]) -> Vec<usize> {
+    let mut order = vec![];
+    let mut visited = vec![false; blocks.len()];
+    // the block and how many of its successors were looked at
+    let mut stack = vec![(0, 0)];
+    visited[0] = true;
+    while let Some((block, next)) = stack.pop() {
+        match blocks[block].successors.get(next) {
+            Some(&successor) => {
+                stack.push((block, next + 1));
+                if !visited[successor] {
+                    visited[successor] = true;
+                    stack.push((successor, 0));
+                }
+            }
+            None => order.push(block)
+        }
+    }
+    order.reverse();
+    order
+}
+
+// "A Simple, Fast Dominance Algorithm" by Cooper, Harvey and Kennedy, every
+// block is reachable from the entry
+fn dominators(blocks: &[CfgBlock]) -> Vec<usize> {
+    let order = reverse_postorder(blocks);
+    let mut position = vec![0; blocks.len()];
+    for (index, block) in order.iter().enumerate() {
+        position[*block] = index;
+    }
+
+    let mut dominators: Vec<Option<usize>> = vec![None; blocks.len()];
+    dominators[0] = Some(0);
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &block in order.iter().skip(1) {
+            let mut processed = blocks[block].predecessors.iter().copied().filter(|x| dominators[*x].is_some());
+            let first = processed.next().unwrap();
+            let dominator = processed.fold(first, |mut a, mut b| {
+                while a != b {
+                    while position[a] > position[b] {
+                        a = dominators[a].unwrap();
+                    }
+                    while position[b] > position[a] {
+                        b = dominators[b].unwrap();
+                    }
+                }
+                a
+            });
+            if dominators[block] != Some(dominator) {
+                dominators[block] = Some(dominator);
+                changed = true;
+            }
+        }
+    }
+    dominators.into_iter().map(Option::unwrap).collect()
+}
+
+/// Graphviz source of the graphs of a program, one cluster per `Cfg` with a
+/// box for every block. Edges back to a dominator, which close loops, are
+/// bold and the immediate dominators are dashed edges that do not take part
+/// in the layout.
+pub struct CfgDot<'a> {
+    pub ir: &'a Ir,
+    pub cfgs: &'a [Cfg]
+}
+
+impl fmt::Display for CfgDot<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "digraph dang {{")?;
+        writeln!(f, "    node [shape=box fontname=\"monospace\"];")?;
+        for (index, cfg) in self.cfgs.iter().enumerate() {
+            let name = match cfg.function {
+                Some(function) => format!("fn {}", self.ir.functions[function].name),
+                None           => "program".to_string()
+            };
+            writeln!(f, "    subgraph cluster_{} {{", index)?;
+            writeln!(f, "        label=\"{}\";", name)?;
+            for (block_index, block) in cfg.blocks.iter().enumerate() {
+                // `\l` ends a left aligned line
+                write!(f, "        b{}_{} [label=\"", index, block_index)?;
+                for address in block.start..block.end {
+                    write!(f, "{}: {}\\l", address, self.ir.operations[address])?;
+                }
+                writeln!(f, "\"];")?;
+            }
+            for (block_index, block) in cfg.blocks.iter().enumerate() {
+                for &successor in &block.successors {
+                    let style = if cfg.dominates(successor, block_index) { " [style=bold]" } else { "" };
+                    writeln!(f, "        b{}_{} -> b{}_{}{};", index, block_index, index, successor, style)?;
+                }
+                // most dominators are already there as an edge
+                if block_index != 0 && !block.predecessors.contains(&cfg.dominators[block_index]) {
+                    writeln!(f, "        b{}_{} -> b{}_{} [style=dashed color=gray constraint=false];", index, cfg.dominators[block_index], index, block_index)?;
+                }
+            }
+            writeln!(f, "    }}")?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cfg(source: &str) -> Cfg {
+        let ir = Ir::parse(source).unwrap();
+        ir.verify().unwrap();
+        Cfg::new(&ir, None)
+    }
+
+    fn starts(cfg: &Cfg) -> Vec<usize> {
+        cfg.blocks.iter().map(|x| x.start).collect()
+    }
+
+    #[test]
+    fn diamonds_are_dominated_by_their_branch() {
+        let cfg = cfg("enter 8\nload_local -8\njump_if_false 5\npush_int 1\njump 6\npush_int 2\nprint\n");
+        assert_eq!(starts(&cfg), [0, 3, 5, 6]);
+        assert_eq!(cfg.blocks[0].successors, [1, 2]);
+        assert_eq!(cfg.blocks[3].predecessors, [1, 2]);
+        assert_eq!(cfg.dominators, [0, 0, 0, 0]);
+        assert!(cfg.dominates(0, 3));
+        assert!(!cfg.dominates(1, 3));
+        assert!(!cfg.dominates(2, 3));
+    }
+
+    #[test]
+    fn loops_are_dominated_by_their_header() {
+        let cfg = cfg("enter 8\nload_local -8\njump_if_false 8\nload_local -8\npush_int 1\nminus\nstore_local -8\njump 1\nargc\nprint\n");
+        assert_eq!(starts(&cfg), [0, 1, 3, 8]);
+        // the back edge
+        assert_eq!(cfg.blocks[2].successors, [1]);
+        assert_eq!(cfg.blocks[1].predecessors, [0, 2]);
+        assert_eq!(cfg.dominators, [0, 0, 1, 1]);
+        assert!(cfg.dominates(1, 2));
+        assert!(!cfg.dominates(2, 1));
+        assert!(!cfg.dominates(2, 3));
+    }
+
+    #[test]
+    fn unreachable_code_is_left_out() {
+        let cfg = cfg("enter 0\njump 3\nprint\nargc\nprint\n");
+        assert_eq!(starts(&cfg), [0, 3]);
+        assert_eq!(cfg.dominators, [0, 0]);
+    }
+}
@@ -0,0 +1,52 @@
+use crate::lexer::*;
+
+use std::iter::Peekable;
+
+// lexes from any `char` iterator, building a `String` for each word, which
+// `StrInput` does not need to do. only the tests and the lexer benchmark
+// use it, to check the two agree and to compare their speed
+pub struct CharsInput<Chars: Iterator<Item=char>> {
+    pub chars: Peekable<Chars>,
+    pub offset: usize
+}
+
+impl<Chars: Iterator<Item=char>> LexerInput for CharsInput<Chars> {
+    fn next_char_if(&mut self, func: impl FnOnce(&char) -> bool) -> Option<char> {
+        let x = self.chars.next_if(func)?;
+        self.offset += x.len_utf8();
+        Some(x)
+    }
+
+    fn offset(&self) -> usize {
+        self.offset
+    }
+
+    fn next_word<R>(&mut self, _start: usize, first: char, func: impl FnOnce(&str) -> R) -> R {
+        let mut text = first.to_string();
+        while let Some(x) = self.next_char_if(|x| x.is_alphanumeric() || *x == '_') {
+            text.push(x);
+        }
+        func(&text)
+    }
+}
+
+impl<Chars: Iterator<Item=char>> Lexer<CharsInput<Chars>> {
+    pub fn from_chars(chars: Chars) -> Self {
+        Self { input: CharsInput { chars: chars.peekable(), offset: 0 } }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // both inputs have to give the same tokens, spans included
+    #[test]
+    fn chars_and_str_inputs_agree() {
+        let source = "print!(foo ** 2, bar2 >= 35 % baz) é->x";
+        let from_chars: Vec<(LexerTokenKind, LexerTokenValue, usize, usize)> = Lexer::from_chars(source.chars()).map(|x| (x.kind, x.value, x.span.start, x.span.end)).collect();
+        let from_source: Vec<(LexerTokenKind, LexerTokenValue, usize, usize)> = Lexer::from_source(source).map(|x| (x.kind, x.value, x.span.start, x.span.end)).collect();
+        assert_eq!(from_chars.len(), 16);
+        assert_eq!(from_chars, from_source);
+    }
+}
@@ -0,0 +1,204 @@
+use crate::ast::*;
+use crate::cfg::*;
+use crate::diagnostic::*;
+use crate::ir::*;
+use crate::symbol::*;
+
+// -=-=-=-= begin warnings =-=-=-=-
+
+/// Warns about statements that can never run because one before them in the
+/// same block always leaves, with `return`, `exit!` or an `if` whose
+/// branches all do. It runs on a type checked `DangAst`.
+pub struct ReachabilityChecker {
+    pub diagnostics: Vec<Diagnostic>
+}
+
+// whether running `statement` never gets to the one after it
+fn leaves(statement: &DangStatement) -> bool {
+    use DangStatementKind::*;
+    match &statement.kind {
+        Return(_)                   => true,
+        FunctionCall(function_call) => function_call.is_built_in && function_call.name == Symbol::EXIT,
+        Block(block)                => block_leaves(block),
+        If(if_statement)            => {
+            block_leaves(&if_statement.then_block)
+                && if_statement.else_block.as_ref().is_some_and(block_leaves)
+        }
+        _ => false
+    }
+}
+
+fn block_leaves(block: &DangBlock) -> bool {
+    block.symbols.iter().any(leaves)
+}
+
+impl ReachabilityChecker {
+    pub fn new() -> ReachabilityChecker {
+        ReachabilityChecker { diagnostics: vec![] }
+    }
+
+    // the diagnostics are all `Lint::UnreachableCode` warnings
+    pub fn check_ast(ast: &DangAst) -> Vec<Diagnostic> {
+        let mut checker = ReachabilityChecker::new();
+
+        for function in &ast.functions {
+            checker.check_statements(&function.body.symbols);
+        }
+        checker.check_statements(&ast.ast);
+
+        checker.diagnostics
+    }
+
+    // only the first unreachable statement of a block is reported, the
+    // others are unreachable for the same reason
+    fn check_statements(&mut self, statements: &[DangStatement]) {
+        for (index, statement) in statements.iter().enumerate() {
+            self.check_statement(statement);
+            if leaves(statement) {
+                if let Some(next) = statements.get(index + 1) {
+                    self.diagnostics.push(Diagnostic::lint(Lint::UnreachableCode, next.span, "unreachable statement".to_string()));
+                }
+                break;
+            }
+        }
+    }
+
+    fn check_statement(&mut self, statement: &DangStatement) {
+        match &statement.kind {
+            DangStatementKind::Block(block) => self.check_statements(&block.symbols),
+            DangStatementKind::If(if_statement) => {
+                self.check_statements(&if_statement.then_block.symbols);
+                if let Some(else_block) = &if_statement.else_block {
+                    self.check_statements(&else_block.symbols);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// -=-=-=-= end warnings =-=-=-=-
+
+// -=-=-=-= begin IR =-=-=-=-
+
+impl Ir {
+    // removes the instructions no path reaches and the ones that only
+    // compute something that is dropped right away
+    pub fn eliminate_dead_code(&mut self) {
+        let mut removed = vec![true; self.operations.len()];
+        for cfg in Cfg::from_ir(self) {
+            for block in &cfg.blocks {
+                removed[block.start..block.end].fill(false);
+            }
+        }
+        self.remove_operations(&removed);
+
+        while self.eliminate_dropped() {}
+
+        if cfg!(debug_assertions) {
+            if let Err(error) = self.verify() {
+                panic!("unreachable: dead code elimination generated invalid IR: {}\n{}", error, self);
+            }
+        }
+    }
+
+    // a removable instruction followed by a `Drop` becomes a `Drop` for each
+    // value it pops, which can make the ones before it dropped as well
+    fn eliminate_dropped(&mut self) -> bool {
+        let length = self.operations.len();
+        let mut targets = vec![false; length + 1];
+        for operation in self.operations.iter().filter(|x| x.instruction_type.is_jump()) {
+            targets[operation.operand as usize] = true;
+        }
+        for function in &self.functions {
+            targets[function.address] = true;
+        }
+
+        let mut removed = vec![false; length];
+        let mut changed = false;
+        let mut address = 0;
+        while address + 1 < length {
+            let operation = &self.operations[address];
+            let dropped = self.operations[address + 1].instruction_type == IrInstructionType::Drop && !targets[address + 1];
+            let (pops, pushes) = operation.stack_effect(&self.functions);
+            if !dropped || !operation.instruction_type.is_removable() || pushes != 1 {
+                address += 1;
+                continue;
+            }
+
+            let drop = IrInstruction { instruction_type: IrInstructionType::Drop, operand: 0 };
+            match pops {
+                0 => {
+                    removed[address] = true;
+                    removed[address + 1] = true;
+                }
+                1 => {
+                    self.operations[address] = drop;
+                    removed[address + 1] = true;
+                }
+                _ => self.operations[address] = drop
+            }
+            changed = true;
+            address += 2;
+        }
+
+        self.remove_operations(&removed);
+        changed
+    }
+
+    // jumps to a removed instruction go to whatever comes after it
+    fn remove_operations(&mut self, removed: &[bool]) {
+        let length = self.operations.len();
+        let mut new_addresses = vec![0; length + 1];
+        let mut operations = vec![];
+        for (address, operation) in self.operations.iter().enumerate() {
+            new_addresses[address] = operations.len();
+            if !removed[address] {
+                operations.push(operation.clone());
+            }
+        }
+        new_addresses[length] = operations.len();
+
+        for operation in operations.iter_mut().filter(|x| x.instruction_type.is_jump()) {
+            operation.operand = new_addresses[operation.operand as usize] as i64;
+        }
+        for function in &mut self.functions {
+            function.address = new_addresses[function.address];
+        }
+        self.at_in_instructions = operations.len() as i64;
+        self.operations = operations;
+    }
+}
+
+// -=-=-=-= end IR =-=-=-=-
+
+#[cfg(test)]
+mod tests {
+    use crate::compile;
+    use crate::ir::*;
+    use crate::sim::tests::*;
+
+    fn count(ir: &Ir, instruction_type: IrInstructionType) -> usize {
+        ir.operations.iter().filter(|x| x.instruction_type == instruction_type).count()
+    }
+
+    #[test]
+    fn code_after_exit_is_removed() {
+        let mut ir = compile("print!(4); exit!(0); print!(5); print!(6)");
+        assert_eq!(count(&ir, IrInstructionType::PrintSigned), 3);
+        ir.eliminate_dead_code();
+        assert_eq!(count(&ir, IrInstructionType::PrintSigned), 1);
+        assert_eq!(ir.operations.last().unwrap().instruction_type, IrInstructionType::Exit);
+        assert_eq!(simulate(&ir), "4\n");
+    }
+
+    #[test]
+    fn code_after_return_is_removed() {
+        let source = "fn f(a: i64) -> i64 { if a > 0 { return 1 } else { return 2 } print!(99); return 3 } print!(f(1));";
+        let mut ir = compile(source);
+        let before = simulate(&ir);
+        ir.eliminate_dead_code();
+        assert_eq!(count(&ir, IrInstructionType::PrintSigned), 1);
+        assert_eq!(simulate(&ir), before);
+    }
+}
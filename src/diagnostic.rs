@@ -0,0 +1,119 @@
+use crate::lexer::*;
+
+use std::collections::HashMap;
+use std::fmt::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticLevel {
+    Error,
+    Warning
+}
+
+/// Something that is only reported when it is asked for, `-A`, `-W` and
+/// `-D` followed by its name allow it, warn about it or make it an error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lint {
+    // statements after one that always leaves, like `return` or `exit!`
+    UnreachableCode
+}
+
+pub const LINT_NAMES: [(Lint, &str); 1] = [
+    (Lint::UnreachableCode, "unreachable-code")
+];
+
+impl Lint {
+    pub fn name(self) -> &'static str {
+        LINT_NAMES.iter().find(|x| x.0 == self).unwrap().1
+    }
+
+    pub fn from_name(name: &str) -> Option<Lint> {
+        LINT_NAMES.iter().find(|x| x.1 == name).map(|x| x.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LintLevel {
+    Allow,
+    Warn,
+    Deny
+}
+
+/// What every `Lint` is set to, they all warn unless told otherwise.
+#[derive(Debug, Clone, Default)]
+pub struct LintLevels {
+    levels: HashMap<Lint, LintLevel>
+}
+
+impl LintLevels {
+    pub fn set(&mut self, lint: Lint, level: LintLevel) {
+        self.levels.insert(lint, level);
+    }
+
+    pub fn level(&self, lint: Lint) -> LintLevel {
+        self.levels.get(&lint).copied().unwrap_or(LintLevel::Warn)
+    }
+
+    // drops the diagnostics of allowed lints and turns the ones of denied
+    // lints into errors
+    pub fn apply(&self, diagnostics: Vec<Diagnostic>) -> Vec<Diagnostic> {
+        diagnostics.into_iter().filter_map(|mut diagnostic| {
+            match diagnostic.lint.map(|x| self.level(x)) {
+                Some(LintLevel::Allow) => return None,
+                Some(LintLevel::Deny)  => diagnostic.level = DiagnosticLevel::Error,
+                _                      => {}
+            }
+            Some(diagnostic)
+        }).collect()
+    }
+}
+
+/// Something wrong with the program being compiled, pointing at the part of
+/// the source that caused it.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub span: LexerSpan,
+    pub message: String,
+    pub level: DiagnosticLevel,
+    // the lint that reported it, errors of the language have none
+    pub lint: Option<Lint>
+}
+
+impl Diagnostic {
+    pub fn new(span: LexerSpan, message: String) -> Diagnostic {
+        Diagnostic { span, message, level: DiagnosticLevel::Error, lint: None }
+    }
+
+    pub fn lint(lint: Lint, span: LexerSpan, message: String) -> Diagnostic {
+        Diagnostic { span, message, level: DiagnosticLevel::Warning, lint: Some(lint) }
+    }
+
+    pub fn display<'a>(&'a self, path: &'a str, source: &'a str) -> DiagnosticDisplay<'a> {
+        DiagnosticDisplay { diagnostic: self, path, source }
+    }
+}
+
+/// Renders a `Diagnostic` as `path:line:column: error: message`, followed by
+/// the flag that reports it for lints.
+pub struct DiagnosticDisplay<'a> {
+    diagnostic: &'a Diagnostic,
+    path: &'a str,
+    source: &'a str
+}
+
+impl Display for DiagnosticDisplay<'_> {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        let before = &self.source[..self.diagnostic.span.start.min(self.source.len())];
+        let line = before.matches('\n').count() + 1;
+        let column = before.chars().rev().take_while(|x| *x != '\n').count() + 1;
+        let level = match self.diagnostic.level {
+            DiagnosticLevel::Error   => "error",
+            DiagnosticLevel::Warning => "warning"
+        };
+        write!(f, "{}:{}:{}: {}: {}", self.path, line, column, level, self.diagnostic.message)?;
+        if let Some(lint) = self.diagnostic.lint {
+            let flag = if self.diagnostic.level == DiagnosticLevel::Error { 'D' } else { 'W' };
+            write!(f, " [-{}{}]", flag, lint.name())?;
+        }
+        Ok(())
+    }
+}
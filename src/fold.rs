@@ -0,0 +1,314 @@
+use crate::ast::*;
+use crate::diagnostic::*;
+use crate::lexer::*;
+use crate::types::*;
+
+/// Replaces operations, negations and casts whose operands are all constants
+/// with their result, computed the way the generated code would compute it.
+/// It runs on a type checked `DangAst`. Operations that would overflow their
+/// type or divide by zero are reported instead of being folded.
+pub struct ConstantFolder {
+    pub diagnostics: Vec<Diagnostic>
+}
+
+// the value of a constant in its own type, `None` for anything else
+fn constant(statement: &DangStatement) -> Option<i128> {
+    use DangStatementKind::*;
+    match &statement.kind {
+        Number(number) if statement.ty.as_integer().is_some() => Some(*number as i128),
+        Bool(value) => Some(*value as i128),
+        // negative constants stay negated literals, like in the source
+        UnaryOperation(unary_operation) if unary_operation.unary_operation_type == DangUnaryOperationType::Negate => {
+            match unary_operation.operand.kind {
+                Number(number) => Some(-(number as i128)),
+                _              => None
+            }
+        }
+        _ => None
+    }
+}
+
+fn constant_statement(value: i128, ty: DangType, span: LexerSpan) -> DangStatement {
+    let kind = match ty {
+        DangType::Bool => DangStatementKind::Bool(value != 0),
+        _ if value < 0 => DangStatementKind::UnaryOperation(DangUnaryOperation {
+            unary_operation_type: DangUnaryOperationType::Negate,
+            operand: Box::new(DangStatement { kind: DangStatementKind::Number(value.unsigned_abs() as u64), span, ty })
+        }),
+        _ => DangStatementKind::Number(value as u64)
+    };
+    DangStatement { kind, span, ty }
+}
+
+// what an integer becomes when it is cut down to the bits of `integer_type`
+fn wrap(value: i128, integer_type: DangIntegerType) -> i128 {
+    let bits = integer_type.bits();
+    let truncated = value & ((1 << bits) - 1);
+    if integer_type.is_signed() && truncated > integer_type.max() {
+        truncated - (1 << bits)
+    } else {
+        truncated
+    }
+}
+
+impl ConstantFolder {
+    pub fn new() -> ConstantFolder {
+        ConstantFolder { diagnostics: vec![] }
+    }
+
+    pub fn fold_ast(ast: &mut DangAst) -> Result<(), Vec<Diagnostic>> {
+        let mut folder = ConstantFolder::new();
+
+        for function in &mut ast.functions {
+            folder.fold_block(&mut function.body);
+        }
+        for node in &mut ast.ast {
+            folder.fold_statement(node);
+        }
+
+        if folder.diagnostics.is_empty() {
+            Ok(())
+        } else {
+            Err(folder.diagnostics)
+        }
+    }
+
+    fn fold_block(&mut self, block: &mut DangBlock) {
+        for symbol in &mut block.symbols {
+            self.fold_statement(symbol);
+        }
+    }
+
+    // operands are folded first, so whole trees of constants end up as one
+    fn fold_statement(&mut self, statement: &mut DangStatement) {
+        use DangStatementKind::*;
+        let folded = match &mut statement.kind {
+            Name(_) | Number(_) | Bool(_) => None,
+            Let(let_statement) => {
+                self.fold_statement(&mut let_statement.value);
+                None
+            }
+            Assignment(assignment) => {
+                self.fold_statement(&mut assignment.target);
+                self.fold_statement(&mut assignment.value);
+                None
+            }
+            Return(return_statement) => {
+                if let Some(value) = &mut return_statement.value {
+                    self.fold_statement(value);
+                }
+                None
+            }
+            // parentheses around a constant are not needed anymore
+            Expression(expression) => {
+                self.fold_statement(&mut expression.symbols[0]);
+                constant(&expression.symbols[0]).map(|value| constant_statement(value, expression.symbols[0].ty, statement.span))
+            }
+            FunctionCall(function_call) => {
+                for param in &mut function_call.parameters {
+                    self.fold_statement(param);
+                }
+                None
+            }
+            Block(block) => {
+                self.fold_block(block);
+                None
+            }
+            If(if_statement) => {
+                self.fold_statement(&mut if_statement.condition);
+                self.fold_block(&mut if_statement.then_block);
+                if let Some(else_block) = &mut if_statement.else_block {
+                    self.fold_block(else_block);
+                }
+                None
+            }
+            Operation(operation) => {
+                self.fold_statement(&mut operation.first_operand);
+                self.fold_statement(&mut operation.second_operand);
+                self.fold_operation(statement.span, operation, statement.ty)
+                    .map(|value| constant_statement(value, statement.ty, statement.span))
+            }
+            UnaryOperation(unary_operation) => {
+                self.fold_statement(&mut unary_operation.operand);
+                self.fold_unary_operation(statement.span, unary_operation, statement.ty)
+                    .map(|value| constant_statement(value, statement.ty, statement.span))
+            }
+            Cast(cast) => {
+                self.fold_statement(&mut cast.operand);
+                let value = constant(&cast.operand);
+                match cast.target {
+                    DangType::Integer(integer_type) => value.map(|value| wrap(value, integer_type)),
+                    DangType::Bool                  => value,
+                    _                               => None
+                }.map(|value| constant_statement(value, cast.target, statement.span))
+            }
+            Array(array) => {
+                for element in &mut array.elements {
+                    self.fold_statement(element);
+                }
+                None
+            }
+            Index(index) => {
+                self.fold_statement(&mut index.base);
+                self.fold_statement(&mut index.index);
+                None
+            }
+            StructLiteral(struct_literal) => {
+                for field in &mut struct_literal.fields {
+                    self.fold_statement(&mut field.value);
+                }
+                None
+            }
+            FieldAccess(field_access) => {
+                self.fold_statement(&mut field_access.base);
+                None
+            }
+            AddressOf(address_of) => {
+                self.fold_statement(&mut address_of.operand);
+                None
+            }
+            Dereference(dereference) => {
+                self.fold_statement(&mut dereference.operand);
+                None
+            }
+        };
+
+        if let Some(folded) = folded {
+            *statement = folded;
+        }
+    }
+
+    fn fold_unary_operation(&mut self, span: LexerSpan, unary_operation: &DangUnaryOperation, ty: DangType) -> Option<i128> {
+        // a negated literal already is a constant
+        if matches!(unary_operation.operand.kind, DangStatementKind::Number(_)) {
+            return None;
+        }
+
+        let value = constant(&unary_operation.operand)?;
+        match unary_operation.unary_operation_type {
+            DangUnaryOperationType::Not    => Some(value ^ 1),
+            DangUnaryOperationType::Negate => self.check_overflow(span, -value, ty, format!("-({})", value))
+        }
+    }
+
+    fn fold_operation(&mut self, span: LexerSpan, operation: &DangOperation, ty: DangType) -> Option<i128> {
+        use DangBinaryOperationType::*;
+        let a = constant(&operation.first_operand)?;
+        let b = constant(&operation.second_operand)?;
+        let binary_operation_type = operation.binary_operation_type;
+        let description = format!("{} {} {}", a, binary_operation_type.as_string(), b);
+
+        let result = match binary_operation_type {
+            Equal        => (a == b) as i128,
+            NotEqual     => (a != b) as i128,
+            Less         => (a < b) as i128,
+            LessEqual    => (a <= b) as i128,
+            Greater      => (a > b) as i128,
+            GreaterEqual => (a >= b) as i128,
+            And          => a & b,
+            Or           => a | b,
+            Plus         => a + b,
+            Minus        => a - b,
+            // anything past `i128` overflows every type anyway
+            Multiplication => a.checked_mul(b).unwrap_or(i128::MAX),
+            Division | Mod if b == 0 => {
+                self.diagnostics.push(Diagnostic::new(span, format!("`{}` divides by zero", description)));
+                return None;
+            }
+            // `idiv` faults on `MIN / -1` even when only the remainder is
+            // wanted
+            Division | Mod if ty.as_integer().is_some_and(|x| x.is_signed() && a == x.min()) && b == -1 => {
+                self.diagnostics.push(Diagnostic::new(span, format!("`{}` overflows `{}`", description, ty)));
+                return None;
+            }
+            // both truncate towards zero like `idiv`
+            Division => a / b,
+            Mod      => a % b,
+            Power    => {
+                if b < 0 {
+                    self.diagnostics.push(Diagnostic::new(span, format!("`{}` has a negative exponent", description)));
+                    return None;
+                }
+                u32::try_from(b).ok().and_then(|b| a.checked_pow(b)).unwrap_or(i128::MAX)
+            }
+        };
+
+        if ty == DangType::Bool {
+            Some(result)
+        } else {
+            self.check_overflow(span, result, ty, description)
+        }
+    }
+
+    fn check_overflow(&mut self, span: LexerSpan, value: i128, ty: DangType, description: String) -> Option<i128> {
+        let integer_type = ty.as_integer()?;
+        if value < integer_type.min() || value > integer_type.max() {
+            self.diagnostics.push(Diagnostic::new(span, format!("`{}` overflows `{}`", description, ty)));
+            return None;
+        }
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile;
+    use crate::ir::*;
+    use crate::parser::*;
+    use crate::sim::tests::*;
+    use crate::typecheck::*;
+
+    // the messages of what folding `source` reports, with where they start
+    fn fold_errors(source: &str) -> Vec<String> {
+        let mut ast = Parser::new(Lexer::from_source(source)).parse_ast().unwrap();
+        TypeChecker::check_ast(&mut ast).unwrap();
+        match ConstantFolder::fold_ast(&mut ast) {
+            Ok(())           => vec![],
+            Err(diagnostics) => diagnostics.iter().map(|x| format!("{}: {}", x.span.start, x.message)).collect()
+        }
+    }
+
+    #[test]
+    fn overflows_and_divisions_by_zero_are_reported() {
+        assert_eq!(fold_errors("print!(1 / 0)"), ["7: `1 / 0` divides by zero"]);
+        assert_eq!(fold_errors("print!(200 as u8 + 100 as u8)"), ["7: `200 + 100` overflows `u8`"]);
+        assert_eq!(fold_errors("print!(2 ** 64)"), ["7: `2 ** 64` overflows `i64`"]);
+        assert_eq!(fold_errors("let x = 1; print!(x / 0); print!(1 + 2)"), Vec::<String>::new());
+    }
+
+    // the operation is folded when both operands are literals and computed
+    // at runtime when they are in variables, which has to give the same
+    #[test]
+    fn folding_computes_what_the_program_would() {
+        let cases = [
+            ("i64", "7", "+", "-12"), ("i64", "7", "-", "12"), ("i64", "-7", "*", "12"),
+            ("i64", "-7", "/", "2"), ("i64", "-7", "%", "2"), ("u64", "7", "/", "2"),
+            ("i64", "3", "**", "4"), ("i64", "-2", "**", "63"), ("i64", "5", "**", "0"),
+            ("u8", "3", "**", "5"), ("i8", "-2", "**", "7"), ("u64", "2", "**", "63"),
+            ("i8", "-3", "<", "2"), ("u8", "3", ">=", "200"), ("i32", "4", "==", "4")
+        ];
+        for (ty, a, operation, b) in cases {
+            let folded = compile(&format!("print!(({} as {}) {} ({} as {}));", a, ty, operation, b, ty));
+            let computed = compile(&format!("let a: {} = {}; let b: {} = {}; print!(a {} b);", ty, a, ty, b, operation));
+
+            let pushes = folded.operations.iter().filter(|x| x.instruction_type == IrInstructionType::PushInt).count();
+            assert_eq!(pushes, 1, "`{} {} {}` is not folded:\n{}", a, operation, b, folded);
+            assert_eq!(simulate(&folded), simulate(&computed), "{} {} {} as {}", a, operation, b, ty);
+        }
+
+        // what the folder rejects has to stop the program too
+        let faults = [
+            ("i64", "2", "**", "-1", "error: negative exponent"),
+            ("i64", "7", "/", "0", "error: division by zero"), ("u8", "7", "%", "0", "error: division by zero"),
+            ("i64", "-9223372036854775807 - 1", "/", "-1", "error: division overflow")
+        ];
+        for (ty, a, operation, b, message) in faults {
+            let folded = format!("print!(({} as {}) {} ({} as {}));", a, ty, operation, b, ty);
+            let computed = compile(&format!("let a: {} = {}; let b: {} = {}; print!(a {} b);", ty, a, ty, b, operation));
+
+            assert_eq!(fold_errors(&folded).len(), 1, "`{} {} {}` is not reported", a, operation, b);
+            assert_eq!(simulate_failure(&computed), (1, Some(message.to_string())), "{} {} {} as {}", a, operation, b, ty);
+        }
+    }
+}
@@ -0,0 +1,333 @@
+use crate::cfg::*;
+use crate::ir::*;
+
+use std::collections::HashSet;
+
+// instructions in the body of a function, besides its `enter`, up to which it
+// is inlined without `#[inline]`, about what calling it costs
+pub const INLINE_THRESHOLD: usize = 16;
+
+// a function whose body can take the place of a call to it
+#[derive(Debug, Clone, Copy)]
+struct Inlinee {
+    // where the body is, after the `enter`
+    start: usize,
+    end: usize,
+    frame_size: i64,
+    parameters: usize
+}
+
+impl Inlinee {
+    // where the body keeps a local of the function it came from, in a frame
+    // whose first `base` bytes of locals belong to the caller, the arguments
+    // go after its own locals
+    fn local(&self, base: i64, offset: i64) -> i64 {
+        if offset < 0 {
+            offset - base
+        } else {
+            let parameter = self.parameters as i64 - 1 - (offset - 16) / 8;
+            self.argument(base, parameter as usize)
+        }
+    }
+
+    fn argument(&self, base: i64, parameter: usize) -> i64 {
+        -(base + self.frame_size + 8 * (parameter as i64 + 1))
+    }
+}
+
+impl Ir {
+    // replaces calls to small functions, and to the ones marked
+    // `#[inline]`, with their bodies until there are none left, recursive
+    // functions and the ones marked `#[noinline]` are always called
+    pub fn inline_functions(&mut self) {
+        while self.inline_pass() {}
+
+        if cfg!(debug_assertions) {
+            if let Err(error) = self.verify() {
+                panic!("unreachable: the inliner generated invalid IR: {}\n{}", error, self);
+            }
+        }
+    }
+
+    // the address of the `enter` that sets up the frame of the code starting
+    // at `entry`
+    fn frame_address(&self, mut entry: usize) -> Option<usize> {
+        let mut jumped = HashSet::new();
+        loop {
+            let operation = self.operations.get(entry)?;
+            match operation.instruction_type {
+                IrInstructionType::Enter => return Some(entry),
+                IrInstructionType::Jump if jumped.insert(entry) => entry = operation.operand as usize,
+                _ => return None
+            }
+        }
+    }
+
+    // the body of `function` and whether it is small enough, `None` when it
+    // cannot be moved somewhere else, like when it does not look like what
+    // the compiler generates for a function
+    fn inlinee(&self, cfg: &Cfg, depths: &[Option<usize>]) -> Option<(Inlinee, bool)> {
+        let function = &self.functions[cfg.function?];
+        let enter = &self.operations[function.address];
+        if enter.instruction_type != IrInstructionType::Enter {
+            return None;
+        }
+
+        // the body has to be the instructions right after the `enter`, with
+        // nothing in between that cannot run
+        let start = function.address + 1;
+        let end = cfg.blocks.iter().map(|x| x.end).max().unwrap();
+        if cfg.blocks.iter().map(|x| x.end - x.start).sum::<usize>() != end - function.address {
+            return None;
+        }
+
+        let inlinee = Inlinee { start, end, frame_size: enter.operand, parameters: function.parameters };
+        let arguments = 16..16 + 8 * function.parameters as i64;
+        for (address, operation) in self.operations.iter().enumerate().take(end).skip(start) {
+            let valid = match operation.instruction_type {
+                // every `return` becomes a jump past the body, so they have to
+                // leave the stack the same way
+                IrInstructionType::Return => depths[address] == Some(1),
+                IrInstructionType::LoadLocal | IrInstructionType::StoreLocal | IrInstructionType::LocalAddress => {
+                    let offset = operation.operand;
+                    (-inlinee.frame_size..0).contains(&offset) || (arguments.contains(&offset) && offset % 8 == 0)
+                }
+                _ => true
+            };
+            if !valid || !successors(self, address).iter().all(|x| (start..end).contains(x)) {
+                return None;
+            }
+        }
+
+        Some((inlinee, end - start <= INLINE_THRESHOLD))
+    }
+
+    fn inline_pass(&mut self) -> bool {
+        let Ok(depths) = self.verify() else {
+            return false;
+        };
+        let length = self.operations.len();
+        let cfgs = Cfg::from_ir(self);
+
+        // which graph every instruction belongs to, `None` for the ones
+        // that are in more than one
+        let mut owners = vec![None; length];
+        let mut shared = vec![false; length];
+        for (index, cfg) in cfgs.iter().enumerate() {
+            for block in &cfg.blocks {
+                for address in block.start..block.end {
+                    shared[address] |= owners[address].is_some();
+                    owners[address] = Some(index);
+                }
+            }
+        }
+
+        // functions each function calls, to find the recursive ones
+        let calls: Vec<Vec<usize>> = cfgs.iter().map(|cfg| {
+            cfg.blocks.iter()
+                .flat_map(|block| block.start..block.end)
+                .filter(|x| self.operations[*x].instruction_type == IrInstructionType::Call)
+                .map(|x| self.operations[x].operand as usize)
+                .collect()
+        }).collect();
+        let recursive = |function: usize| {
+            let mut visited = HashSet::new();
+            let mut work = calls[function + 1].clone();
+            while let Some(called) = work.pop() {
+                if called == function {
+                    return true;
+                }
+                if visited.insert(called) {
+                    work.extend(&calls[called + 1]);
+                }
+            }
+            false
+        };
+
+        let inlinees: Vec<Option<Inlinee>> = cfgs.iter().skip(1).enumerate().map(|(function, cfg)| {
+            let (inlinee, small) = self.inlinee(cfg, &depths)?;
+            let wanted = match self.functions[function].inline {
+                IrInline::WhenSmall => small,
+                IrInline::Always    => true,
+                IrInline::Never     => false
+            };
+            (wanted && !recursive(function)).then_some(inlinee)
+        }).collect();
+        let frames: Vec<Option<usize>> = cfgs.iter().map(|cfg| {
+            self.frame_address(cfg.function.map_or(0, |x| self.functions[x].address))
+        }).collect();
+
+        // the calls that get replaced and by what
+        let sites: Vec<Option<(Inlinee, usize)>> = (0..length).map(|address| {
+            let operation = &self.operations[address];
+            if operation.instruction_type != IrInstructionType::Call || shared[address] {
+                return None;
+            }
+            let owner = owners[address]?;
+            frames[owner]?;
+            inlinees[operation.operand as usize].map(|x| (x, owner))
+        }).collect();
+        if sites.iter().all(Option::is_none) {
+            return false;
+        }
+
+        let mut new_addresses = vec![0; length + 1];
+        let mut new_length = 0;
+        for address in 0..length {
+            new_addresses[address] = new_length;
+            new_length += match sites[address] {
+                Some((inlinee, _)) => inlinee.parameters + inlinee.end - inlinee.start,
+                None               => 1
+            };
+        }
+        new_addresses[length] = new_length;
+
+        let mut growths = vec![0; cfgs.len()];
+        let mut operations = Vec::with_capacity(new_length);
+        for (address, operation) in self.operations.iter().enumerate() {
+            let Some((inlinee, owner)) = sites[address] else {
+                let mut operation = operation.clone();
+                if operation.instruction_type.is_jump() {
+                    operation.operand = new_addresses[operation.operand as usize] as i64;
+                }
+                operations.push(operation);
+                continue;
+            };
+
+            let base = self.operations[frames[owner].unwrap()].operand + growths[owner];
+            growths[owner] += inlinee.frame_size + 8 * inlinee.parameters as i64;
+
+            // the arguments are on the stack with the last one on top
+            for parameter in (0..inlinee.parameters).rev() {
+                operations.push(IrInstruction { instruction_type: IrInstructionType::StoreLocal, operand: inlinee.argument(base, parameter) });
+            }
+            let body = new_addresses[address] + inlinee.parameters;
+            for inlined in &self.operations[inlinee.start..inlinee.end] {
+                let mut inlined = inlined.clone();
+                match inlined.instruction_type {
+                    IrInstructionType::Return => {
+                        inlined = IrInstruction { instruction_type: IrInstructionType::Jump, operand: new_addresses[address + 1] as i64 };
+                    }
+                    IrInstructionType::Jump | IrInstructionType::JumpIfFalse => {
+                        inlined.operand = (body + inlined.operand as usize - inlinee.start) as i64;
+                    }
+                    IrInstructionType::LoadLocal | IrInstructionType::StoreLocal | IrInstructionType::LocalAddress => {
+                        inlined.operand = inlinee.local(base, inlined.operand);
+                    }
+                    _ => {}
+                }
+                operations.push(inlined);
+            }
+        }
+
+        for (frame, growth) in frames.iter().zip(growths) {
+            if let Some(frame) = frame {
+                operations[new_addresses[*frame]].operand += growth;
+            }
+        }
+        for function in &mut self.functions {
+            function.address = new_addresses[function.address];
+        }
+        self.at_in_instructions = operations.len() as i64;
+        self.operations = operations;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile;
+    use crate::sim::tests::*;
+    use crate::symbol::*;
+
+    // `source` with its functions inlined, which has to print the same
+    fn inline(source: &str) -> Ir {
+        let mut ir = compile(source);
+        let expected = simulate(&ir);
+        ir.inline_functions();
+        assert_eq!(simulate(&ir), expected, "{}", ir);
+        ir
+    }
+
+    // how many calls to the function named `name` are left
+    fn calls(ir: &Ir, name: &str) -> usize {
+        let function = ir.functions.iter().position(|x| x.name == Symbol::intern(name)).unwrap();
+        ir.operations.iter().filter(|x| x.instruction_type == IrInstructionType::Call && x.operand == function as i64).count()
+    }
+
+    // the frame the top level statements set up
+    fn top_level_frame(ir: &Ir) -> i64 {
+        ir.operations[ir.frame_address(0).unwrap()].operand
+    }
+
+    #[test]
+    fn small_functions_are_inlined() {
+        let ir = inline("fn sq(a: i64) -> i64 { return a * a } print!(sq(3) + sq(-4));");
+        assert_eq!(calls(&ir, "sq"), 0);
+    }
+
+    #[test]
+    fn recursive_functions_are_called() {
+        let source = "
+            #[inline]
+            fn fact(n: i64) -> i64 { if n < 2 { return 1 } return n * fact(n - 1) }
+            fn even(n: i64) -> bool { if n == 0 { return true } return odd(n - 1) }
+            fn odd(n: i64) -> bool { if n == 0 { return false } return even(n - 1) }
+            print!(fact(5));
+            print!(even(7));
+        ";
+        let ir = inline(source);
+        assert_eq!(calls(&ir, "fact"), 2);
+        assert!(calls(&ir, "even") > 0);
+        assert!(calls(&ir, "odd") > 0);
+    }
+
+    #[test]
+    fn noinline_functions_are_called() {
+        let ir = inline("#[noinline] fn sq(a: i64) -> i64 { return a * a } print!(sq(3));");
+        assert_eq!(calls(&ir, "sq"), 1);
+    }
+
+    #[test]
+    fn inline_functions_are_inlined_whatever_their_size() {
+        let body = "let b = a * 3; let c = b - a; let d = c * c; let e = d + b; let f = e * a; return f - d + c";
+        let big = compile(&format!("fn big(a: i64) -> i64 {{ {} }} print!(big(1));", body));
+        let function = big.functions[0].address;
+        let end = (function..big.operations.len()).rfind(|x| big.operations[*x].instruction_type == IrInstructionType::Return).unwrap();
+        assert!(end - function > INLINE_THRESHOLD);
+
+        let ir = inline(&format!("fn big(a: i64) -> i64 {{ {} }} print!(big(2));", body));
+        assert_eq!(calls(&ir, "big"), 1);
+        let ir = inline(&format!("#[inline] fn big(a: i64) -> i64 {{ {} }} print!(big(2));", body));
+        assert_eq!(calls(&ir, "big"), 0);
+    }
+
+    #[test]
+    fn early_returns_leave_the_inlined_body() {
+        let source = "
+            #[inline]
+            fn sign(a: i64) -> i64 { if a < 0 { return -1 } if a == 0 { return 0 } return 1 }
+            print!(sign(-5)); print!(sign(0)); print!(sign(5));
+        ";
+        let ir = inline(source);
+        assert_eq!(calls(&ir, "sign"), 0);
+        assert_eq!(simulate(&ir), "-1\n0\n1\n");
+    }
+
+    #[test]
+    fn nested_inlining_grows_the_frame() {
+        let source = "
+            fn twice(a: i64) -> i64 { let b = a * 2; return b }
+            fn both(a: i64) -> i64 { let c = twice(a) + twice(a + 1); return c }
+            let x = 3;
+            print!(both(x));
+        ";
+        let before = top_level_frame(&compile(source));
+        let ir = inline(source);
+        assert_eq!(calls(&ir, "twice"), 0);
+        assert_eq!(calls(&ir, "both"), 0);
+        // `both` with its argument and local, and twice `twice` with theirs
+        assert_eq!(top_level_frame(&ir), before + 16 + 2 * 16);
+    }
+}
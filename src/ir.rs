@@ -1,84 +1,720 @@
-use std::fs::File;
+use crate::cfg::*;
+use crate::diagnostic::*;
+use crate::lexer::*;
+use crate::peephole::*;
+use crate::symbol::*;
+use crate::types::*;
+
+use std::fmt;
 use std::io::*;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum IrInstructionType {
     PushInt,
     Plus,
     Minus,
     Division,
+    UnsignedDivision,
     Multiplication,
     Mod,
-    Print
+    UnsignedMod,
+    // raises the first value popped to the power of the second one, wrapping
+    // around like `Multiplication`. `Power` stops the program when the
+    // exponent is negative, `UnsignedPower` takes it as unsigned
+    Power,
+    UnsignedPower,
+    Negate,
+    Not,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    UnsignedLess,
+    UnsignedLessEqual,
+    UnsignedGreater,
+    UnsignedGreaterEqual,
+    // truncate the value on top of the stack to `operand` bits and extend it
+    // back to 64
+    SignExtend,
+    ZeroExtend,
+    Jump,
+    JumpIfFalse,
+    // sets up the frame of a function with `operand` bytes of locals
+    Enter,
+    // push or pop the 64 bit local at `operand` bytes from the frame pointer
+    LoadLocal,
+    StoreLocal,
+    // calls `Ir::functions[operand]`, the arguments are popped and the
+    // returned value is pushed
+    Call,
+    Return,
+    // exits the process with the status it pops, the same as
+    // `syscall!(60, status)` but nothing comes after it
+    Exit,
+    Drop,
+    // pushes the address `operand` bytes from the frame pointer
+    LocalAddress,
+    // moves the address on top of the stack `operand` bytes forward
+    Offset,
+    // `operand` is the width in bytes, values are zero extended when loaded
+    // and truncated when stored, `Store` pops the value and then the address
+    Load,
+    Store,
+    // pops a source and then a destination address and copies `operand`
+    // bytes one at a time from the first byte forward, so copying an element
+    // onto the next one repeats it
+    Copy,
+    // exits with an error unless the index on top of the stack is below
+    // `operand`, the index stays on the stack
+    BoundsCheck,
+    // pops `operand` arguments and then the syscall number, and pushes what
+    // the kernel returned
+    Syscall,
+    // stdin is read through a buffer of the runtime, so raw `read` syscalls
+    // on it may miss input these already took
+    ReadInt,
+    // pops a capacity and then a buffer address and pushes the length of the
+    // line read into it, or -1 at the end of the input
+    ReadLine,
+    // the process arguments and environment the kernel left on the stack
+    // `_start` got, `Arg` and `Env` pop an index and push a pointer to a nul
+    // terminated string or 0 when there is none
+    Argc,
+    Arg,
+    Env,
+    Print,
+    PrintSigned
+}
+
+// square and multiply, the result only depends on the low 64 bits of each
+// step so signed bases work as well
+pub fn power(mut base: u64, mut exponent: u64) -> u64 {
+    let mut result: u64 = 1;
+    while exponent != 0 {
+        if exponent & 1 == 1 {
+            result = result.wrapping_mul(base);
+        }
+        base = base.wrapping_mul(base);
+        exponent >>= 1;
+    }
+    result
+}
+
+impl IrInstructionType {
+    // the error an instruction that pops `b` and then `a` stops the program
+    // with, the simulator and the generated code report the same
+    pub fn fault(self, a: u64, b: u64) -> Option<&'static str> {
+        use IrInstructionType::*;
+        match self {
+            Division | Mod | UnsignedDivision | UnsignedMod if b == 0   => Some(DIVISION_BY_ZERO_MESSAGE),
+            // `idiv` faults on this instead of wrapping around
+            Division | Mod if a as i64 == i64::MIN && b as i64 == -1     => Some(DIVISION_OVERFLOW_MESSAGE),
+            Power if (b as i64) < 0                                     => Some(NEGATIVE_EXPONENT_MESSAGE),
+            _                                                           => None
+        }
+    }
+
+    // what an instruction that pops `b` and then `a` and pushes one value
+    // computes, `None` for the ones that fault and instructions that are not
+    // like that, the simulator and the optimizer both go through here
+    pub fn evaluate(self, a: u64, b: u64) -> Option<u64> {
+        use IrInstructionType::*;
+        if self.fault(a, b).is_some() {
+            return None;
+        }
+        let result = match self {
+            Plus                 => a.wrapping_add(b),
+            Minus                => a.wrapping_sub(b),
+            Multiplication       => a.wrapping_mul(b),
+            Division             => (a as i64).wrapping_div(b as i64) as u64,
+            Mod                  => (a as i64).wrapping_rem(b as i64) as u64,
+            UnsignedDivision     => a / b,
+            UnsignedMod          => a % b,
+            Power                => power(a, b),
+            UnsignedPower        => power(a, b),
+            Equal                => (a == b) as u64,
+            NotEqual             => (a != b) as u64,
+            Less                 => ((a as i64) < (b as i64)) as u64,
+            LessEqual            => ((a as i64) <= (b as i64)) as u64,
+            Greater              => ((a as i64) > (b as i64)) as u64,
+            GreaterEqual         => ((a as i64) >= (b as i64)) as u64,
+            UnsignedLess         => (a < b) as u64,
+            UnsignedLessEqual    => (a <= b) as u64,
+            UnsignedGreater      => (a > b) as u64,
+            UnsignedGreaterEqual => (a >= b) as u64,
+            _                    => return None
+        };
+        Some(result)
+    }
+
+    pub fn is_jump(self) -> bool {
+        matches!(self, IrInstructionType::Jump | IrInstructionType::JumpIfFalse)
+    }
+
+    // whether it leaves the function or the program, so execution never goes
+    // on to the next instruction
+    pub fn leaves(self) -> bool {
+        matches!(self, IrInstructionType::Return | IrInstructionType::Exit)
+    }
+
+    // whether it does nothing but compute what it pushes from what it pops
+    pub fn is_pure(self) -> bool {
+        use IrInstructionType::*;
+        matches!(self,
+            PushInt | Plus | Minus | Multiplication | UnsignedPower | Negate | Not |
+            Equal | NotEqual | Less | LessEqual | Greater | GreaterEqual |
+            UnsignedLess | UnsignedLessEqual | UnsignedGreater | UnsignedGreaterEqual |
+            SignExtend | ZeroExtend | LocalAddress | Offset)
+    }
+
+    // pure instructions and reads that cannot fault can go when nothing uses
+    // what they push
+    pub fn is_removable(self) -> bool {
+        self.is_pure() || matches!(self, IrInstructionType::LoadLocal | IrInstructionType::Argc)
+    }
 }
 
-#[derive(Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct IrInstruction {
     pub instruction_type: IrInstructionType,
     pub operand: i64
 }
 
 impl IrInstruction {
-    pub fn to_nasm_linux_x86_64_assembly(&self, f: &mut File) -> Result<()> {
+    pub fn to_nasm_linux_x86_64_assembly(&self, f: &mut impl Write, functions: &[IrFunction]) -> Result<()> {
         use IrInstructionType::*;
-        write!(f, ";; -- {:?} --\n", self.instruction_type)?;
+        writeln!(f, ";; -- {:?} --", self.instruction_type)?;
         match self.instruction_type {
             PushInt => {
-                write!(f, "mov rax, {}\n", self.operand)?;
-                write!(f, "push rax\n")?;
+                writeln!(f, "mov rax, {}", self.operand)?;
+                writeln!(f, "push rax")?;
             },
             Plus => {
-                write!(f, "pop rax\n")?;
-                write!(f, "pop rbx\n")?;
-                write!(f, "add rax, rbx\n")?;
-                write!(f, "push rax\n")?;
+                writeln!(f, "pop rax")?;
+                writeln!(f, "pop rbx")?;
+                writeln!(f, "add rax, rbx")?;
+                writeln!(f, "push rax")?;
             },
             Minus => {
-                write!(f, "pop rbx\n")?;
-                write!(f, "pop rax\n")?;
-                write!(f, "sub rax, rbx\n")?;
-                write!(f, "push rax\n")?;
+                writeln!(f, "pop rbx")?;
+                writeln!(f, "pop rax")?;
+                writeln!(f, "sub rax, rbx")?;
+                writeln!(f, "push rax")?;
             },
             Division => {
-                write!(f, "xor rdx, rdx\n")?;
-                write!(f, "pop rbx\n")?;
-                write!(f, "pop rax\n")?;
-                write!(f, "div rbx\n")?;
-                write!(f, "push rax\n")?;
+                writeln!(f, "pop rbx")?;
+                writeln!(f, "pop rax")?;
+                writeln!(f, "call check_signed_divisor")?;
+                writeln!(f, "cqo")?;
+                writeln!(f, "idiv rbx")?;
+                writeln!(f, "push rax")?;
+            },
+            UnsignedDivision => {
+                writeln!(f, "xor rdx, rdx")?;
+                writeln!(f, "pop rbx")?;
+                writeln!(f, "pop rax")?;
+                writeln!(f, "call check_divisor")?;
+                writeln!(f, "div rbx")?;
+                writeln!(f, "push rax")?;
             },
             Multiplication => {
-                write!(f, "pop rax\n")?;
-                write!(f, "pop rbx\n")?;
-                write!(f, "mul rax, rbx\n")?;
-                write!(f, "push rax\n")?;
+                writeln!(f, "pop rax")?;
+                writeln!(f, "pop rbx")?;
+                writeln!(f, "imul rax, rbx")?;
+                writeln!(f, "push rax")?;
             },
             Mod => {
-                write!(f, "xor rdx, rdx\n")?;
-                write!(f, "pop rbx\n")?;
-                write!(f, "pop rax\n")?;
-                write!(f, "div rbx\n")?;
-                write!(f, "push rdx\n")?;
+                writeln!(f, "pop rbx")?;
+                writeln!(f, "pop rax")?;
+                writeln!(f, "call check_signed_divisor")?;
+                writeln!(f, "cqo")?;
+                writeln!(f, "idiv rbx")?;
+                writeln!(f, "push rdx")?;
+            },
+            UnsignedMod => {
+                writeln!(f, "xor rdx, rdx")?;
+                writeln!(f, "pop rbx")?;
+                writeln!(f, "pop rax")?;
+                writeln!(f, "call check_divisor")?;
+                writeln!(f, "div rbx")?;
+                writeln!(f, "push rdx")?;
+            },
+            Power => {
+                writeln!(f, "pop rsi")?;
+                writeln!(f, "pop rdi")?;
+                writeln!(f, "test rsi, rsi")?;
+                writeln!(f, "js negative_exponent")?;
+                writeln!(f, "call power")?;
+                writeln!(f, "push rax")?;
+            },
+            UnsignedPower => {
+                writeln!(f, "pop rsi")?;
+                writeln!(f, "pop rdi")?;
+                writeln!(f, "call power")?;
+                writeln!(f, "push rax")?;
+            },
+            Negate => {
+                writeln!(f, "pop rax")?;
+                writeln!(f, "neg rax")?;
+                writeln!(f, "push rax")?;
+            },
+            Not => {
+                writeln!(f, "pop rax")?;
+                writeln!(f, "xor rax, 1")?;
+                writeln!(f, "push rax")?;
+            },
+            Equal | NotEqual | Less | LessEqual | Greater | GreaterEqual |
+            UnsignedLess | UnsignedLessEqual | UnsignedGreater | UnsignedGreaterEqual => {
+                let set = match self.instruction_type {
+                    Equal                => "sete",
+                    NotEqual             => "setne",
+                    Less                 => "setl",
+                    LessEqual            => "setle",
+                    Greater              => "setg",
+                    GreaterEqual         => "setge",
+                    UnsignedLess         => "setb",
+                    UnsignedLessEqual    => "setbe",
+                    UnsignedGreater      => "seta",
+                    UnsignedGreaterEqual => "setae",
+                    _                    => unreachable!()
+                };
+                writeln!(f, "pop rbx")?;
+                writeln!(f, "pop rax")?;
+                writeln!(f, "cmp rax, rbx")?;
+                writeln!(f, "{} al", set)?;
+                writeln!(f, "movzx rax, al")?;
+                writeln!(f, "push rax")?;
+            },
+            SignExtend => {
+                writeln!(f, "pop rax")?;
+                match self.operand {
+                    8  => writeln!(f, "movsx rax, al")?,
+                    16 => writeln!(f, "movsx rax, ax")?,
+                    32 => writeln!(f, "movsxd rax, eax")?,
+                    _  => {}
+                }
+                writeln!(f, "push rax")?;
+            },
+            ZeroExtend => {
+                writeln!(f, "pop rax")?;
+                match self.operand {
+                    8  => writeln!(f, "movzx rax, al")?,
+                    16 => writeln!(f, "movzx rax, ax")?,
+                    32 => writeln!(f, "mov eax, eax")?,
+                    _  => {}
+                }
+                writeln!(f, "push rax")?;
+            },
+            Jump => {
+                writeln!(f, "jmp addr_{}", self.operand)?;
+            },
+            JumpIfFalse => {
+                writeln!(f, "pop rax")?;
+                writeln!(f, "test rax, rax")?;
+                writeln!(f, "jz addr_{}", self.operand)?;
+            },
+            Enter => {
+                writeln!(f, "push rbp")?;
+                writeln!(f, "mov rbp, rsp")?;
+                writeln!(f, "sub rsp, {}", self.operand)?;
+            },
+            LoadLocal => {
+                writeln!(f, "push QWORD [rbp{:+}]", self.operand)?;
+            },
+            StoreLocal => {
+                writeln!(f, "pop QWORD [rbp{:+}]", self.operand)?;
+            },
+            Call => {
+                let function = &functions[self.operand as usize];
+                writeln!(f, "call addr_{}", function.address)?;
+                if function.parameters > 0 {
+                    writeln!(f, "add rsp, {}", function.parameters * 8)?;
+                }
+                writeln!(f, "push rax")?;
+            },
+            Return => {
+                writeln!(f, "pop rax")?;
+                writeln!(f, "mov rsp, rbp")?;
+                writeln!(f, "pop rbp")?;
+                writeln!(f, "ret")?;
+            },
+            Exit => {
+                writeln!(f, "pop rdi")?;
+                writeln!(f, "mov rax, {}", SYS_EXIT)?;
+                writeln!(f, "syscall")?;
+            },
+            Drop => {
+                writeln!(f, "add rsp, 8")?;
+            },
+            LocalAddress => {
+                writeln!(f, "lea rax, [rbp{:+}]", self.operand)?;
+                writeln!(f, "push rax")?;
+            },
+            Offset => {
+                writeln!(f, "add QWORD [rsp], {}", self.operand)?;
+            },
+            Load => {
+                writeln!(f, "pop rax")?;
+                match self.operand {
+                    1 => writeln!(f, "movzx rax, BYTE [rax]")?,
+                    2 => writeln!(f, "movzx rax, WORD [rax]")?,
+                    4 => writeln!(f, "mov eax, DWORD [rax]")?,
+                    _ => writeln!(f, "mov rax, QWORD [rax]")?
+                }
+                writeln!(f, "push rax")?;
+            },
+            Store => {
+                writeln!(f, "pop rbx")?;
+                writeln!(f, "pop rax")?;
+                match self.operand {
+                    1 => writeln!(f, "mov BYTE [rax], bl")?,
+                    2 => writeln!(f, "mov WORD [rax], bx")?,
+                    4 => writeln!(f, "mov DWORD [rax], ebx")?,
+                    _ => writeln!(f, "mov QWORD [rax], rbx")?
+                }
+            },
+            Copy => {
+                writeln!(f, "pop rsi")?;
+                writeln!(f, "pop rdi")?;
+                writeln!(f, "mov rcx, {}", self.operand)?;
+                writeln!(f, "rep movsb")?;
+            },
+            BoundsCheck => {
+                writeln!(f, "mov rax, QWORD [rsp]")?;
+                writeln!(f, "cmp rax, {}", self.operand)?;
+                writeln!(f, "jae bounds_check_failed")?;
+            },
+            Syscall => {
+                for register in SYSCALL_REGISTERS[..self.operand as usize].iter().rev() {
+                    writeln!(f, "pop {}", register)?;
+                }
+                writeln!(f, "pop rax")?;
+                writeln!(f, "syscall")?;
+                writeln!(f, "push rax")?;
+            },
+            ReadInt => {
+                writeln!(f, "call read_int")?;
+                writeln!(f, "push rax")?;
+            },
+            ReadLine => {
+                writeln!(f, "pop rsi")?;
+                writeln!(f, "pop rdi")?;
+                writeln!(f, "call read_line")?;
+                writeln!(f, "push rax")?;
+            },
+            Argc => {
+                writeln!(f, "mov rax, QWORD [arguments]")?;
+                writeln!(f, "push QWORD [rax]")?;
+            },
+            Arg => {
+                writeln!(f, "pop rdi")?;
+                writeln!(f, "call arg")?;
+                writeln!(f, "push rax")?;
+            },
+            Env => {
+                writeln!(f, "pop rdi")?;
+                writeln!(f, "call env")?;
+                writeln!(f, "push rax")?;
             },
             Print => {
-                write!(f, "pop rdi\n")?;
-                write!(f, "call print\n")?;
+                writeln!(f, "pop rdi")?;
+                writeln!(f, "call print")?;
+            },
+            PrintSigned => {
+                writeln!(f, "pop rdi")?;
+                writeln!(f, "call print_signed")?;
             }
         }
         Ok(())
     }
 }
 
-#[derive(Clone)]
+// what `#[inline]` and `#[noinline]` ask of the inliner
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IrInline {
+    #[default]
+    WhenSmall,
+    Always,
+    Never
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IrFunction {
+    pub name: Symbol,
+    pub address: usize,
+    pub parameters: usize,
+    pub inline: IrInline
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Ir {
     pub operations: Vec<IrInstruction>,
-    pub at_in_instructions: i64
+    pub at_in_instructions: i64,
+    pub functions: Vec<IrFunction>,
+    // whether indexing emits a `BoundsCheck`, set before the AST is turned
+    // into `Ir`
+    pub bounds_checks: bool
+}
+
+pub const BOUNDS_CHECK_MESSAGE: &str = "error: index out of bounds";
+pub const DIVISION_BY_ZERO_MESSAGE: &str = "error: division by zero";
+pub const DIVISION_OVERFLOW_MESSAGE: &str = "error: division overflow";
+pub const NEGATIVE_EXPONENT_MESSAGE: &str = "error: negative exponent";
+
+// where the Linux x86_64 syscall ABI takes the arguments, in order
+pub const SYSCALL_REGISTERS: [&str; 6] = ["rdi", "rsi", "rdx", "r10", "r8", "r9"];
+
+pub const SYS_READ: u64 = 0;
+pub const SYS_WRITE: u64 = 1;
+pub const SYS_EXIT: u64 = 60;
+pub const SYS_EXIT_GROUP: u64 = 231;
+
+// bytes of stdin the runtime reads at once
+pub const INPUT_BUFFER_SIZE: usize = 4096;
+
+// a routine at `label` that prints `message` to stderr and exits with 1,
+// like the simulator does
+fn write_nasm_error(file: &mut impl Write, label: &str, message: &str) -> Result<()> {
+    writeln!(file, "{}:", label)?;
+    writeln!(file, "mov rax, {}", SYS_WRITE)?;
+    writeln!(file, "mov rdi, 2")?;
+    writeln!(file, "mov rsi, {}_message", label)?;
+    writeln!(file, "mov rdx, {}", message.len() + 1)?;
+    writeln!(file, "syscall")?;
+    writeln!(file, "mov rax, {}", SYS_EXIT)?;
+    writeln!(file, "mov rdi, 1")?;
+    writeln!(file, "syscall")?;
+    writeln!(file, "{}_message: db \"{}\", 10", label, message)?;
+    Ok(())
+}
+
+// the routines every program can call and its entry point, which the code
+// of the program has to follow
+pub fn write_nasm_runtime(file: &mut impl Write) -> Result<()> {
+    writeln!(file, "BITS 64")?;
+    writeln!(file, "print:")?;
+    writeln!(file, "mov r9, -3689348814741910323")?;
+    writeln!(file, "sub rsp, 40")?;
+    writeln!(file, "mov BYTE [rsp+31], 10")?;
+    writeln!(file, "lea rcx, [rsp+30]")?;
+    writeln!(file, ".L2:")?;
+    writeln!(file, "mov rax, rdi")?;
+    writeln!(file, "lea r8, [rsp+32]")?;
+    writeln!(file, "mul r9")?;
+    writeln!(file, "mov rax, rdi")?;
+    writeln!(file, "sub r8, rcx")?;
+    writeln!(file, "shr rdx, 3")?;
+    writeln!(file, "lea rsi, [rdx+rdx*4]")?;
+    writeln!(file, "add rsi, rsi")?;
+    writeln!(file, "sub rax, rsi")?;
+    writeln!(file, "add eax, 48")?;
+    writeln!(file, "mov BYTE [rcx], al")?;
+    writeln!(file, "mov rax, rdi")?;
+    writeln!(file, "mov rdi, rdx")?;
+    writeln!(file, "mov rdx, rcx")?;
+    writeln!(file, "sub rcx, 1")?;
+    writeln!(file, "cmp rax, 9")?;
+    writeln!(file, "ja  .L2")?;
+    writeln!(file, "lea rax, [rsp+32]")?;
+    writeln!(file, "mov edi, 1")?;
+    writeln!(file, "sub rdx, rax")?;
+    writeln!(file, "xor eax, eax")?;
+    writeln!(file, "lea rsi, [rsp+32+rdx]")?;
+    writeln!(file, "mov rdx, r8")?;
+    writeln!(file, "mov rax, 1")?;
+    writeln!(file, "syscall")?;
+    writeln!(file, "add rsp, 40")?;
+    writeln!(file, "ret")?;
+    writeln!(file, "print_signed:")?;
+    writeln!(file, "test rdi, rdi")?;
+    writeln!(file, "jns print")?;
+    writeln!(file, "push rdi")?;
+    writeln!(file, "push 45")?;
+    writeln!(file, "mov rax, 1")?;
+    writeln!(file, "mov rdi, 1")?;
+    writeln!(file, "mov rsi, rsp")?;
+    writeln!(file, "mov rdx, 1")?;
+    writeln!(file, "syscall")?;
+    writeln!(file, "pop rax")?;
+    writeln!(file, "pop rdi")?;
+    writeln!(file, "neg rdi")?;
+    writeln!(file, "jmp print")?;
+    // rdi to the power of rsi in rax, the same way `power` does it
+    writeln!(file, "power:")?;
+    writeln!(file, "mov rax, 1")?;
+    writeln!(file, ".bit:")?;
+    writeln!(file, "test rsi, rsi")?;
+    writeln!(file, "jz .done")?;
+    writeln!(file, "test rsi, 1")?;
+    writeln!(file, "jz .square")?;
+    writeln!(file, "imul rax, rdi")?;
+    writeln!(file, ".square:")?;
+    writeln!(file, "imul rdi, rdi")?;
+    writeln!(file, "shr rsi, 1")?;
+    writeln!(file, "jmp .bit")?;
+    writeln!(file, ".done:")?;
+    writeln!(file, "ret")?;
+    // stops the program when rax cannot be divided by rbx, like the
+    // simulator does, only touches rcx
+    writeln!(file, "check_signed_divisor:")?;
+    writeln!(file, "cmp rbx, -1")?;
+    writeln!(file, "jne check_divisor")?;
+    writeln!(file, "mov rcx, 1")?;
+    writeln!(file, "shl rcx, 63")?;
+    writeln!(file, "cmp rax, rcx")?;
+    writeln!(file, "je division_overflow")?;
+    writeln!(file, "check_divisor:")?;
+    writeln!(file, "test rbx, rbx")?;
+    writeln!(file, "jz division_by_zero")?;
+    writeln!(file, "ret")?;
+    write_nasm_error(file, "bounds_check_failed", BOUNDS_CHECK_MESSAGE)?;
+    write_nasm_error(file, "division_by_zero", DIVISION_BY_ZERO_MESSAGE)?;
+    write_nasm_error(file, "division_overflow", DIVISION_OVERFLOW_MESSAGE)?;
+    write_nasm_error(file, "negative_exponent", NEGATIVE_EXPONENT_MESSAGE)?;
+    // returns the next byte of stdin in rax, or -1 at the end of it
+    writeln!(file, "read_byte:")?;
+    writeln!(file, "mov rax, QWORD [input_position]")?;
+    writeln!(file, "cmp rax, QWORD [input_length]")?;
+    writeln!(file, "jb .buffered")?;
+    writeln!(file, "mov rax, {}", SYS_READ)?;
+    writeln!(file, "mov rdi, 0")?;
+    writeln!(file, "lea rsi, [input_buffer]")?;
+    writeln!(file, "mov rdx, {}", INPUT_BUFFER_SIZE)?;
+    writeln!(file, "syscall")?;
+    writeln!(file, "test rax, rax")?;
+    writeln!(file, "jle .end")?;
+    writeln!(file, "mov QWORD [input_length], rax")?;
+    writeln!(file, "xor rax, rax")?;
+    writeln!(file, ".buffered:")?;
+    writeln!(file, "movzx rcx, BYTE [input_buffer+rax]")?;
+    writeln!(file, "inc rax")?;
+    writeln!(file, "mov QWORD [input_position], rax")?;
+    writeln!(file, "mov rax, rcx")?;
+    writeln!(file, "ret")?;
+    writeln!(file, ".end:")?;
+    writeln!(file, "mov QWORD [input_position], 0")?;
+    writeln!(file, "mov QWORD [input_length], 0")?;
+    writeln!(file, "mov rax, -1")?;
+    writeln!(file, "ret")?;
+    // the byte right after the number is taken as well
+    writeln!(file, "read_int:")?;
+    writeln!(file, "push rbx")?;
+    writeln!(file, "push r12")?;
+    writeln!(file, ".skip:")?;
+    writeln!(file, "call read_byte")?;
+    writeln!(file, "cmp rax, -1")?;
+    writeln!(file, "je .zero")?;
+    writeln!(file, "cmp rax, 32")?;
+    writeln!(file, "jbe .skip")?;
+    writeln!(file, "xor r12, r12")?;
+    writeln!(file, "cmp rax, 45")?;
+    writeln!(file, "jne .number")?;
+    writeln!(file, "mov r12, 1")?;
+    writeln!(file, "call read_byte")?;
+    writeln!(file, ".number:")?;
+    writeln!(file, "xor rbx, rbx")?;
+    writeln!(file, ".digit:")?;
+    writeln!(file, "cmp rax, 48")?;
+    writeln!(file, "jb .done")?;
+    writeln!(file, "cmp rax, 57")?;
+    writeln!(file, "ja .done")?;
+    writeln!(file, "imul rbx, rbx, 10")?;
+    writeln!(file, "lea rbx, [rbx+rax-48]")?;
+    writeln!(file, "call read_byte")?;
+    writeln!(file, "jmp .digit")?;
+    writeln!(file, ".done:")?;
+    writeln!(file, "mov rax, rbx")?;
+    writeln!(file, "test r12, r12")?;
+    writeln!(file, "jz .return")?;
+    writeln!(file, "neg rax")?;
+    writeln!(file, "jmp .return")?;
+    writeln!(file, ".zero:")?;
+    writeln!(file, "xor rax, rax")?;
+    writeln!(file, ".return:")?;
+    writeln!(file, "pop r12")?;
+    writeln!(file, "pop rbx")?;
+    writeln!(file, "ret")?;
+    // what does not fit in the buffer is skipped, the newline is not
+    // stored
+    writeln!(file, "read_line:")?;
+    writeln!(file, "push rbx")?;
+    writeln!(file, "push r12")?;
+    writeln!(file, "push r13")?;
+    writeln!(file, "mov r12, rdi")?;
+    writeln!(file, "mov r13, rsi")?;
+    writeln!(file, "xor rbx, rbx")?;
+    writeln!(file, "call read_byte")?;
+    writeln!(file, "cmp rax, -1")?;
+    writeln!(file, "je .return")?;
+    writeln!(file, ".byte:")?;
+    writeln!(file, "cmp rax, -1")?;
+    writeln!(file, "je .done")?;
+    writeln!(file, "cmp rax, 10")?;
+    writeln!(file, "je .done")?;
+    writeln!(file, "cmp rbx, r13")?;
+    writeln!(file, "jae .next")?;
+    writeln!(file, "mov BYTE [r12+rbx], al")?;
+    writeln!(file, "inc rbx")?;
+    writeln!(file, ".next:")?;
+    writeln!(file, "call read_byte")?;
+    writeln!(file, "jmp .byte")?;
+    writeln!(file, ".done:")?;
+    writeln!(file, "mov rax, rbx")?;
+    writeln!(file, ".return:")?;
+    writeln!(file, "pop r13")?;
+    writeln!(file, "pop r12")?;
+    writeln!(file, "pop rbx")?;
+    writeln!(file, "ret")?;
+    // argc is where `_start` found the stack, argv and then envp follow
+    // it, each ending with a null pointer
+    writeln!(file, "arg:")?;
+    writeln!(file, "mov rax, QWORD [arguments]")?;
+    writeln!(file, "cmp rdi, QWORD [rax]")?;
+    writeln!(file, "jae .none")?;
+    writeln!(file, "mov rax, QWORD [rax+8+rdi*8]")?;
+    writeln!(file, "ret")?;
+    writeln!(file, ".none:")?;
+    writeln!(file, "xor rax, rax")?;
+    writeln!(file, "ret")?;
+    writeln!(file, "env:")?;
+    writeln!(file, "mov rax, QWORD [arguments]")?;
+    writeln!(file, "mov rcx, QWORD [rax]")?;
+    writeln!(file, "lea rax, [rax+16+rcx*8]")?;
+    writeln!(file, ".next:")?;
+    writeln!(file, "cmp QWORD [rax], 0")?;
+    writeln!(file, "je .none")?;
+    writeln!(file, "test rdi, rdi")?;
+    writeln!(file, "jz .found")?;
+    writeln!(file, "dec rdi")?;
+    writeln!(file, "add rax, 8")?;
+    writeln!(file, "jmp .next")?;
+    writeln!(file, ".found:")?;
+    writeln!(file, "mov rax, QWORD [rax]")?;
+    writeln!(file, "ret")?;
+    writeln!(file, ".none:")?;
+    writeln!(file, "xor rax, rax")?;
+    writeln!(file, "ret")?;
+    writeln!(file, "section .bss")?;
+    writeln!(file, "arguments: resq 1")?;
+    writeln!(file, "input_buffer: resb {}", INPUT_BUFFER_SIZE)?;
+    writeln!(file, "input_position: resq 1")?;
+    writeln!(file, "input_length: resq 1")?;
+    writeln!(file, "section .text")?;
+    writeln!(file, "global _start")?;
+    writeln!(file, "_start:")?;
+    writeln!(file, "mov QWORD [arguments], rsp")?;
+    Ok(())
 }
 
 impl Ir {
     pub fn new() -> Ir {
         Ir {
             operations: vec![],
-            at_in_instructions: 0
+            at_in_instructions: 0,
+            functions: vec![],
+            bounds_checks: true
         }
     }
 
@@ -87,60 +723,513 @@ impl Ir {
         self.at_in_instructions += 1;
     }
 
-    pub fn to_nasm_linux_x86_64_assembly(&self, output: String) -> Result<()> {
-        let mut file = File::options()
-            .read(true)
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(output)?;
-
-        write!(file, "BITS 64\n")?;
-        write!(file, "print:\n")?;
-        write!(file, "mov r9, -3689348814741910323\n")?;
-        write!(file, "sub rsp, 40\n")?;
-        write!(file, "mov BYTE [rsp+31], 10\n")?;
-        write!(file, "lea rcx, [rsp+30]\n")?;
-        write!(file, ".L2:\n")?;
-        write!(file, "mov rax, rdi\n")?;
-        write!(file, "lea r8, [rsp+32]\n")?;
-        write!(file, "mul r9\n")?;
-        write!(file, "mov rax, rdi\n")?;
-        write!(file, "sub r8, rcx\n")?;
-        write!(file, "shr rdx, 3\n")?;
-        write!(file, "lea rsi, [rdx+rdx*4]\n" )?;
-        write!(file, "add rsi, rsi\n")?;
-        write!(file, "sub rax, rsi\n")?;
-        write!(file, "add eax, 48\n")?;
-        write!(file, "mov BYTE [rcx], al\n")?;
-        write!(file, "mov rax, rdi\n")?;
-        write!(file, "mov rdi, rdx\n")?;
-        write!(file, "mov rdx, rcx\n")?;
-        write!(file, "sub rcx, 1\n")?;
-        write!(file, "cmp rax, 9\n")?;
-        write!(file, "ja  .L2\n")?;
-        write!(file, "lea rax, [rsp+32]\n")?;
-        write!(file, "mov edi, 1\n")?;
-        write!(file, "sub rdx, rax\n")?;
-        write!(file, "xor eax, eax\n")?;
-        write!(file, "lea rsi, [rsp+32+rdx]\n")?;
-        write!(file, "mov rdx, r8\n")?;
-        write!(file, "mov rax, 1\n")?;
-        write!(file, "syscall\n")?;
-        write!(file, "add rsp, 40\n")?;
-        write!(file, "ret\n")?;
-        write!(file, "global _start\n")?;
-        write!(file, "_start:\n")?;
-
-        for operation in &self.operations {
-            operation.to_nasm_linux_x86_64_assembly(&mut file)?;
-        }
-
-        write!(file, "mov rax, 60\n")?;
-        write!(file, "mov rdi, 0\n")?;
-        write!(file, "syscall\n")?;
-
-        file.sync_all()?;
+    // pushes a jump whose target is not known yet, `patch_jump` fills it in
+    // once the code it jumps over has been pushed
+    pub fn push_jump(&mut self, instruction_type: IrInstructionType) -> usize {
+        self.push(IrInstruction { instruction_type, operand: 0 });
+        self.operations.len() - 1
+    }
+
+    // pushes a `Load` of a value of type `ty` from the address on top of the
+    // stack, leaving it extended the way values of `ty` always are
+    pub fn push_load(&mut self, ty: DangType) {
+        self.push(IrInstruction { instruction_type: IrInstructionType::Load, operand: ty.size() });
+        if let Some(integer_type) = ty.as_integer().filter(|x| x.is_signed()) {
+            self.push_extend(integer_type);
+        }
+    }
+
+    // pushes the variable of type `ty` at `offset`. it has a whole 8 byte
+    // slot, but a store through a pointer to it only writes the bytes of
+    // `ty`, so sized integers are extended again from those
+    pub fn push_load_local(&mut self, offset: i64, ty: DangType) {
+        self.push(IrInstruction { instruction_type: IrInstructionType::LoadLocal, operand: offset });
+        if let Some(integer_type) = ty.as_integer() {
+            self.push_extend(integer_type);
+        }
+    }
+
+    // brings a value that may have left the range of `integer_type` back
+    // into it, 64 bit values wrap on their own
+    pub fn push_extend(&mut self, integer_type: DangIntegerType) {
+        if integer_type.bits() < 64 {
+            let instruction_type = if integer_type.is_signed() { IrInstructionType::SignExtend } else { IrInstructionType::ZeroExtend };
+            self.push(IrInstruction { instruction_type, operand: integer_type.bits() as i64 });
+        }
+    }
+
+    // exits with the status `status` pushes
+    pub fn push_exit(&mut self, status: impl FnOnce(&mut Ir)) {
+        status(self);
+        self.push(IrInstruction { instruction_type: IrInstructionType::Exit, operand: 0 });
+    }
+
+    pub fn patch_jump(&mut self, jump: usize) {
+        self.operations[jump].operand = self.operations.len() as i64;
+    }
+
+    // `peephole` runs `optimize_assembly` over the result, which needs labels
+    // only where something jumps to
+    pub fn to_nasm_linux_x86_64_assembly(&self, output: String, peephole: bool) -> Result<()> {
+        let mut file = vec![];
+
+        write_nasm_runtime(&mut file)?;
+
+        let mut labels = vec![!peephole; self.operations.len() + 1];
+        for operation in self.operations.iter().filter(|x| x.instruction_type.is_jump()) {
+            labels[operation.operand as usize] = true;
+        }
+        for function in &self.functions {
+            labels[function.address] = true;
+        }
+
+        for (address, operation) in self.operations.iter().enumerate() {
+            if let Some(function) = self.functions.iter().find(|x| x.address == address) {
+                writeln!(file, ";; -- fn {} --", function.name)?;
+            }
+            if labels[address] {
+                writeln!(file, "addr_{}:", address)?;
+            }
+            operation.to_nasm_linux_x86_64_assembly(&mut file, &self.functions)?;
+        }
+        writeln!(file, "addr_{}:", self.operations.len())?;
+
+        writeln!(file, "mov rax, {}", SYS_EXIT)?;
+        writeln!(file, "mov rdi, 0")?;
+        writeln!(file, "syscall")?;
+
+        let assembly = String::from_utf8(file).unwrap();
+        if peephole {
+            std::fs::write(output, optimize_assembly(&assembly))
+        } else {
+            std::fs::write(output, assembly)
+        }
+    }
+}
+
+// -=-=-=-= begin text format =-=-=-=-
+
+// the name of every instruction in the text format and whether it takes an
+// operand, the ones that do not still print it when it is not 0, the index
+// in here is the opcode in bytecode so new instructions go at the end
+pub const INSTRUCTION_NAMES: &[(IrInstructionType, &str, bool)] = &[
+    (IrInstructionType::PushInt,              "push_int",                true),
+    (IrInstructionType::Plus,                 "plus",                    false),
+    (IrInstructionType::Minus,                "minus",                   false),
+    (IrInstructionType::Division,             "division",                false),
+    (IrInstructionType::UnsignedDivision,     "unsigned_division",       false),
+    (IrInstructionType::Multiplication,       "multiplication",          false),
+    (IrInstructionType::Mod,                  "mod",                     false),
+    (IrInstructionType::UnsignedMod,          "unsigned_mod",            false),
+    (IrInstructionType::Power,                "power",                   false),
+    (IrInstructionType::UnsignedPower,        "unsigned_power",          false),
+    (IrInstructionType::Negate,               "negate",                  false),
+    (IrInstructionType::Not,                  "not",                     false),
+    (IrInstructionType::Equal,                "equal",                   false),
+    (IrInstructionType::NotEqual,             "not_equal",               false),
+    (IrInstructionType::Less,                 "less",                    false),
+    (IrInstructionType::LessEqual,            "less_equal",              false),
+    (IrInstructionType::Greater,              "greater",                 false),
+    (IrInstructionType::GreaterEqual,         "greater_equal",           false),
+    (IrInstructionType::UnsignedLess,         "unsigned_less",           false),
+    (IrInstructionType::UnsignedLessEqual,    "unsigned_less_equal",     false),
+    (IrInstructionType::UnsignedGreater,      "unsigned_greater",        false),
+    (IrInstructionType::UnsignedGreaterEqual, "unsigned_greater_equal",  false),
+    (IrInstructionType::SignExtend,           "sign_extend",             true),
+    (IrInstructionType::ZeroExtend,           "zero_extend",             true),
+    (IrInstructionType::Jump,                 "jump",                    true),
+    (IrInstructionType::JumpIfFalse,          "jump_if_false",           true),
+    (IrInstructionType::Enter,                "enter",                   true),
+    (IrInstructionType::LoadLocal,            "load_local",              true),
+    (IrInstructionType::StoreLocal,           "store_local",             true),
+    (IrInstructionType::Call,                 "call",                    true),
+    (IrInstructionType::Return,               "return",                  false),
+    (IrInstructionType::Drop,                 "drop",                    false),
+    (IrInstructionType::LocalAddress,         "local_address",           true),
+    (IrInstructionType::Offset,               "offset",                  true),
+    (IrInstructionType::Load,                 "load",                    true),
+    (IrInstructionType::Store,                "store",                   true),
+    (IrInstructionType::Copy,                 "copy",                    true),
+    (IrInstructionType::BoundsCheck,          "bounds_check",            true),
+    (IrInstructionType::Syscall,              "syscall",                 true),
+    (IrInstructionType::ReadInt,              "read_int",                false),
+    (IrInstructionType::ReadLine,             "read_line",               false),
+    (IrInstructionType::Argc,                 "argc",                    false),
+    (IrInstructionType::Arg,                  "arg",                     false),
+    (IrInstructionType::Env,                  "env",                     false),
+    (IrInstructionType::Print,                "print",                   false),
+    (IrInstructionType::PrintSigned,          "print_signed",            false),
+    (IrInstructionType::Exit,                 "exit",                    false)
+];
+
+impl IrInstructionType {
+    pub fn name(self) -> &'static str {
+        INSTRUCTION_NAMES.iter().find(|x| x.0 == self).unwrap().1
+    }
+
+    pub fn has_operand(self) -> bool {
+        INSTRUCTION_NAMES.iter().find(|x| x.0 == self).unwrap().2
+    }
+
+    pub fn from_name(name: &str) -> Option<IrInstructionType> {
+        INSTRUCTION_NAMES.iter().find(|x| x.1 == name).map(|x| x.0)
+    }
+}
+
+impl fmt::Display for IrInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.instruction_type.name())?;
+        if self.instruction_type.has_operand() || self.operand != 0 {
+            write!(f, " {}", self.operand)?;
+        }
         Ok(())
     }
 }
+
+/// One declaration per line, every function as
+/// `function <name> <parameters> <address>` followed by `inline` or
+/// `noinline` when it has one of those attributes, in the order calls refer
+/// to them, and then one instruction per line. Whatever follows a `;` is a
+/// comment, the printed comments only help reading it.
+impl fmt::Display for Ir {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for function in &self.functions {
+            write!(f, "function {} {} {}", function.name, function.parameters, function.address)?;
+            match function.inline {
+                IrInline::WhenSmall => writeln!(f)?,
+                IrInline::Always    => writeln!(f, " inline")?,
+                IrInline::Never     => writeln!(f, " noinline")?
+            }
+        }
+
+        for (address, operation) in self.operations.iter().enumerate() {
+            if let Some(function) = self.functions.iter().find(|x| x.address == address) {
+                writeln!(f, "; fn {}", function.name)?;
+            }
+            write!(f, "    {}", operation)?;
+            // invalid `Ir` is printed as well, the parser does not check it
+            let function = usize::try_from(operation.operand).ok().and_then(|x| self.functions.get(x));
+            match (operation.instruction_type, function) {
+                (IrInstructionType::Call, Some(function)) => writeln!(f, " ; {} ; {}", function.name, address)?,
+                _                                         => writeln!(f, " ; {}", address)?
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(word: Option<(LexerSpan, &str)>, what: &str, line: LexerSpan) -> std::result::Result<T, Diagnostic> {
+    let (span, text) = word.ok_or_else(|| Diagnostic::new(line, format!("expected {}", what)))?;
+    text.parse().map_err(|_| Diagnostic::new(span, format!("expected {}, found `{}`", what, text)))
+}
+
+impl Ir {
+    // reads back what `Display` prints, the result does not have to be
+    // valid `Ir`
+    pub fn parse(source: &str) -> std::result::Result<Ir, Diagnostic> {
+        let mut ir = Ir::new();
+
+        let mut start = 0;
+        for line in source.split_inclusive('\n') {
+            let line_span = LexerSpan { start, end: start + line.len() };
+            start += line.len();
+            let code = line.split(';').next().unwrap();
+            let mut words = code.split_whitespace().map(|word| {
+                let offset = line_span.start + word.as_ptr() as usize - line.as_ptr() as usize;
+                (LexerSpan { start: offset, end: offset + word.len() }, word)
+            });
+
+            let Some((span, name)) = words.next() else {
+                continue;
+            };
+
+            if name == "function" {
+                let (_, function) = words.next().ok_or_else(|| Diagnostic::new(line_span, "expected a function name".to_string()))?;
+                let parameters = parse_number(words.next(), "a parameter count", line_span)?;
+                let address = parse_number(words.next(), "an address", line_span)?;
+                let inline = match words.clone().next() {
+                    Some((_, "inline"))   => IrInline::Always,
+                    Some((_, "noinline")) => IrInline::Never,
+                    _                     => IrInline::WhenSmall
+                };
+                if inline != IrInline::WhenSmall {
+                    words.next();
+                }
+                ir.functions.push(IrFunction { name: Symbol::intern(function), address, parameters, inline });
+            } else {
+                let instruction_type = IrInstructionType::from_name(name)
+                    .ok_or_else(|| Diagnostic::new(span, format!("unknown instruction `{}`", name)))?;
+                let operand = match words.clone().next() {
+                    Some(_)                                => parse_number(words.next(), "an operand", line_span)?,
+                    None if instruction_type.has_operand() => return Err(Diagnostic::new(span, format!("`{}` takes an operand", name))),
+                    None                                   => 0
+                };
+                ir.push(IrInstruction { instruction_type, operand });
+            }
+
+            if let Some((span, word)) = words.next() {
+                return Err(Diagnostic::new(span, format!("unexpected `{}`", word)));
+            }
+        }
+
+        Ok(ir)
+    }
+}
+
+// -=-=-=-= end text format =-=-=-=-
+
+// -=-=-=-= begin verifier =-=-=-=-
+
+/// Why `Ir::verify` rejected an `Ir`, `address` is the offending instruction
+/// when there is one.
+#[derive(Debug, Clone)]
+pub struct IrVerifyError {
+    pub address: Option<usize>,
+    pub message: String
+}
+
+impl fmt::Display for IrVerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.address {
+            Some(address) => write!(f, "instruction {}: {}", address, self.message),
+            None          => write!(f, "{}", self.message)
+        }
+    }
+}
+
+impl IrInstruction {
+    // how many values the instruction pops and how many it pushes, `Return`
+    // pushes nothing here because execution continues after the `Call`
+    pub fn stack_effect(&self, functions: &[IrFunction]) -> (usize, usize) {
+        use IrInstructionType::*;
+        match self.instruction_type {
+            PushInt | LoadLocal | LocalAddress | ReadInt | Argc => (0, 1),
+            Plus | Minus | Division | UnsignedDivision | Multiplication | Mod | UnsignedMod | Power | UnsignedPower |
+            Equal | NotEqual | Less | LessEqual | Greater | GreaterEqual |
+            UnsignedLess | UnsignedLessEqual | UnsignedGreater | UnsignedGreaterEqual |
+            ReadLine => (2, 1),
+            Negate | Not | SignExtend | ZeroExtend | Offset | Load | BoundsCheck | Arg | Env => (1, 1),
+            Jump | Enter => (0, 0),
+            JumpIfFalse | StoreLocal | Drop | Return | Exit | Print | PrintSigned => (1, 0),
+            Store | Copy => (2, 0),
+            Call => (functions[self.operand as usize].parameters, 1),
+            Syscall => (self.operand as usize + 1, 1)
+        }
+    }
+
+    fn check_operand(&self, length: usize, functions: &[IrFunction]) -> std::result::Result<(), String> {
+        use IrInstructionType::*;
+        let operand = self.operand;
+        let valid = match self.instruction_type {
+            PushInt | Offset => true,
+            SignExtend | ZeroExtend => matches!(operand, 8 | 16 | 32 | 64),
+            Jump | JumpIfFalse => (0..=length as i64).contains(&operand),
+            Enter | Copy | BoundsCheck => operand >= 0,
+            // they end up as displacements, which x86_64 has 32 bits for
+            LoadLocal | StoreLocal | LocalAddress => i32::try_from(operand).is_ok(),
+            Call => (0..functions.len() as i64).contains(&operand),
+            Load | Store => matches!(operand, 1 | 2 | 4 | 8),
+            Syscall => (0..=SYSCALL_REGISTERS.len() as i64).contains(&operand),
+            _ => operand == 0
+        };
+
+        if valid {
+            Ok(())
+        } else if self.instruction_type.has_operand() {
+            Err(format!("`{}` cannot take {} as its operand", self.instruction_type.name(), operand))
+        } else {
+            Err(format!("`{}` takes no operand", self.instruction_type.name()))
+        }
+    }
+}
+
+// records that `to` is reached with `depth` values on the stack
+fn reach(depths: &mut [Option<usize>], work: &mut Vec<usize>, from: Option<usize>, to: usize, depth: usize) -> std::result::Result<(), IrVerifyError> {
+    match depths[to] {
+        None => {
+            depths[to] = Some(depth);
+            work.push(to);
+            Ok(())
+        }
+        Some(expected) if expected == depth => Ok(()),
+        Some(expected) => Err(IrVerifyError {
+            address: from,
+            message: format!("reaches {} with a stack depth of {} but another path reaches it with {}", to, depth, expected)
+        })
+    }
+}
+
+impl Ir {
+    // checks that every operand is valid, that the stack never underflows
+    // and that it has the same depth whichever way an instruction is
+    // reached. the program and every function start with an empty stack,
+    // the program has to end with one and only functions can return.
+    // returns the depth before each instruction, `None` for the ones
+    // nothing reaches
+    pub fn verify(&self) -> std::result::Result<Vec<Option<usize>>, IrVerifyError> {
+        let length = self.operations.len();
+        let error = |address, message| IrVerifyError { address, message };
+
+        for function in &self.functions {
+            if function.address >= length {
+                return Err(error(None, format!("function `{}` starts at {}, past the end of the program", function.name, function.address)));
+            }
+        }
+        for (address, operation) in self.operations.iter().enumerate() {
+            operation.check_operand(length, &self.functions).map_err(|message| error(Some(address), message))?;
+        }
+
+        // one more for the end of the program
+        let mut depths: Vec<Option<usize>> = vec![None; length + 1];
+        let mut work = vec![];
+
+        if length > 0 {
+            reach(&mut depths, &mut work, None, 0, 0)?;
+        }
+        for function in &self.functions {
+            reach(&mut depths, &mut work, None, function.address, 0)
+                .map_err(|_| error(Some(function.address), format!("function `{}` has to start with an empty stack", function.name)))?;
+        }
+
+        while let Some(address) = work.pop() {
+            if address == length {
+                continue;
+            }
+            let operation = &self.operations[address];
+            let depth = depths[address].unwrap();
+            let (pops, pushes) = operation.stack_effect(&self.functions);
+            if depth < pops {
+                return Err(error(Some(address), format!("`{}` pops {} but the stack depth is {}", operation.instruction_type.name(), pops, depth)));
+            }
+            let next = depth - pops + pushes;
+
+            match operation.instruction_type {
+                IrInstructionType::Jump        => reach(&mut depths, &mut work, Some(address), operation.operand as usize, next)?,
+                IrInstructionType::JumpIfFalse => {
+                    reach(&mut depths, &mut work, Some(address), operation.operand as usize, next)?;
+                    reach(&mut depths, &mut work, Some(address), address + 1, next)?;
+                }
+                kind if kind.leaves()          => {}
+                _                              => reach(&mut depths, &mut work, Some(address), address + 1, next)?
+            }
+        }
+
+        if let Some(depth) = depths[length].filter(|x| *x != 0) {
+            return Err(error(None, format!("the program ends with a stack depth of {}", depth)));
+        }
+
+        // the top level statements are not called, so a `return` they reach
+        // has nowhere to go back to
+        let mut top_level = vec![false; length + 1];
+        let mut work = vec![0];
+        while let Some(address) = work.pop() {
+            if address == length || top_level[address] {
+                continue;
+            }
+            top_level[address] = true;
+            if self.operations[address].instruction_type == IrInstructionType::Return {
+                return Err(error(Some(address), "`return` outside of a function".to_string()));
+            }
+            work.extend(successors(self, address));
+        }
+
+        depths.pop();
+        Ok(depths)
+    }
+}
+
+// -=-=-=-= end verifier =-=-=-=-
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::compile;
+
+    // every instruction with operands of every size and functions with every
+    // inlining attribute, which does not have to be valid
+    pub fn every_instruction() -> Ir {
+        let mut ir = Ir::new();
+        for (instruction_type, _, _) in INSTRUCTION_NAMES {
+            for operand in [0, 1, -1, 300, -70000, i64::MAX, i64::MIN] {
+                ir.push(IrInstruction { instruction_type: *instruction_type, operand });
+            }
+        }
+        for (index, inline) in [IrInline::WhenSmall, IrInline::Always, IrInline::Never].into_iter().enumerate() {
+            ir.functions.push(IrFunction { name: Symbol::intern(&format!("f{}", index)), address: index * 3, parameters: index, inline });
+        }
+        ir
+    }
+
+    pub const PROGRAM: &str = "
+        struct Point { x: i64, y: i64 }
+        #[inline]
+        fn dist(p: Point) -> i64 { return p.x * p.x + p.y * p.y }
+        #[noinline]
+        fn fact(n: i64) -> i64 { if n < 2 { return 1 } return n * fact(n - 1) }
+        let p = Point { x: 3, y: -4000000000 };
+        let a: [u8; 3] = [1, 2, 3];
+        print!(dist(p) + fact(a[2] as i64) ** 2);
+    ";
+
+    #[test]
+    fn text_round_trips() {
+        for ir in [every_instruction(), compile(PROGRAM)] {
+            assert_eq!(Ir::parse(&ir.to_string()).unwrap(), ir);
+        }
+    }
+
+    #[test]
+    fn text_reports_where_it_is_wrong() {
+        let error = Ir::parse("    plus\n    frobnicate 3\n").unwrap_err();
+        assert_eq!((error.span.start, error.span.end), (13, 23));
+        assert_eq!(error.message, "unknown instruction `frobnicate`");
+
+        let error = Ir::parse("push_int\n").unwrap_err();
+        assert_eq!(error.message, "`push_int` takes an operand");
+        let error = Ir::parse("plus 1 2\n").unwrap_err();
+        assert_eq!(error.message, "unexpected `2`");
+    }
+
+    fn verify_error(source: &str) -> IrVerifyError {
+        Ir::parse(source).unwrap().verify().unwrap_err()
+    }
+
+    #[test]
+    fn verify_accepts_compiled_programs() {
+        compile(PROGRAM).verify().unwrap();
+    }
+
+    #[test]
+    fn verify_reports_underflows() {
+        let error = verify_error("push_int 1\nplus\n");
+        assert_eq!(error.address, Some(1));
+        assert_eq!(error.message, "`plus` pops 2 but the stack depth is 1");
+    }
+
+    #[test]
+    fn verify_reports_mismatched_depths() {
+        let error = verify_error("push_int 1\njump_if_false 4\npush_int 2\njump 4\npush_int 3\ndrop\n");
+        assert_eq!(error.address, Some(3));
+        assert_eq!(error.message, "reaches 4 with a stack depth of 1 but another path reaches it with 0");
+    }
+
+    #[test]
+    fn verify_reports_bad_jump_targets() {
+        let error = verify_error("push_int 1\njump 3\n");
+        assert_eq!(error.address, Some(1));
+        assert_eq!(error.message, "`jump` cannot take 3 as its operand");
+
+        let error = verify_error("function f 0 2\npush_int 1\ndrop\n");
+        assert_eq!(error.address, None);
+    }
+
+    #[test]
+    fn verify_reports_returns_outside_of_functions() {
+        let error = verify_error("enter 0\npush_int 1\nreturn\n");
+        assert_eq!(error.address, Some(2));
+        assert_eq!(error.message, "`return` outside of a function");
+
+        // a jump from the top level into a function body is just as bad
+        let error = verify_error("function f 0 1\njump 2\nenter 0\npush_int 1\nreturn\n");
+        assert_eq!(error.address, Some(3));
+    }
+}
@@ -0,0 +1,114 @@
+use crate::symbol::*;
+use crate::types::*;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+/// Where values of a type go in memory, `size` is always a multiple of
+/// `align` so arrays of it need no padding between elements.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DangLayout {
+    pub size: i64,
+    pub align: i64
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DangStructField {
+    pub name: Symbol,
+    pub ty: DangType,
+    // bytes from the start of the struct
+    pub offset: i64
+}
+
+#[derive(Debug, Clone)]
+pub struct DangStructLayout {
+    pub fields: Vec<DangStructField>,
+    pub layout: DangLayout
+}
+
+// structs are only known by name in `DangType`, like symbols their layouts
+// live here for the whole run so anything can ask for the size of a type
+thread_local! {
+    static STRUCTS: RefCell<HashMap<Symbol, DangStructLayout>> = RefCell::new(HashMap::new());
+}
+
+fn align_to(offset: i64, align: i64) -> i64 {
+    (offset + align - 1) / align * align
+}
+
+impl DangLayout {
+    pub fn of(ty: DangType) -> DangLayout {
+        match ty {
+            DangType::Integer(integer_type) => {
+                let size = integer_type.bits() as i64 / 8;
+                DangLayout { size, align: size }
+            }
+            DangType::Bool                   => DangLayout { size: 1, align: 1 },
+            DangType::Pointer(_)             => DangLayout { size: 8, align: 8 },
+            DangType::Array(element, length) => {
+                let element = DangLayout::of(*element);
+                DangLayout { size: element.size * length as i64, align: element.align }
+            }
+            DangType::Struct(name) => match DangLayout::struct_layout(name) {
+                Some(layout) => layout.layout,
+                None         => panic!("unreachable: struct `{}` is used before its layout is computed", name)
+            }
+            DangType::Unit | DangType::Unknown => DangLayout { size: 0, align: 1 }
+        }
+    }
+
+    // lays the fields out in order, each at the first offset that suits its
+    // alignment, the layouts of the field types have to be known already
+    pub fn define_struct(name: Symbol, fields: &[(Symbol, DangType)]) -> DangLayout {
+        let mut offset = 0;
+        let mut align = 1;
+        let mut struct_fields = vec![];
+        for &(field, ty) in fields {
+            let layout = DangLayout::of(ty);
+            offset = align_to(offset, layout.align);
+            struct_fields.push(DangStructField { name: field, ty, offset });
+            offset += layout.size;
+            align = align.max(layout.align);
+        }
+
+        let layout = DangLayout { size: align_to(offset, align), align };
+        STRUCTS.with(|structs| structs.borrow_mut().insert(name, DangStructLayout { fields: struct_fields, layout }));
+        layout
+    }
+
+    pub fn struct_layout(name: Symbol) -> Option<DangStructLayout> {
+        STRUCTS.with(|structs| structs.borrow().get(&name).cloned())
+    }
+
+    pub fn field(name: Symbol, field: Symbol) -> Option<DangStructField> {
+        DangLayout::struct_layout(name)?.fields.into_iter().find(|x| x.name == field)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn offsets(name: Symbol) -> Vec<i64> {
+        DangLayout::struct_layout(name).unwrap().fields.iter().map(|x| x.offset).collect()
+    }
+
+    #[test]
+    fn fields_are_padded_to_their_alignment() {
+        let u8 = DangType::Integer(DangIntegerType::U8);
+        let u16 = DangType::Integer(DangIntegerType::U16);
+        let i64 = DangType::Integer(DangIntegerType::I64);
+
+        let small = Symbol::intern("Small");
+        let layout = DangLayout::define_struct(small, &[(Symbol::intern("a"), u8), (Symbol::intern("b"), u16), (Symbol::intern("c"), u8)]);
+        assert_eq!(layout, DangLayout { size: 6, align: 2 });
+        assert_eq!(offsets(small), vec![0, 2, 4]);
+
+        let big = Symbol::intern("Big");
+        let layout = DangLayout::define_struct(big, &[(Symbol::intern("flag"), DangType::Bool), (Symbol::intern("small"), DangType::Struct(small)), (Symbol::intern("x"), i64)]);
+        assert_eq!(layout, DangLayout { size: 16, align: 8 });
+        assert_eq!(offsets(big), vec![0, 2, 8]);
+        assert_eq!(DangLayout::field(big, Symbol::intern("x")).unwrap().ty, i64);
+        assert!(DangLayout::field(big, Symbol::intern("y")).is_none());
+    }
+}
@@ -1,6 +1,8 @@
-use std::iter::Peekable;
+use crate::symbol::*;
 
-#[derive(Debug, PartialEq)]
+use std::fmt::*;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum LexerTokenKind {
     Integer,
     Word,
@@ -8,33 +10,109 @@ pub enum LexerTokenKind {
     CloseParen,
     OpenCurly,
     CloseCurly,
+    OpenBracket,
+    CloseBracket,
     Plus,
+    PlusEqual,
     Minus,
+    Arrow,
     Multiplication,
+    Power,
     Division,
     Mod,
     ExclamationMark,
-    Comma
+    NotEqual,
+    Equal,
+    EqualEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Ampersand,
+    AndAnd,
+    Pipe,
+    OrOr,
+    Comma,
+    Dot,
+    Colon,
+    Semicolon,
+    Hash
 }
 
-#[derive(Debug)]
-pub struct LexerTokenValue {
-    pub integer: i64,
-    pub string: String
+impl Display for LexerTokenKind {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        use LexerTokenKind::*;
+        match self {
+            Integer         => write!(f, "integer"),
+            Word            => write!(f, "word"),
+            OpenParen       => write!(f, "`(`"),
+            CloseParen      => write!(f, "`)`"),
+            OpenCurly       => write!(f, "`{{`"),
+            CloseCurly      => write!(f, "`}}`"),
+            OpenBracket     => write!(f, "`[`"),
+            CloseBracket    => write!(f, "`]`"),
+            Plus            => write!(f, "`+`"),
+            PlusEqual       => write!(f, "`+=`"),
+            Minus           => write!(f, "`-`"),
+            Arrow           => write!(f, "`->`"),
+            Multiplication  => write!(f, "`*`"),
+            Power           => write!(f, "`**`"),
+            Division        => write!(f, "`/`"),
+            Mod             => write!(f, "`%`"),
+            ExclamationMark => write!(f, "`!`"),
+            NotEqual        => write!(f, "`!=`"),
+            Equal           => write!(f, "`=`"),
+            EqualEqual      => write!(f, "`==`"),
+            Less            => write!(f, "`<`"),
+            LessEqual       => write!(f, "`<=`"),
+            Greater         => write!(f, "`>`"),
+            GreaterEqual    => write!(f, "`>=`"),
+            Ampersand       => write!(f, "`&`"),
+            AndAnd          => write!(f, "`&&`"),
+            Pipe            => write!(f, "`|`"),
+            OrOr            => write!(f, "`||`"),
+            Comma           => write!(f, "`,`"),
+            Dot             => write!(f, "`.`"),
+            Colon           => write!(f, "`:`"),
+            Semicolon       => write!(f, "`;`"),
+            Hash            => write!(f, "`#`")
+        }
+    }
+}
+
+// byte offsets into the source, `start` inclusive and `end` exclusive.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LexerSpan {
+    pub start: usize,
+    pub end: usize
+}
+
+impl LexerSpan {
+    // the span covering both `self` and `other`
+    pub fn to(self, other: LexerSpan) -> LexerSpan {
+        LexerSpan { start: self.start.min(other.start), end: self.end.max(other.end) }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LexerTokenValue {
+    None,
+    Integer(u64),
+    Word(Symbol)
 }
 
 impl LexerTokenValue {
-    pub fn from_string(s: String) -> LexerTokenValue {
-        LexerTokenValue {
-            integer: 0,
-            string: s
+    pub fn as_integer(&self) -> Option<u64> {
+        match self {
+            LexerTokenValue::Integer(i) => Some(*i),
+            _                           => None
         }
     }
 
-    pub fn from_int(i: i64) -> LexerTokenValue {
-        LexerTokenValue {
-            integer: i,
-            string: "".to_string()
+    pub fn as_word(&self) -> Option<Symbol> {
+        match self {
+            LexerTokenValue::Word(s) => Some(*s),
+            _                        => None
         }
     }
 }
@@ -42,62 +120,175 @@ impl LexerTokenValue {
 #[derive(Debug)]
 pub struct LexerToken {
     pub kind: LexerTokenKind,
-    pub value: LexerTokenValue
+    pub value: LexerTokenValue,
+    pub span: LexerSpan
+}
+
+// where the lexer pulls its chars from, either any `char` iterator or a
+// borrowed `&str`
+pub trait LexerInput {
+    fn next_char_if(&mut self, func: impl FnOnce(&char) -> bool) -> Option<char>;
+    fn offset(&self) -> usize;
+
+    // consumes the rest of a word that starts with `first` at `start` and
+    // hands its whole text to `func`
+    fn next_word<R>(&mut self, start: usize, first: char, func: impl FnOnce(&str) -> R) -> R;
+}
+
+// words and numbers are handed to `func` as slices of the source, so no
+// token builds a `String`. tokens only keep their span into the source, a
+// word is copied once when `Symbol::intern` first sees it and never again
+pub struct StrInput<'a> {
+    pub source: &'a str,
+    pub offset: usize
+}
+
+impl LexerInput for StrInput<'_> {
+    fn next_char_if(&mut self, func: impl FnOnce(&char) -> bool) -> Option<char> {
+        let x = self.source[self.offset..].chars().next().filter(func)?;
+        self.offset += x.len_utf8();
+        Some(x)
+    }
+
+    fn offset(&self) -> usize {
+        self.offset
+    }
+
+    fn next_word<R>(&mut self, start: usize, _first: char, func: impl FnOnce(&str) -> R) -> R {
+        while self.next_char_if(|x| x.is_alphanumeric() || *x == '_').is_some() {}
+        func(&self.source[start..self.offset])
+    }
+}
+
+pub struct Lexer<Input: LexerInput> {
+    pub input: Input
 }
 
-pub struct Lexer<Chars: Iterator<Item=char>> {
-    pub chars: Peekable<Chars>
+impl<'a> Lexer<StrInput<'a>> {
+    pub fn from_source(source: &'a str) -> Self {
+        Self { input: StrInput { source, offset: 0 } }
+    }
 }
 
-impl<Chars: Iterator<Item=char>> Lexer<Chars> {
-    pub fn from_chars(chars: Chars) -> Self {
-        Self { chars: chars.peekable() }
+impl<Input: LexerInput> Lexer<Input> {
+    // consumes `expected` if it is the next char, this is what makes `==` a
+    // single token instead of two `=`
+    fn next_char_is(&mut self, expected: char) -> bool {
+        self.input.next_char_if(|x| *x == expected).is_some()
     }
 }
 
-impl<Chars: Iterator<Item=char>> Iterator for Lexer<Chars> {
+impl<Input: LexerInput> Iterator for Lexer<Input> {
     type Item = LexerToken;
     fn next(&mut self) -> Option<LexerToken> {
         use LexerTokenKind::*;
-        while let Some(_) = self.chars.next_if(|x| x.is_whitespace()) {}
-
-        if let Some(x) = self.chars.next() {
-            let mut text = "".to_string();
-            text.push(x);
-            match x {
-                '(' => Some(LexerToken {kind: OpenParen, value: LexerTokenValue::from_string(text)}),
-                ')' => Some(LexerToken {kind: CloseParen, value: LexerTokenValue::from_string(text)}),
-                '{' => Some(LexerToken {kind: OpenCurly, value: LexerTokenValue::from_string(text)}),
-                '}' => Some(LexerToken {kind: CloseCurly, value: LexerTokenValue::from_string(text)}),
-                '+' => Some(LexerToken {kind: Plus, value: LexerTokenValue::from_string(text)}),
-                '-' => Some(LexerToken {kind: Minus, value: LexerTokenValue::from_string(text)}),
-                '*' => Some(LexerToken {kind: Multiplication, value: LexerTokenValue::from_string(text)}),
-                '/' => Some(LexerToken {kind: Division, value: LexerTokenValue::from_string(text)}),
-                '!' => Some(LexerToken {kind: ExclamationMark, value: LexerTokenValue::from_string(text)}),
-                ',' => Some(LexerToken {kind: Comma, value: LexerTokenValue::from_string(text)}),
-                '%' => Some(LexerToken {kind: Mod, value: LexerTokenValue::from_string(text)}),
-                _   => {
-                    while let Some(x) = self.chars.next_if(|x| x.is_alphanumeric()) {
-                        text.push(x);
-                    }
-
-                    let parsed = text.parse::<i64>();
-                    if let Err(_) = parsed {
-                        Some(LexerToken {kind: Word, value: LexerTokenValue::from_string(text)})
-                    } else {
-                        Some(LexerToken {kind: Integer, value: LexerTokenValue::from_int(parsed.unwrap())})
-                    }
-                }
+        while self.input.next_char_if(|x| x.is_whitespace()).is_some() {}
+
+        let start = self.input.offset();
+        let x = self.input.next_char_if(|_| true)?;
+
+        let kind = match x {
+            '(' => OpenParen,
+            ')' => CloseParen,
+            '{' => OpenCurly,
+            '}' => CloseCurly,
+            '[' => OpenBracket,
+            ']' => CloseBracket,
+            '/' => Division,
+            ',' => Comma,
+            '.' => Dot,
+            ':' => Colon,
+            ';' => Semicolon,
+            '#' => Hash,
+            '%' => Mod,
+            '+' => if self.next_char_is('=') { PlusEqual } else { Plus },
+            '-' => if self.next_char_is('>') { Arrow } else { Minus },
+            '*' => if self.next_char_is('*') { Power } else { Multiplication },
+            '!' => if self.next_char_is('=') { NotEqual } else { ExclamationMark },
+            '=' => if self.next_char_is('=') { EqualEqual } else { Equal },
+            '<' => if self.next_char_is('=') { LessEqual } else { Less },
+            '>' => if self.next_char_is('=') { GreaterEqual } else { Greater },
+            '&' => if self.next_char_is('&') { AndAnd } else { Ampersand },
+            '|' => if self.next_char_is('|') { OrOr } else { Pipe },
+            _   => {
+                // an integer too large for `u64` is still an integer, it is
+                // left without a value for the parser to report
+                let (kind, value) = self.input.next_word(start, x, |text| match text.parse::<u64>() {
+                    Ok(integer)                                        => (Integer, LexerTokenValue::Integer(integer)),
+                    Err(_) if text.bytes().all(|x| x.is_ascii_digit()) => (Integer, LexerTokenValue::None),
+                    Err(_)                                             => (Word, LexerTokenValue::Word(Symbol::intern(text)))
+                });
+
+                let span = LexerSpan { start, end: self.input.offset() };
+                return Some(LexerToken {kind, value, span})
             }
-        } else {
-            None
-        }
+        };
+
+        let span = LexerSpan { start, end: self.input.offset() };
+        Some(LexerToken {kind, value: LexerTokenValue::None, span})
     }
 }
 
-#[macro_export]
-macro_rules! lexer_type {
-    () => {
-        Peekable<impl Iterator<Item=LexerToken>>
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use LexerTokenKind::*;
+
+    fn kinds(source: &str) -> Vec<LexerTokenKind> {
+        Lexer::from_source(source).map(|x| x.kind).collect()
+    }
+
+    // the longest operator wins, so `==` is never two `=`
+    #[test]
+    fn operators_are_lexed_with_maximal_munch() {
+        assert_eq!(kinds("== -> ** += != <= >= && ||"), vec![EqualEqual, Arrow, Power, PlusEqual, NotEqual, LessEqual, GreaterEqual, AndAnd, OrOr]);
+        assert_eq!(kinds("= - * + ! < > & |"), vec![Equal, Minus, Multiplication, Plus, ExclamationMark, Less, Greater, Ampersand, Pipe]);
+        assert_eq!(kinds("==="), vec![EqualEqual, Equal]);
+        assert_eq!(kinds("***"), vec![Power, Multiplication]);
+        assert_eq!(kinds("a->b"), vec![Word, Arrow, Word]);
+        assert_eq!(kinds("1+=2"), vec![Integer, PlusEqual, Integer]);
+    }
+
+    #[test]
+    fn tokens_record_their_spans() {
+        let spans: Vec<(usize, usize)> = Lexer::from_source("foo  == 35\n\t**x").map(|x| (x.span.start, x.span.end)).collect();
+        assert_eq!(spans, vec![(0, 3), (5, 7), (8, 10), (12, 14), (14, 15)]);
+
+        // offsets are in bytes, not chars
+        let spans: Vec<(usize, usize)> = Lexer::from_source("é + ü").map(|x| (x.span.start, x.span.end)).collect();
+        assert_eq!(spans, vec![(0, 2), (3, 4), (5, 7)]);
+    }
+
+    // a char that is not an operator starts a word and never swallows the
+    // operator after it
+    #[test]
+    fn unknown_characters_are_lexed_as_words() {
+        assert_eq!(kinds("@==$"), vec![Word, EqualEqual, Word]);
+        let tokens: Vec<LexerToken> = Lexer::from_source("@==$").collect();
+        assert_eq!(tokens[0].value, LexerTokenValue::Word(Symbol::intern("@")));
+        assert_eq!(tokens[2].value, LexerTokenValue::Word(Symbol::intern("$")));
+        assert_eq!((tokens[2].span.start, tokens[2].span.end), (3, 4));
+    }
+
+    #[test]
+    fn integers_too_large_have_no_value() {
+        let tokens: Vec<LexerToken> = Lexer::from_source("18446744073709551615 18446744073709551616 1x").collect();
+        assert_eq!(tokens[0].value, LexerTokenValue::Integer(u64::MAX));
+        assert_eq!((tokens[1].kind, tokens[1].value), (Integer, LexerTokenValue::None));
+        assert_eq!(tokens[2].kind, Word);
+    }
+
+    // only integers and words carry a payload
+    #[test]
+    fn tokens_carry_a_typed_value() {
+        let values: Vec<LexerTokenValue> = Lexer::from_source("foo(35 + bar2)").map(|x| x.value).collect();
+        assert_eq!(values, vec![
+            LexerTokenValue::Word(Symbol::intern("foo")),
+            LexerTokenValue::None,
+            LexerTokenValue::Integer(35),
+            LexerTokenValue::None,
+            LexerTokenValue::Word(Symbol::intern("bar2")),
+            LexerTokenValue::None
+        ]);
     }
 }
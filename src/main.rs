@@ -1,14 +1,343 @@
 mod ir;
 mod ast;
+mod bytecode;
+mod cfg;
+#[cfg(test)]
+mod chars_input;
+mod deadcode;
+mod diagnostic;
+mod fold;
+mod inline;
+mod layout;
 mod lexer;
+mod parser;
+mod peephole;
+mod regalloc;
+mod sim;
+mod ssa;
+mod symbol;
+mod typecheck;
+mod types;
 
-use lexer::*;
 use ast::*;
+use cfg::*;
+use deadcode::*;
+use diagnostic::*;
+use fold::*;
+use ir::*;
+use lexer::*;
+use parser::*;
+use peephole::*;
+use sim::*;
+use ssa::*;
+use typecheck::*;
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::exit;
+use std::process::Command;
+
+fn usage(program: &str) {
+    eprintln!("Usage: {} <SUBCOMMAND> [OPTIONS] <FILE> [ARGUMENTS...]", program);
+    eprintln!("SUBCOMMANDS:");
+    eprintln!("    com     Compile the program into a native executable");
+    eprintln!("    run     Compile the program and run it, bytecode is simulated");
+    eprintln!("    sim     Simulate the program without compiling it");
+    eprintln!("    dump    Print the type checked program");
+    eprintln!("FILE is a dang program, a textual IR program ending in `.ir` or bytecode ending in `.dangc`");
+    eprintln!("OPTIONS:");
+    eprintln!("    -o <PATH>             Where to put the executable, defaults to FILE without its extension,");
+    eprintln!("                          `com` writes bytecode instead when PATH ends in `.dangc`");
+    eprintln!("    --no-bounds-checks    Do not check array indices at runtime");
+    eprintln!("    -O<LEVEL>             Optimize, 0 does nothing, 1 the IR, 2 also the assembly and 3 compiles");
+    eprintln!("                          through the SSA IR, defaults to 0");
+    eprintln!("    --ir                  Make `dump` print the IR instead of the program");
+    eprintln!("    --ssa                 Make `dump` print the SSA IR instead of the program");
+    eprintln!("    --cfg=dot             Make `dump` print the control flow graph of the IR for Graphviz");
+    eprintln!("    -A<LINT>              Allow LINT, reporting nothing");
+    eprintln!("    -W<LINT>              Warn about LINT, the default");
+    eprintln!("    -D<LINT>              Deny LINT, reporting it as an error");
+    eprintln!("LINTS:");
+    eprintln!("    unreachable-code      Statements after `return`, `exit!` or an `if` whose branches both leave");
+    eprintln!("ARGUMENTS are passed to the program by `run` and `sim`");
+}
+
+fn read_source(path: &str) -> String {
+    match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(error) => {
+            eprintln!("error: could not read `{}`: {}", path, error);
+            exit(1)
+        }
+    }
+}
+
+// parses, type checks and folds the constants of `path`, reporting every
+// diagnostic and exiting if there are any errors
+fn load(path: &str, lints: &LintLevels) -> DangAst {
+    let source = read_source(path);
+
+    let mut ast = match Parser::new(Lexer::from_source(&source)).parse_ast() {
+        Ok(ast) => ast,
+        Err(diagnostic) => {
+            eprintln!("{}", diagnostic.display(path, &source));
+            exit(1)
+        }
+    };
+
+    if let Err(diagnostics) = TypeChecker::check_ast(&mut ast).and_then(|_| ConstantFolder::fold_ast(&mut ast)) {
+        for diagnostic in diagnostics {
+            eprintln!("{}", diagnostic.display(path, &source));
+        }
+        exit(1)
+    }
+
+    let diagnostics = lints.apply(ReachabilityChecker::check_ast(&ast));
+    for diagnostic in &diagnostics {
+        eprintln!("{}", diagnostic.display(path, &source));
+    }
+    if diagnostics.iter().any(|x| x.level == DiagnosticLevel::Error) {
+        exit(1)
+    }
+
+    ast
+}
+
+// the `Ir` of `source`, which has to parse, type check and fold
+#[cfg(test)]
+pub fn compile(source: &str) -> ir::Ir {
+    let mut ast = Parser::new(Lexer::from_source(source)).parse_ast().unwrap();
+    if let Err(diagnostics) = TypeChecker::check_ast(&mut ast).and_then(|_| ConstantFolder::fold_ast(&mut ast)) {
+        panic!("{}", diagnostics[0].display("<test>", source));
+    }
+    ast.parse_into_operantions(true)
+}
+
+// compiles `path` into IR, files ending in `.ir` are already IR and are only
+// parsed, which makes it possible to hand write programs for the backend,
+// and files ending in `.dangc` are compiled bytecode
+fn load_ir(path: &str, bounds_checks: bool, optimization_level: u8, lints: &LintLevels) -> Ir {
+    let mut ir = if path.ends_with(".dangc") {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(error) => {
+                eprintln!("error: could not read `{}`: {}", path, error);
+                exit(1)
+            }
+        };
+        match Ir::from_bytecode(&bytes) {
+            Ok(ir) => ir,
+            Err(message) => {
+                eprintln!("error: could not load `{}`: {}", path, message);
+                exit(1)
+            }
+        }
+    } else if path.ends_with(".ir") {
+        let source = read_source(path);
+        match Ir::parse(&source) {
+            Ok(ir) => ir,
+            Err(diagnostic) => {
+                eprintln!("{}", diagnostic.display(path, &source));
+                exit(1)
+            }
+        }
+    } else {
+        load(path, lints).parse_into_operantions(bounds_checks)
+    };
 
-//use std::io::*;
-//use std::process::Command;
+    // unlike what the compiler generates loaded IR can be anything
+    if path.ends_with(".dangc") || path.ends_with(".ir") {
+        if let Err(error) = ir.verify() {
+            eprintln!("error: `{}` is not valid IR: {}", path, error);
+            exit(1)
+        }
+    }
+
+    if optimization_level >= 1 {
+        ir.eliminate_dead_code();
+        ir.inline_functions();
+        ir.eliminate_dead_code();
+        ir.optimize_peephole();
+    }
+    ir
+}
+
+fn lower_ssa(path: &str, ir: &Ir, optimize: bool) -> Ssa {
+    let mut ssa = match Ssa::from_ir(ir) {
+        Ok(ssa) => ssa,
+        Err(message) => {
+            eprintln!("error: could not compile `{}` through SSA: {}", path, message);
+            exit(1)
+        }
+    };
+    if optimize {
+        ssa.optimize();
+    }
+    ssa
+}
+
+fn simulate(ir: &Ir, input: String, arguments: Vec<String>) -> ! {
+    // the program gets the path of its source in place of the path of an
+    // executable
+    let arguments: Vec<String> = std::iter::once(input).chain(arguments).collect();
+    let environment: Vec<String> = env::vars_os()
+        .map(|(key, value)| format!("{}={}", key.to_string_lossy(), value.to_string_lossy()))
+        .collect();
+    let status = Simulator::new(ir, &arguments, &environment).run(&mut std::io::stdout().lock());
+    exit(status)
+}
+
+fn run_command(command: &mut Command) {
+    match command.status() {
+        Ok(status) if status.success() => {}
+        Ok(status) => {
+            eprintln!("error: {:?} failed with {}", command, status);
+            exit(1)
+        }
+        Err(error) => {
+            eprintln!("error: could not run {:?}: {}", command, error);
+            exit(1)
+        }
+    }
+}
 
 fn main() {
-    let ast = DangAst::from_tokens(Lexer::from_chars("print!(34 + 35, 10)".chars()).peekable());
-    println!("{}", ast)
+    let mut args = env::args();
+    let program = args.next().unwrap_or("dang".to_string());
+
+    let Some(subcommand) = args.next() else {
+        usage(&program);
+        eprintln!("error: no subcommand is provided");
+        exit(1)
+    };
+
+    let mut input = None;
+    let mut output = None;
+    let mut bounds_checks = true;
+    let mut dump_ir = false;
+    let mut dump_ssa = false;
+    let mut dump_cfg = false;
+    let mut optimization_level = 0;
+    let mut lints = LintLevels::default();
+    // everything after the input file belongs to the program
+    while input.is_none() {
+        let Some(arg) = args.next() else {
+            break
+        };
+        match arg.as_str() {
+            "-o" => match args.next() {
+                Some(path) => output = Some(path),
+                None => {
+                    usage(&program);
+                    eprintln!("error: no path is provided for `-o`");
+                    exit(1)
+                }
+            }
+            "--no-bounds-checks" => bounds_checks = false,
+            "--ir" => dump_ir = true,
+            "--ssa" => dump_ssa = true,
+            "--cfg=dot" => dump_cfg = true,
+            _ if arg.starts_with("--cfg=") => {
+                usage(&program);
+                eprintln!("error: unknown control flow graph format `{}`", &arg[6..]);
+                exit(1)
+            }
+            _ if arg.starts_with("-O") => match arg[2..].parse() {
+                Ok(level) if level <= MAX_OPTIMIZATION_LEVEL => optimization_level = level,
+                _ => {
+                    usage(&program);
+                    eprintln!("error: unknown optimization level `{}`", &arg[2..]);
+                    exit(1)
+                }
+            }
+            _ if arg.starts_with("-A") || arg.starts_with("-W") || arg.starts_with("-D") => {
+                let level = match &arg[..2] {
+                    "-A" => LintLevel::Allow,
+                    "-W" => LintLevel::Warn,
+                    _    => LintLevel::Deny
+                };
+                match Lint::from_name(&arg[2..]) {
+                    Some(lint) => lints.set(lint, level),
+                    None => {
+                        usage(&program);
+                        eprintln!("error: unknown lint `{}`", &arg[2..]);
+                        exit(1)
+                    }
+                }
+            }
+            _ => input = Some(arg)
+        }
+    }
+    let arguments: Vec<String> = args.collect();
+
+    let Some(input) = input else {
+        usage(&program);
+        eprintln!("error: no input file is provided");
+        exit(1)
+    };
+
+    if !arguments.is_empty() && subcommand != "run" && subcommand != "sim" {
+        usage(&program);
+        eprintln!("error: unexpected argument `{}`", arguments[0]);
+        exit(1)
+    }
+
+    match subcommand.as_str() {
+        // bytecode does not need an assembler to run
+        "run" if input.ends_with(".dangc") => simulate(&load_ir(&input, bounds_checks, optimization_level, &lints), input, arguments),
+        "com" if output.as_ref().is_some_and(|x| x.ends_with(".dangc")) => {
+            let output = output.unwrap();
+            if let Err(error) = fs::write(&output, load_ir(&input, bounds_checks, optimization_level, &lints).to_bytecode()) {
+                eprintln!("error: could not write `{}`: {}", output, error);
+                exit(1)
+            }
+        }
+        "com" | "run" => {
+            let ir = load_ir(&input, bounds_checks, optimization_level, &lints);
+
+            let output = output.unwrap_or_else(|| Path::new(&input).with_extension("").to_string_lossy().into_owned());
+            let assembly = format!("{}.asm", output);
+            let object = format!("{}.o", output);
+            let written = if optimization_level >= 3 {
+                lower_ssa(&input, &ir, true).to_nasm_linux_x86_64_assembly(assembly.clone())
+            } else {
+                ir.to_nasm_linux_x86_64_assembly(assembly.clone(), optimization_level >= 2)
+            };
+            if let Err(error) = written {
+                eprintln!("error: could not write `{}`: {}", assembly, error);
+                exit(1)
+            }
+            run_command(Command::new("nasm").args(["-felf64", &assembly, "-o", &object]));
+            run_command(Command::new("ld").args([&object, "-o", &output]));
+
+            if subcommand == "run" {
+                // a bare name would be looked up in `PATH`
+                let executable = if output.contains('/') { output.clone() } else { format!("./{}", output) };
+                match Command::new(&executable).args(&arguments).status() {
+                    Ok(status) => exit(status.code().unwrap_or(1)),
+                    Err(error) => {
+                        eprintln!("error: could not run `{}`: {}", output, error);
+                        exit(1)
+                    }
+                }
+            }
+        }
+        "sim" => simulate(&load_ir(&input, bounds_checks, optimization_level, &lints), input, arguments),
+        "dump" if dump_cfg => {
+            let ir = load_ir(&input, bounds_checks, optimization_level, &lints);
+            print!("{}", CfgDot { ir: &ir, cfgs: &Cfg::from_ir(&ir) })
+        }
+        "dump" if dump_ssa => {
+            let ir = load_ir(&input, bounds_checks, optimization_level, &lints);
+            print!("{}", lower_ssa(&input, &ir, optimization_level >= 3))
+        }
+        "dump" if dump_ir || input.ends_with(".ir") || input.ends_with(".dangc") => print!("{}", load_ir(&input, bounds_checks, optimization_level, &lints)),
+        "dump" => println!("{}", load(&input, &lints)),
+        _ => {
+            usage(&program);
+            eprintln!("error: unknown subcommand `{}`", subcommand);
+            exit(1)
+        }
+    }
 }
@@ -0,0 +1,651 @@
+use crate::ast::*;
+use crate::diagnostic::*;
+use crate::ir::*;
+use crate::lexer::*;
+use crate::lexer::LexerTokenKind::*;
+use crate::symbol::*;
+use crate::types::*;
+
+use std::collections::VecDeque;
+
+pub type ParseResult<T> = std::result::Result<T, Diagnostic>;
+
+/// Owns the token stream and turns it into a `DangAst`, every parse
+/// function leaves the stream right after what it parsed so the caller can
+/// keep going.
+pub struct Parser<Tokens: Iterator<Item=LexerToken>> {
+    tokens: Tokens,
+    lookahead: VecDeque<LexerToken>,
+    pub position: usize,
+    last_span: LexerSpan,
+    // `if x {}` would otherwise start a struct literal
+    no_struct_literals: bool
+}
+
+// precedence and associativity of every binary operator, higher binds
+// tighter
+fn binary_operation(kind: LexerTokenKind) -> Option<(DangBinaryOperationType, u8, bool)> {
+    match kind {
+        OrOr           => Some((DangBinaryOperationType::Or, 1, false)),
+        AndAnd         => Some((DangBinaryOperationType::And, 2, false)),
+        EqualEqual     => Some((DangBinaryOperationType::Equal, 3, false)),
+        NotEqual       => Some((DangBinaryOperationType::NotEqual, 3, false)),
+        Less           => Some((DangBinaryOperationType::Less, 3, false)),
+        LessEqual      => Some((DangBinaryOperationType::LessEqual, 3, false)),
+        Greater        => Some((DangBinaryOperationType::Greater, 3, false)),
+        GreaterEqual   => Some((DangBinaryOperationType::GreaterEqual, 3, false)),
+        Plus           => Some((DangBinaryOperationType::Plus, 4, false)),
+        Minus          => Some((DangBinaryOperationType::Minus, 4, false)),
+        Multiplication => Some((DangBinaryOperationType::Multiplication, 5, false)),
+        Division       => Some((DangBinaryOperationType::Division, 5, false)),
+        Mod            => Some((DangBinaryOperationType::Mod, 5, false)),
+        Power          => Some((DangBinaryOperationType::Power, 6, true)),
+        _              => None
+    }
+}
+
+impl<Tokens: Iterator<Item=LexerToken>> Parser<Tokens> {
+    pub fn new(tokens: Tokens) -> Self {
+        Self {
+            tokens,
+            lookahead: VecDeque::new(),
+            position: 0,
+            last_span: LexerSpan { start: 0, end: 0 },
+            no_struct_literals: false
+        }
+    }
+
+    pub fn peek(&mut self) -> Option<&LexerToken> {
+        self.peek_nth(0)
+    }
+
+    pub fn peek_nth(&mut self, n: usize) -> Option<&LexerToken> {
+        while self.lookahead.len() <= n {
+            self.lookahead.push_back(self.tokens.next()?);
+        }
+        self.lookahead.get(n)
+    }
+
+    pub fn next(&mut self) -> Option<LexerToken> {
+        let token = match self.lookahead.pop_front() {
+            Some(token) => token,
+            None        => self.tokens.next()?
+        };
+        self.position += 1;
+        self.last_span = token.span;
+        Some(token)
+    }
+
+    pub fn at(&mut self, kind: LexerTokenKind) -> bool {
+        self.peek().is_some_and(|x| x.kind == kind)
+    }
+
+    pub fn eat(&mut self, kind: LexerTokenKind) -> Option<LexerToken> {
+        if self.at(kind) {
+            self.next()
+        } else {
+            None
+        }
+    }
+
+    pub fn expect(&mut self, kind: LexerTokenKind) -> ParseResult<LexerToken> {
+        match self.eat(kind) {
+            Some(token) => Ok(token),
+            None        => Err(self.unexpected(&kind.to_string()))
+        }
+    }
+
+    // the value of the next token, which has to be an integer that fits in
+    // 64 bits
+    fn expect_integer(&mut self) -> ParseResult<u64> {
+        let token = self.expect(Integer)?;
+        token.value.as_integer().ok_or_else(|| Diagnostic::new(token.span, "integer literal is too large".to_string()))
+    }
+
+    // span of the next token, or the empty span right after the last one
+    // when the stream is over
+    pub fn span(&mut self) -> LexerSpan {
+        let end = self.last_span.end;
+        self.peek().map(|x| x.span).unwrap_or(LexerSpan { start: end, end })
+    }
+
+    fn unexpected(&mut self, expected: &str) -> Diagnostic {
+        let span = self.span();
+        match self.peek() {
+            Some(token) => Diagnostic::new(span, format!("expected {}, found {}", expected, token.kind)),
+            None        => Diagnostic::new(span, format!("expected {}, reached end of file", expected))
+        }
+    }
+
+    // statements are separated by `;`, which is optional after the last one
+    // and after a statement that ends with a block, returns whether another
+    // statement may follow
+    fn eat_separator(&mut self, statement: &DangStatement) -> bool {
+        let ends_with_block = matches!(statement.kind, DangStatementKind::If(_) | DangStatementKind::Block(_));
+        self.eat(Semicolon).is_some() || ends_with_block
+    }
+
+    fn parse_statements(&mut self, end: LexerTokenKind) -> ParseResult<Vec<DangStatement>> {
+        let mut statements = vec![];
+        while self.peek().is_some() && !self.at(end) {
+            let statement = self.parse_statement()?;
+            let more = self.eat_separator(&statement);
+            statements.push(statement);
+
+            if !more {
+                break;
+            }
+        }
+        Ok(statements)
+    }
+
+    // functions and structs can only be declared at the top level, between
+    // the statements
+    pub fn parse_ast(&mut self) -> ParseResult<DangAst> {
+        let mut ast = DangAst::new();
+
+        loop {
+            if self.at_keyword(Symbol::FN) || self.at(Hash) {
+                ast.functions.push(self.parse_function()?);
+                self.eat(Semicolon);
+                continue;
+            }
+            if self.at_keyword(Symbol::STRUCT) {
+                ast.structs.push(self.parse_struct()?);
+                self.eat(Semicolon);
+                continue;
+            }
+            if self.peek().is_none() {
+                break;
+            }
+
+            let statement = self.parse_statement()?;
+            let more = self.eat_separator(&statement);
+            ast.ast.push(statement);
+
+            if !more && !self.at_keyword(Symbol::FN) && !self.at(Hash) && !self.at_keyword(Symbol::STRUCT) {
+                break;
+            }
+        }
+
+        if self.peek().is_some() {
+            return Err(self.unexpected(&Semicolon.to_string()));
+        }
+
+        Ok(ast)
+    }
+
+    pub fn parse_statement(&mut self) -> ParseResult<DangStatement> {
+        let start = self.span();
+
+        if self.eat_keyword(Symbol::LET).is_some() {
+            let name = self.expect(Word)?.value.as_word().unwrap();
+            let annotation = if self.eat(Colon).is_some() { Some(self.parse_type()?) } else { None };
+            self.expect(Equal)?;
+            let value = self.parse_expression()?;
+
+            let span = start.to(value.span);
+            return Ok(DangStatement::new(DangStatementKind::Let(DangLet {
+                variable: DangVariable::new(name),
+                annotation,
+                value: Box::new(value)
+            }), span));
+        }
+
+        if self.eat_keyword(Symbol::RETURN).is_some() {
+            let value = if self.at(Semicolon) || self.at(CloseCurly) || self.peek().is_none() {
+                None
+            } else {
+                Some(Box::new(self.parse_expression()?))
+            };
+
+            return Ok(DangStatement::new(DangStatementKind::Return(DangReturn { value }), start.to(self.last_span)));
+        }
+
+        if self.at_keyword(Symbol::FN) {
+            return Err(Diagnostic::new(start, "functions can only be declared at the top level".to_string()));
+        }
+        if self.at_keyword(Symbol::STRUCT) {
+            return Err(Diagnostic::new(start, "structs can only be declared at the top level".to_string()));
+        }
+
+        // `target += value` keeps the operation apart so the place is only
+        // evaluated once
+        let target = self.parse_expression()?;
+        let operation = match self.peek().map(|x| x.kind) {
+            Some(Equal)     => None,
+            Some(PlusEqual) => Some(DangBinaryOperationType::Plus),
+            _               => return Ok(target)
+        };
+        self.next();
+        let value = self.parse_expression()?;
+        let span = target.span.to(value.span);
+        Ok(DangStatement::new(DangStatementKind::Assignment(DangAssignment {
+            target: Box::new(target),
+            value: Box::new(value),
+            operation,
+            offset: 0
+        }), span))
+    }
+
+    pub fn parse_function(&mut self) -> ParseResult<DangFunction> {
+        let start = self.span();
+
+        // `#[inline]` and `#[noinline]` are the only attributes
+        let mut inline = IrInline::WhenSmall;
+        while self.eat(Hash).is_some() {
+            self.expect(OpenBracket)?;
+            let token = self.expect(Word)?;
+            let attribute = match token.value.as_word().unwrap() {
+                Symbol::INLINE   => IrInline::Always,
+                Symbol::NOINLINE => IrInline::Never,
+                name             => return Err(Diagnostic::new(token.span, format!("unknown attribute `{}`", name)))
+            };
+            if inline != IrInline::WhenSmall {
+                return Err(Diagnostic::new(token.span, "a function can only have one inlining attribute".to_string()));
+            }
+            inline = attribute;
+            self.expect(CloseBracket)?;
+        }
+
+        if self.eat_keyword(Symbol::FN).is_none() {
+            return Err(self.unexpected("`fn`"));
+        }
+        let name = self.expect(Word)?.value.as_word().unwrap();
+
+        let mut parameters = vec![];
+        self.expect(OpenParen)?;
+        while !self.at(CloseParen) {
+            let token = self.expect(Word)?;
+            let annotation = if self.eat(Colon).is_some() { Some(self.parse_type()?) } else { None };
+            parameters.push(DangParameter {
+                variable: DangVariable::new(token.value.as_word().unwrap()),
+                annotation,
+                span: token.span.to(self.last_span),
+                argument_offset: 0,
+                ty: DangType::Unknown
+            });
+
+            if self.eat(Comma).is_none() {
+                break;
+            }
+        }
+        self.expect(CloseParen)?;
+
+        let return_type = if self.eat(Arrow).is_some() { Some(self.parse_type()?) } else { None };
+        let span = start.to(self.last_span);
+        let body = self.parse_block()?;
+
+        Ok(DangFunction { name, parameters, return_type, body, span, inline, frame_size: 0 })
+    }
+
+    pub fn parse_struct(&mut self) -> ParseResult<DangStructDeclaration> {
+        let start = self.span();
+        if self.eat_keyword(Symbol::STRUCT).is_none() {
+            return Err(self.unexpected("`struct`"));
+        }
+        let name = self.expect(Word)?.value.as_word().unwrap();
+        let span = start.to(self.last_span);
+
+        let mut fields = vec![];
+        self.expect(OpenCurly)?;
+        while !self.at(CloseCurly) {
+            let token = self.expect(Word)?;
+            self.expect(Colon)?;
+            let annotation = self.parse_type()?;
+            fields.push(DangStructDeclarationField {
+                name: token.value.as_word().unwrap(),
+                annotation,
+                span: token.span.to(self.last_span)
+            });
+
+            if self.eat(Comma).is_none() {
+                break;
+            }
+        }
+        self.expect(CloseCurly)?;
+
+        Ok(DangStructDeclaration { name, fields, span })
+    }
+
+    pub fn parse_expression(&mut self) -> ParseResult<DangStatement> {
+        self.parse_operation(0)
+    }
+
+    fn parse_operation(&mut self, min_precedence: u8) -> ParseResult<DangStatement> {
+        let mut first_operand = self.parse_cast()?;
+
+        while let Some((binary_operation_type, precedence, right_associative)) =
+            self.peek().and_then(|x| binary_operation(x.kind)) {
+            if precedence < min_precedence {
+                break;
+            }
+            self.next();
+
+            let next_precedence = if right_associative { precedence } else { precedence + 1 };
+            let second_operand = self.parse_operation(next_precedence)?;
+
+            let span = first_operand.span.to(second_operand.span);
+            first_operand = DangStatement::new(DangStatementKind::Operation(DangOperation {
+                binary_operation_type,
+                first_operand: Box::new(first_operand),
+                second_operand: Box::new(second_operand)
+            }), span);
+        }
+
+        Ok(first_operand)
+    }
+
+    // `as` binds tighter than every binary operator but looser than the
+    // unary ones, so `-x as u8` casts `-x`
+    fn parse_cast(&mut self) -> ParseResult<DangStatement> {
+        let mut operand = self.parse_unary_operation()?;
+
+        while self.eat_keyword(Symbol::AS).is_some() {
+            let target = self.parse_type()?;
+            let span = operand.span.to(self.last_span);
+            operand = DangStatement::new(DangStatementKind::Cast(DangCast {
+                operand: Box::new(operand),
+                target
+            }), span);
+        }
+
+        Ok(operand)
+    }
+
+    pub fn parse_type(&mut self) -> ParseResult<DangType> {
+        if self.eat(OpenBracket).is_some() {
+            let element = self.parse_type()?;
+            self.expect(Semicolon)?;
+            let length = self.expect_integer()?;
+            self.expect(CloseBracket)?;
+            return Ok(DangType::array(element, length));
+        }
+        if self.eat(Ampersand).is_some() {
+            return Ok(DangType::pointer(self.parse_type()?));
+        }
+
+        // names that are not built in are structs, the `TypeChecker` makes
+        // sure they are declared
+        match self.peek().and_then(|x| x.value.as_word()) {
+            Some(name) => {
+                self.next();
+                Ok(DangType::from_name(name.as_str()).unwrap_or(DangType::Struct(name)))
+            }
+            None => Err(self.unexpected("type"))
+        }
+    }
+
+    fn parse_unary_operation(&mut self) -> ParseResult<DangStatement> {
+        if let Some(start) = self.eat(Ampersand) {
+            let operand = self.parse_unary_operation()?;
+            let span = start.span.to(operand.span);
+            return Ok(DangStatement::new(DangStatementKind::AddressOf(DangAddressOf { operand: Box::new(operand) }), span));
+        }
+
+        // `**p` is lexed as a single `**`
+        let dereferences = match self.peek().map(|x| x.kind) {
+            Some(Multiplication) => 1,
+            Some(Power)          => 2,
+            _                    => 0
+        };
+        if dereferences > 0 {
+            let start = self.next().unwrap().span;
+            let mut operand = self.parse_unary_operation()?;
+            for _ in 0..dereferences {
+                let span = start.to(operand.span);
+                operand = DangStatement::new(DangStatementKind::Dereference(DangDereference { operand: Box::new(operand) }), span);
+            }
+            return Ok(operand);
+        }
+
+        let unary_operation_type = match self.peek().map(|x| x.kind) {
+            Some(Minus)           => DangUnaryOperationType::Negate,
+            Some(ExclamationMark) => DangUnaryOperationType::Not,
+            _                     => return self.parse_postfix()
+        };
+        let start = self.next().unwrap().span;
+        let operand = self.parse_unary_operation()?;
+
+        let span = start.to(operand.span);
+        Ok(DangStatement::new(DangStatementKind::UnaryOperation(DangUnaryOperation {
+            unary_operation_type,
+            operand: Box::new(operand)
+        }), span))
+    }
+
+    // indexing and field accesses bind tighter than the unary operators, so
+    // `-a[0]` negates `a[0]`
+    fn parse_postfix(&mut self) -> ParseResult<DangStatement> {
+        let mut base = self.parse_primary()?;
+
+        // `{} [1]` is a block followed by an array, like after an `if`
+        if matches!(base.kind, DangStatementKind::Block(_) | DangStatementKind::If(_)) {
+            return Ok(base);
+        }
+
+        loop {
+            if self.eat(OpenBracket).is_some() {
+                let index = self.parse_expression()?;
+                self.expect(CloseBracket)?;
+                let span = base.span.to(self.last_span);
+                base = DangStatement::new(DangStatementKind::Index(DangIndex {
+                    base: Box::new(base),
+                    index: Box::new(index)
+                }), span);
+            } else if self.eat(Dot).is_some() {
+                let field = self.expect(Word)?.value.as_word().unwrap();
+                let span = base.span.to(self.last_span);
+                base = DangStatement::new(DangStatementKind::FieldAccess(DangFieldAccess {
+                    base: Box::new(base),
+                    field,
+                    offset: 0
+                }), span);
+            } else {
+                break;
+            }
+        }
+
+        Ok(base)
+    }
+
+    fn parse_array(&mut self) -> ParseResult<DangStatement> {
+        let start = self.expect(OpenBracket)?.span;
+        let mut elements = vec![self.parse_expression()?];
+        let mut repeat = None;
+
+        if self.eat(Semicolon).is_some() {
+            repeat = Some(self.expect_integer()?);
+        } else {
+            while self.eat(Comma).is_some() && !self.at(CloseBracket) {
+                elements.push(self.parse_expression()?);
+            }
+        }
+        self.expect(CloseBracket)?;
+
+        Ok(DangStatement::new(DangStatementKind::Array(DangArray { elements, repeat, offset: 0 }), start.to(self.last_span)))
+    }
+
+    fn parse_primary(&mut self) -> ParseResult<DangStatement> {
+        let (kind, value, span) = match self.peek() {
+            Some(token) => (token.kind, token.value, token.span),
+            None        => return Err(self.unexpected("expression"))
+        };
+
+        match kind {
+            Integer => {
+                let value = self.expect_integer()?;
+                Ok(DangStatement::new(DangStatementKind::Number(value), span))
+            }
+            Word => match value.as_word().unwrap() {
+                Symbol::TRUE | Symbol::FALSE => {
+                    self.next();
+                    Ok(DangStatement::new(DangStatementKind::Bool(value.as_word() == Some(Symbol::TRUE)), span))
+                }
+                Symbol::IF => self.parse_if(),
+                name => {
+                    let after = self.peek_nth(1).map(|x| x.kind);
+                    if after == Some(OpenCurly) && !self.no_struct_literals {
+                        self.parse_struct_literal()
+                    } else if after == Some(ExclamationMark) || after == Some(OpenParen) {
+                        let function_call = self.parse_function_call()?;
+                        Ok(DangStatement::new(DangStatementKind::FunctionCall(function_call), span.to(self.last_span)))
+                    } else {
+                        self.next();
+                        Ok(DangStatement::new(DangStatementKind::Name(DangVariable::new(name)), span))
+                    }
+                }
+            }
+            OpenParen => {
+                self.next();
+                let no_struct_literals = std::mem::replace(&mut self.no_struct_literals, false);
+                let symbol = self.parse_expression();
+                self.no_struct_literals = no_struct_literals;
+                let symbol = symbol?;
+                self.expect(CloseParen)?;
+                Ok(DangStatement::new(DangStatementKind::Expression(DangExpression { symbols: vec![symbol] }), span.to(self.last_span)))
+            }
+            OpenCurly => {
+                let block = self.parse_block()?;
+                Ok(DangStatement::new(DangStatementKind::Block(block), span.to(self.last_span)))
+            }
+            OpenBracket => self.parse_array(),
+            _ => Err(self.unexpected("expression"))
+        }
+    }
+
+    fn parse_struct_literal(&mut self) -> ParseResult<DangStatement> {
+        let start = self.expect(Word)?;
+        let mut fields = vec![];
+
+        self.expect(OpenCurly)?;
+        while !self.at(CloseCurly) {
+            let name = self.expect(Word)?.value.as_word().unwrap();
+            self.expect(Colon)?;
+            let value = self.parse_expression()?;
+            fields.push(DangStructLiteralField { name, value, offset: 0 });
+
+            if self.eat(Comma).is_none() {
+                break;
+            }
+        }
+        self.expect(CloseCurly)?;
+
+        Ok(DangStatement::new(DangStatementKind::StructLiteral(DangStructLiteral {
+            name: start.value.as_word().unwrap(),
+            fields,
+            offset: 0
+        }), start.span.to(self.last_span)))
+    }
+
+    pub fn parse_block(&mut self) -> ParseResult<DangBlock> {
+        self.expect(OpenCurly)?;
+        let symbols = self.parse_statements(CloseCurly)?;
+        self.expect(CloseCurly)?;
+        Ok(DangBlock { symbols })
+    }
+
+    fn at_keyword(&mut self, keyword: Symbol) -> bool {
+        self.peek().is_some_and(|x| x.value.as_word() == Some(keyword))
+    }
+
+    fn eat_keyword(&mut self, keyword: Symbol) -> Option<LexerToken> {
+        if self.at_keyword(keyword) {
+            self.next()
+        } else {
+            None
+        }
+    }
+
+    // `if` `else if` chains are nested ifs inside the else block
+    pub fn parse_if(&mut self) -> ParseResult<DangStatement> {
+        let start = match self.eat_keyword(Symbol::IF) {
+            Some(token) => token.span,
+            None        => return Err(self.unexpected("`if`"))
+        };
+        let no_struct_literals = std::mem::replace(&mut self.no_struct_literals, true);
+        let condition = self.parse_expression();
+        self.no_struct_literals = no_struct_literals;
+        let condition = condition?;
+        let then_block = self.parse_block()?;
+
+        let else_block = if self.eat_keyword(Symbol::ELSE).is_some() {
+            if self.at_keyword(Symbol::IF) {
+                Some(DangBlock { symbols: vec![self.parse_if()?] })
+            } else {
+                Some(self.parse_block()?)
+            }
+        } else {
+            None
+        };
+
+        Ok(DangStatement::new(DangStatementKind::If(DangIf {
+            condition: Box::new(condition),
+            then_block,
+            else_block
+        }), start.to(self.last_span)))
+    }
+
+    pub fn parse_function_call(&mut self) -> ParseResult<DangFunctionCall> {
+        let name = self.expect(Word)?.value.as_word().unwrap();
+        let is_built_in = self.eat(ExclamationMark).is_some();
+        let mut parameters = vec![];
+
+        self.expect(OpenParen)?;
+        while !self.at(CloseParen) {
+            parameters.push(self.parse_expression()?);
+
+            if self.eat(Comma).is_none() {
+                break;
+            }
+        }
+        self.expect(CloseParen)?;
+
+        Ok(DangFunctionCall { name, parameters, is_built_in, function: None })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> ParseResult<String> {
+        let ast = Parser::new(Lexer::from_source(source)).parse_ast()?;
+        Ok(ast.ast.iter().map(|x| x.to_string()).collect::<Vec<_>>().join("; "))
+    }
+
+    fn error(source: &str) -> (usize, usize, String) {
+        let diagnostic = parse(source).unwrap_err();
+        (diagnostic.span.start, diagnostic.span.end, diagnostic.message)
+    }
+
+    #[test]
+    fn operators_bind_by_precedence() {
+        assert_eq!(parse("1 + 2 * 3").unwrap(), "(1 + (2 * 3))");
+        assert_eq!(parse("1 * 2 + 3 % 4").unwrap(), "((1 * 2) + (3 % 4))");
+        assert_eq!(parse("2 * 3 ** 2").unwrap(), "(2 * (3 ** 2))");
+    }
+
+    #[test]
+    fn power_is_right_associative_and_the_rest_left() {
+        assert_eq!(parse("1 - 2 - 3").unwrap(), "((1 - 2) - 3)");
+        assert_eq!(parse("8 / 4 / 2").unwrap(), "((8 / 4) / 2)");
+        assert_eq!(parse("2 ** 3 ** 2").unwrap(), "(2 ** (3 ** 2))");
+    }
+
+    #[test]
+    fn statements_and_calls_are_parsed() {
+        assert_eq!(parse("print!(34 + 35); print!(10)").unwrap(), "print!((34 + 35)); print!(10)");
+        assert_eq!(parse("f(1, 2,); x;").unwrap(), "f(1, 2); x");
+        assert_eq!(parse("x = 1; a[0] += 2 * 3").unwrap(), "x = 1; a[0] += (2 * 3)");
+    }
+
+    #[test]
+    fn errors_point_at_the_unexpected_token() {
+        assert_eq!(error("print!(1 +)"), (10, 11, "expected expression, found `)`".to_string()));
+        assert_eq!(error("print!(1"), (8, 8, "expected `)`, reached end of file".to_string()));
+        assert_eq!(error("1 2"), (2, 3, "expected `;`, found integer".to_string()));
+        assert_eq!(error("print!(99999999999999999999)"), (7, 27, "integer literal is too large".to_string()));
+        assert_eq!(error("let a: [u8; 18446744073709551616] = 1"), (12, 32, "integer literal is too large".to_string()));
+    }
+}
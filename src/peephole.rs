@@ -0,0 +1,243 @@
+use crate::ir::*;
+
+// `-O0` leaves the program alone, `-O1` rewrites the `Ir`, `-O2` also
+// rewrites the assembly generated from it and `-O3` generates the assembly
+// from the optimized `Ssa` instead
+pub const MAX_OPTIMIZATION_LEVEL: u8 = 3;
+
+fn instruction(instruction_type: IrInstructionType, operand: i64) -> IrInstruction {
+    IrInstruction { instruction_type, operand }
+}
+
+// what `SignExtend` or `ZeroExtend` leave of `value`
+fn extend(instruction_type: IrInstructionType, value: i64, bits: i64) -> i64 {
+    let shift = 64 - bits as u32;
+    match instruction_type {
+        IrInstructionType::SignExtend => (value << shift) >> shift,
+        _                             => ((value as u64) << shift >> shift) as i64
+    }
+}
+
+// a cheaper replacement for the first instructions of `window` and how many
+// of them it replaces, nothing jumps into the window past its first
+// instruction
+fn rewrite(address: usize, window: &[IrInstruction]) -> Option<(usize, Vec<IrInstruction>)> {
+    use IrInstructionType::*;
+    let rewritten = match window {
+        // what faults is left for the program to do
+        [IrInstruction { instruction_type: PushInt, operand: a }, IrInstruction { instruction_type: PushInt, operand: b }, operation, ..] => {
+            operation.instruction_type.evaluate(*a as u64, *b as u64).map(|x| (3, vec![instruction(PushInt, x as i64)]))
+        }
+        _ => None
+    };
+    if rewritten.is_some() {
+        return rewritten;
+    }
+
+    let (first, second) = (&window[0], window.get(1));
+    let operand = first.operand;
+    let rewritten = match (first.instruction_type, second.map(|x| (x.instruction_type, x.operand))) {
+        (Offset, _) if operand == 0 => (1, vec![]),
+        (Jump, _) if operand == address as i64 + 1 => (1, vec![]),
+
+        // `Offset` is an add with an immediate, which has 32 bits
+        (PushInt, Some((Plus, _))) if i32::try_from(operand).is_ok() => (2, vec![instruction(Offset, operand)]),
+        (PushInt, Some((Minus, _))) if i32::try_from(operand.wrapping_neg()).is_ok() => (2, vec![instruction(Offset, operand.wrapping_neg())]),
+        (PushInt, Some((Multiplication | Division | UnsignedDivision, _))) if operand == 1 => (2, vec![]),
+        (Offset, Some((Offset, b))) if i32::try_from(operand.wrapping_add(b)).is_ok() => (2, vec![instruction(Offset, operand.wrapping_add(b))]),
+        (LocalAddress, Some((Offset, b))) if i32::try_from(operand.wrapping_add(b)).is_ok() => (2, vec![instruction(LocalAddress, operand.wrapping_add(b))]),
+
+        (PushInt, Some((Offset, b)))                  => (2, vec![instruction(PushInt, operand.wrapping_add(b))]),
+        (PushInt, Some((Negate, _)))                  => (2, vec![instruction(PushInt, operand.wrapping_neg())]),
+        (PushInt, Some((Not, _)))                     => (2, vec![instruction(PushInt, operand ^ 1)]),
+        (PushInt, Some((kind @ (SignExtend | ZeroExtend), bits))) => (2, vec![instruction(PushInt, extend(kind, operand, bits))]),
+        (PushInt, Some((JumpIfFalse, target)))        => {
+            if operand == 0 { (2, vec![instruction(Jump, target)]) } else { (2, vec![]) }
+        }
+        (PushInt, Some((BoundsCheck, length))) if (operand as u64) < length as u64 => (2, vec![instruction(PushInt, operand)]),
+        (Not, Some((Not, _)))                         => (2, vec![]),
+
+        // nothing that only pushes needs to happen for a value nobody uses
+        (PushInt | LoadLocal | LocalAddress | Argc, Some((Drop, _))) => (2, vec![]),
+        _ => return None
+    };
+    Some(rewritten)
+}
+
+impl Ir {
+    // rewrites short sequences of instructions into cheaper ones until there
+    // is nothing left to rewrite
+    pub fn optimize_peephole(&mut self) {
+        while self.peephole_pass() {}
+
+        if cfg!(debug_assertions) {
+            if let Err(error) = self.verify() {
+                panic!("unreachable: the peephole optimizer generated invalid IR: {}\n{}", error, self);
+            }
+        }
+    }
+
+    fn peephole_pass(&mut self) -> bool {
+        let length = self.operations.len();
+        let mut targets = vec![false; length + 1];
+        for operation in self.operations.iter().filter(|x| x.instruction_type.is_jump()) {
+            targets[operation.operand as usize] = true;
+        }
+        for function in &self.functions {
+            targets[function.address] = true;
+        }
+
+        let mut operations = vec![];
+        // where every instruction ended up, the removed ones go to whatever
+        // comes after them
+        let mut new_addresses = vec![0; length + 1];
+        let mut changed = false;
+        let mut address = 0;
+        while address < length {
+            let mut end = (address + 3).min(length);
+            if let Some(target) = (address + 1..end).find(|x| targets[*x]) {
+                end = target;
+            }
+
+            let (count, replacement) = match rewrite(address, &self.operations[address..end]) {
+                Some(rewritten) => {
+                    changed = true;
+                    rewritten
+                }
+                None => (1, vec![self.operations[address].clone()])
+            };
+            for new_address in &mut new_addresses[address..address + count] {
+                *new_address = operations.len();
+            }
+            operations.extend(replacement);
+            address += count;
+        }
+        new_addresses[length] = operations.len();
+
+        for operation in operations.iter_mut().filter(|x| x.instruction_type.is_jump()) {
+            operation.operand = new_addresses[operation.operand as usize] as i64;
+        }
+        for function in &mut self.functions {
+            function.address = new_addresses[function.address];
+        }
+        self.at_in_instructions = operations.len() as i64;
+        self.operations = operations;
+        changed
+    }
+}
+
+// the operand of a `push` or `pop`
+fn stack_operand<'a>(line: &'a str, instruction: &str) -> Option<&'a str> {
+    line.strip_prefix(instruction)?.strip_prefix(' ').map(str::trim)
+}
+
+// removes values that are pushed only to be popped right away, `assembly` is
+// what `Ir::to_nasm_linux_x86_64_assembly` writes, where labels are the only
+// way into the middle of it
+pub fn optimize_assembly(assembly: &str) -> String {
+    let mut lines: Vec<String> = vec![];
+    for line in assembly.lines() {
+        // comments do not separate a push from its pop
+        let previous = lines.iter().rposition(|x| !x.starts_with(';'));
+        let pushed = previous.and_then(|x| stack_operand(&lines[x], "push"));
+        let popped = stack_operand(line, "pop");
+
+        match (pushed, popped) {
+            // `rsp` moves in between, so operands that use it would change
+            (Some(pushed), Some(popped)) if !pushed.contains("rsp") && !popped.contains("rsp") => {
+                let previous = previous.unwrap();
+                if pushed == popped {
+                    lines.remove(previous);
+                } else if pushed.contains('[') && popped.contains('[') {
+                    // there is no move from memory to memory
+                    lines.push(line.to_string());
+                } else {
+                    let moved = format!("mov {}, {}", popped, pushed);
+                    lines.remove(previous);
+                    lines.push(moved);
+                }
+            }
+            _ => lines.push(line.to_string())
+        }
+    }
+
+    let mut optimized = lines.join("\n");
+    optimized.push('\n');
+    optimized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the instructions `source` is rewritten into, one per line
+    fn optimize(source: &str) -> String {
+        let mut ir = Ir::parse(source).unwrap();
+        ir.optimize_peephole();
+        ir.operations.iter().map(|x| format!("{}\n", x)).collect()
+    }
+
+    #[test]
+    fn constants_are_computed() {
+        assert_eq!(optimize("push_int 6\npush_int 7\nmultiplication\nprint\n"), "push_int 42\nprint\n");
+        assert_eq!(optimize("push_int 300\nsign_extend 8\nnegate\nprint\n"), "push_int -44\nprint\n");
+        assert_eq!(optimize("push_int 1\nnot\nnot\nprint\n"), "push_int 1\nprint\n");
+    }
+
+    #[test]
+    fn faulting_operations_are_kept() {
+        let source = "push_int 1\npush_int 0\ndivision\nprint\n";
+        assert_eq!(optimize(source), source);
+        let source = "push_int -9223372036854775808\npush_int -1\nmod\nprint\n";
+        assert_eq!(optimize(source), source);
+        let source = "push_int 2\npush_int -1\npower\nprint\n";
+        assert_eq!(optimize(source), source);
+        let source = "push_int 3\nbounds_check 3\nprint\n";
+        assert_eq!(optimize(source), source);
+    }
+
+    #[test]
+    fn additions_of_constants_become_offsets() {
+        assert_eq!(optimize("enter 8\nload_local -8\npush_int 1\nplus\npush_int 2\nplus\nprint\n"), "enter 8\nload_local -8\noffset 3\nprint\n");
+        assert_eq!(optimize("enter 8\nlocal_address -8\npush_int 8\nminus\nprint\n"), "enter 8\nlocal_address -16\nprint\n");
+        let source = "enter 8\nload_local -8\npush_int 4294967296\nplus\nprint\n";
+        assert_eq!(optimize(source), source);
+    }
+
+    #[test]
+    fn constant_conditions_become_jumps() {
+        assert_eq!(optimize("push_int 0\njump_if_false 3\npush_int 1\n"), "jump 2\npush_int 1\n");
+        assert_eq!(optimize("push_int 1\njump_if_false 3\npush_int 2\nprint\n"), "push_int 2\nprint\n");
+    }
+
+    #[test]
+    fn unused_values_are_not_pushed() {
+        assert_eq!(optimize("enter 8\nload_local -8\ndrop\nargc\ndrop\n"), "enter 8\n");
+    }
+
+    #[test]
+    fn rewrites_stop_at_jump_targets() {
+        let source = "enter 8\nload_local -8\nload_local -8\njump_if_false 5\nnot\nnot\nprint\n";
+        assert_eq!(optimize(source), source);
+    }
+
+    #[test]
+    fn pushes_popped_right_away_are_removed() {
+        assert_eq!(optimize_assembly("push rax\npop rax\nret\n"), "ret\n");
+        assert_eq!(optimize_assembly("push rax\npop rbx\n"), "mov rbx, rax\n");
+        assert_eq!(optimize_assembly("push QWORD [rbp-8]\npop rax\n"), "mov rax, QWORD [rbp-8]\n");
+        assert_eq!(optimize_assembly("push rax\n;; plus\npop rbx\n"), ";; plus\nmov rbx, rax\n");
+    }
+
+    #[test]
+    fn pushes_that_cannot_become_moves_are_kept() {
+        for assembly in [
+            "push QWORD [rbp-8]\npop QWORD [rbp-16]\n",
+            "push QWORD [rsp+8]\npop rax\n",
+            "push rax\npop QWORD [rsp]\n",
+            "push rax\naddr_3:\npop rbx\n"
+        ] {
+            assert_eq!(optimize_assembly(assembly), assembly);
+        }
+    }
+}
@@ -0,0 +1,266 @@
+use crate::ssa::*;
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+// the code of `IrInstruction`s and the runtime routines leave these alone,
+// functions save the ones they use so calls do not either
+pub const ALLOCATABLE_REGISTERS: [&str; 4] = ["r12", "r13", "r14", "r15"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SsaLocation {
+    Register(&'static str),
+    // index of an 8 byte slot in the frame after the locals
+    Slot(usize)
+}
+
+/// Where every virtual register of an `SsaFunction` lives, the result of
+/// linear scan register allocation.
+#[derive(Debug, Clone)]
+pub struct SsaAllocation {
+    pub locations: HashMap<SsaRegister, SsaLocation>,
+    pub slots: usize,
+    // what the function has to save for its caller, in slots after the
+    // others
+    pub saved: Vec<&'static str>,
+    frame_size: i64
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Interval {
+    register: SsaRegister,
+    start: usize,
+    end: usize
+}
+
+impl SsaAllocation {
+    fn slot_operand(&self, slot: usize) -> String {
+        format!("QWORD [rbp-{}]", self.frame_size + 8 * (slot as i64 + 1))
+    }
+
+    // how the instructions of the function refer to `register`
+    pub fn operand(&self, register: SsaRegister) -> String {
+        match self.locations[&register] {
+            SsaLocation::Register(name) => name.to_string(),
+            SsaLocation::Slot(slot)     => self.slot_operand(slot)
+        }
+    }
+
+    // where `saved[index]` is kept while the function runs
+    pub fn save_operand(&self, index: usize) -> String {
+        self.slot_operand(self.slots + index)
+    }
+
+    // bytes of the whole frame, locals, spilled registers and saved ones
+    pub fn frame_size(&self) -> i64 {
+        self.frame_size + 8 * (self.slots + self.saved.len()) as i64
+    }
+
+    // every register gets one interval from where it is first defined to
+    // where it is last needed, covering the blocks it is live through in the
+    // order they are emitted, intervals are handed out registers in the
+    // order they start and when there are none left the one that ends last
+    // goes to the stack
+    pub fn allocate(function: &SsaFunction, save: bool) -> SsaAllocation {
+        let intervals = live_intervals(function);
+
+        let mut locations = HashMap::with_capacity(function.registers);
+        let mut slots = 0;
+        let mut free: Vec<&'static str> = ALLOCATABLE_REGISTERS.iter().rev().copied().collect();
+        let mut active: Vec<(Interval, &'static str)> = vec![];
+        let mut used = HashSet::new();
+
+        for interval in intervals {
+            // an interval that ends where this one starts still has to be
+            // readable, the arguments of a jump are read after the
+            // parameters it defines are
+            active.retain(|(other, register)| {
+                if other.end < interval.start {
+                    free.push(register);
+                    false
+                } else {
+                    true
+                }
+            });
+
+            if let Some(register) = free.pop() {
+                locations.insert(interval.register, SsaLocation::Register(register));
+                used.insert(register);
+                active.push((interval, register));
+                continue;
+            }
+
+            let (spilled, _) = active.iter().enumerate().max_by_key(|(_, (other, _))| other.end).unwrap();
+            if active[spilled].0.end > interval.end {
+                let (other, register) = active.remove(spilled);
+                locations.insert(other.register, SsaLocation::Slot(slots));
+                locations.insert(interval.register, SsaLocation::Register(register));
+                active.push((interval, register));
+            } else {
+                locations.insert(interval.register, SsaLocation::Slot(slots));
+            }
+            slots += 1;
+        }
+
+        let saved = if save { ALLOCATABLE_REGISTERS.iter().filter(|x| used.contains(*x)).copied().collect() } else { vec![] };
+        SsaAllocation { locations, slots, saved, frame_size: function.frame_size }
+    }
+}
+
+// registers an instruction or terminator reads
+fn terminator_uses(terminator: &SsaTerminator) -> Vec<SsaRegister> {
+    let mut uses = match terminator {
+        SsaTerminator::Branch { condition, .. }                   => vec![*condition],
+        SsaTerminator::Return(value) | SsaTerminator::Exit(value) => vec![*value],
+        SsaTerminator::Jump(_)                                    => vec![]
+    };
+    for target in terminator.targets() {
+        uses.extend(&target.arguments);
+    }
+    uses
+}
+
+fn live_intervals(function: &SsaFunction) -> Vec<Interval> {
+    // positions in the order the blocks are emitted, the parameters of a
+    // block are defined at its first one and its terminator is at its last
+    let mut starts = HashMap::new();
+    let mut ends = HashMap::new();
+    let mut position = 0;
+    for block in &function.blocks {
+        starts.insert(block.address, position);
+        position += block.instructions.len() + 1;
+        ends.insert(block.address, position);
+        position += 1;
+    }
+
+    // what each block reads before defining it and what it defines
+    let mut uses: HashMap<usize, HashSet<SsaRegister>> = HashMap::new();
+    let mut definitions: HashMap<usize, HashSet<SsaRegister>> = HashMap::new();
+    for block in &function.blocks {
+        let mut defined: HashSet<SsaRegister> = block.parameters.iter().copied().collect();
+        let mut read = HashSet::new();
+        for instruction in &block.instructions {
+            read.extend(instruction.arguments.iter().filter(|x| !defined.contains(x)));
+            defined.extend(instruction.result);
+        }
+        read.extend(terminator_uses(&block.terminator).into_iter().filter(|x| !defined.contains(x)));
+        uses.insert(block.address, read);
+        definitions.insert(block.address, defined);
+    }
+
+    let mut live_in: HashMap<usize, HashSet<SsaRegister>> = function.blocks.iter().map(|x| (x.address, HashSet::new())).collect();
+    let mut live_out: HashMap<usize, HashSet<SsaRegister>> = live_in.clone();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for block in function.blocks.iter().rev() {
+            let out: HashSet<SsaRegister> = block.terminator.targets().iter()
+                .flat_map(|target| live_in[&target.block].iter().copied())
+                .collect();
+            let mut live: HashSet<SsaRegister> = out.difference(&definitions[&block.address]).copied().collect();
+            live.extend(&uses[&block.address]);
+            if live != live_in[&block.address] || out != live_out[&block.address] {
+                changed = true;
+                live_in.insert(block.address, live);
+                live_out.insert(block.address, out);
+            }
+        }
+    }
+
+    let mut ranges: HashMap<SsaRegister, (usize, usize)> = HashMap::new();
+    let mut extend = |register: SsaRegister, position: usize| {
+        let range = ranges.entry(register).or_insert((position, position));
+        range.0 = range.0.min(position);
+        range.1 = range.1.max(position);
+    };
+    for block in &function.blocks {
+        let start = starts[&block.address];
+        let end = ends[&block.address];
+        for &parameter in &block.parameters {
+            extend(parameter, start);
+        }
+        for (index, instruction) in block.instructions.iter().enumerate() {
+            for &argument in &instruction.arguments {
+                extend(argument, start + index + 1);
+            }
+            if let Some(result) = instruction.result {
+                extend(result, start + index + 1);
+            }
+        }
+        for register in terminator_uses(&block.terminator) {
+            extend(register, end);
+        }
+        // jumps write the parameters they pass before getting there, which
+        // can be after the block with them for loops
+        for target in block.terminator.targets() {
+            for &parameter in &function.block(target.block).parameters {
+                extend(parameter, end);
+            }
+        }
+        for &register in &live_in[&block.address] {
+            extend(register, start);
+        }
+        for &register in &live_out[&block.address] {
+            extend(register, end);
+        }
+    }
+
+    let mut intervals: Vec<Interval> = ranges.into_iter().map(|(register, (start, end))| Interval { register, start, end }).collect();
+    intervals.sort_by_key(|x| (x.start, x.register));
+    intervals
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::*;
+    use crate::ssa::tests::*;
+
+    // registers are only shared by values that are never live at once
+    fn assert_no_overlaps(function: &SsaFunction, allocation: &SsaAllocation) {
+        let intervals = live_intervals(function);
+        for a in &intervals {
+            for b in intervals.iter().filter(|x| x.register != a.register) {
+                let shared = allocation.locations[&a.register] == allocation.locations[&b.register];
+                assert!(!shared || a.end < b.start || b.end < a.start, "r{} and r{} overlap in {:?}", a.register, b.register, allocation.locations[&a.register]);
+            }
+        }
+    }
+
+    #[test]
+    fn values_that_do_not_fit_are_spilled() {
+        // six values are live at once before the first `plus`
+        let source = "enter 8\npush_int 1\npush_int 2\npush_int 3\npush_int 4\npush_int 5\npush_int 6\nplus\nplus\nplus\nplus\nplus\nprint\n";
+        let ssa = Ssa::from_ir(&Ir::parse(source).unwrap()).unwrap();
+        let function = &ssa.functions[0];
+        let allocation = SsaAllocation::allocate(function, true);
+
+        // two of the six at least, more when a value is defined where the
+        // ones it is computed from die
+        assert!(allocation.slots >= 2, "{:?}", allocation);
+        assert_eq!(allocation.saved, ALLOCATABLE_REGISTERS);
+        assert_eq!(allocation.frame_size(), 8 + 8 * (allocation.slots + 4) as i64);
+        assert_eq!(allocation.locations.len(), function.registers);
+        assert_no_overlaps(function, &allocation);
+
+        // every slot is used once and goes after the locals
+        let mut slots: Vec<usize> = allocation.locations.values().filter_map(|x| match x {
+            SsaLocation::Slot(slot)  => Some(*slot),
+            SsaLocation::Register(_) => None
+        }).collect();
+        slots.sort();
+        assert_eq!(slots, (0..allocation.slots).collect::<Vec<usize>>());
+        for slot in 0..allocation.slots {
+            assert_eq!(allocation.slot_operand(slot), format!("QWORD [rbp-{}]", 16 + 8 * slot));
+        }
+    }
+
+    #[test]
+    fn loop_parameters_do_not_share_registers() {
+        let function = swapping_loop();
+        let allocation = SsaAllocation::allocate(&function, false);
+        assert_eq!(allocation.slots, 0);
+        assert!(allocation.saved.is_empty());
+        assert_no_overlaps(&function, &allocation);
+    }
+}
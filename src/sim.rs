@@ -0,0 +1,536 @@
+use crate::ir::*;
+
+use std::io::*;
+
+// the simulated stack grows down from the top of this many bytes, like the
+// one the kernel hands to a native executable
+const STACK_SIZE: usize = 8 * 1024 * 1024;
+// where the simulated stack starts, so addresses look like real ones and a
+// null pointer is never valid
+const STACK_BASE: u64 = 0x7ff0_0000_0000;
+
+// the error syscalls return for bad file descriptors, like the kernel would
+const EBADF: i64 = 9;
+
+/// Runs `Ir` without assembling it, behaving the way the NASM output does:
+/// values are 64 bits, the stack lives in memory and locals are addressed
+/// from the frame pointer.
+pub struct Simulator<'a> {
+    ir: &'a Ir,
+    memory: Vec<u8>,
+    rsp: u64,
+    rbp: u64,
+    pc: usize,
+    // where argc is, like the stack pointer `_start` gets
+    arguments: u64
+}
+
+// the ways a simulated program can stop early, with the message it prints
+// and the status it exits with
+pub struct SimulatorExit {
+    pub message: Option<String>,
+    pub status: i32
+}
+
+pub type SimulatorResult<T> = std::result::Result<T, SimulatorExit>;
+
+fn fail<T>(message: &str) -> SimulatorResult<T> {
+    Err(SimulatorExit { message: Some(message.to_string()), status: 1 })
+}
+
+impl<'a> Simulator<'a> {
+    pub fn new(ir: &'a Ir, arguments: &[String], environment: &[String]) -> Simulator<'a> {
+        let mut simulator = Simulator {
+            ir,
+            memory: vec![0; STACK_SIZE],
+            rsp: STACK_BASE + STACK_SIZE as u64,
+            rbp: STACK_BASE + STACK_SIZE as u64,
+            pc: 0,
+            arguments: 0
+        };
+        if simulator.push_arguments(arguments, environment).is_err() {
+            panic!("the arguments and the environment do not fit in the simulated stack");
+        }
+        simulator
+    }
+
+    // lays the strings and the pointers to them out the way the kernel does
+    // for a new process
+    fn push_arguments(&mut self, arguments: &[String], environment: &[String]) -> SimulatorResult<()> {
+        let mut pointers = vec![];
+        for string in arguments.iter().chain(environment) {
+            let size = string.len() as u64 + 1;
+            if self.rsp - STACK_BASE < size {
+                return fail("error: stack overflow");
+            }
+            self.rsp -= size;
+            let index = self.index(self.rsp, size as usize)?;
+            self.memory[index..index + string.len()].copy_from_slice(string.as_bytes());
+            self.memory[index + string.len()] = 0;
+            pointers.push(self.rsp);
+        }
+        self.rsp &= !15;
+
+        let (argv, envp) = pointers.split_at(arguments.len());
+        self.push(0)?;
+        for &pointer in envp.iter().rev() {
+            self.push(pointer)?;
+        }
+        self.push(0)?;
+        for &pointer in argv.iter().rev() {
+            self.push(pointer)?;
+        }
+        self.push(arguments.len() as u64)?;
+
+        self.arguments = self.rsp;
+        self.rbp = self.rsp;
+        Ok(())
+    }
+
+    fn index(&self, address: u64, width: usize) -> SimulatorResult<usize> {
+        let index = address.wrapping_sub(STACK_BASE) as usize;
+        if address < STACK_BASE || index.checked_add(width).is_none_or(|end| end > self.memory.len()) {
+            return fail(&format!("error: invalid memory access at {:#x}", address));
+        }
+        Ok(index)
+    }
+
+    fn read(&self, address: u64, width: usize) -> SimulatorResult<u64> {
+        let index = self.index(address, width)?;
+        let mut bytes = [0; 8];
+        bytes[..width].copy_from_slice(&self.memory[index..index + width]);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn write(&mut self, address: u64, width: usize, value: u64) -> SimulatorResult<()> {
+        let index = self.index(address, width)?;
+        self.memory[index..index + width].copy_from_slice(&value.to_le_bytes()[..width]);
+        Ok(())
+    }
+
+    fn push(&mut self, value: u64) -> SimulatorResult<()> {
+        if self.rsp - STACK_BASE < 8 {
+            return fail("error: stack overflow");
+        }
+        self.rsp -= 8;
+        self.write(self.rsp, 8, value)
+    }
+
+    fn pop(&mut self) -> SimulatorResult<u64> {
+        let value = self.read(self.rsp, 8)?;
+        self.rsp += 8;
+        Ok(value)
+    }
+
+    fn frame(&self, offset: i64) -> u64 {
+        self.rbp.wrapping_add(offset as u64)
+    }
+
+    // runs until the end of the program and returns its exit status
+    pub fn run(&mut self, output: &mut impl Write) -> i32 {
+        match self.run_until_exit(output) {
+            Ok(()) => 0,
+            Err(exit) => {
+                let _ = output.flush();
+                if let Some(message) = exit.message {
+                    eprintln!("{}", message);
+                }
+                exit.status
+            }
+        }
+    }
+
+    fn run_until_exit(&mut self, output: &mut impl Write) -> SimulatorResult<()> {
+        while self.pc < self.ir.operations.len() {
+            self.step(output)?;
+        }
+        let _ = output.flush();
+        Ok(())
+    }
+
+    fn step(&mut self, output: &mut impl Write) -> SimulatorResult<()> {
+        use IrInstructionType::*;
+        let instruction = &self.ir.operations[self.pc];
+        let operand = instruction.operand;
+        self.pc += 1;
+
+        match instruction.instruction_type {
+            PushInt => self.push(operand as u64)?,
+            Plus | Minus | Division | UnsignedDivision | Multiplication | Mod | UnsignedMod | Power | UnsignedPower |
+            Equal | NotEqual | Less | LessEqual | Greater | GreaterEqual |
+            UnsignedLess | UnsignedLessEqual | UnsignedGreater | UnsignedGreaterEqual => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                if let Some(message) = instruction.instruction_type.fault(a, b) {
+                    return fail(message);
+                }
+                self.push(instruction.instruction_type.evaluate(a, b).unwrap())?;
+            }
+            Negate => {
+                let value = self.pop()?;
+                self.push(value.wrapping_neg())?;
+            }
+            Not => {
+                let value = self.pop()?;
+                self.push(value ^ 1)?;
+            }
+            SignExtend => {
+                let shift = 64 - operand as u32;
+                let value = self.pop()?;
+                self.push((((value << shift) as i64) >> shift) as u64)?;
+            }
+            ZeroExtend => {
+                let value = self.pop()?;
+                self.push(value & (u64::MAX >> (64 - operand as u32)))?;
+            }
+            Jump => self.pc = operand as usize,
+            JumpIfFalse => {
+                if self.pop()? == 0 {
+                    self.pc = operand as usize;
+                }
+            }
+            Enter => {
+                self.push(self.rbp)?;
+                self.rbp = self.rsp;
+                if self.rsp - STACK_BASE < operand as u64 {
+                    return fail("error: stack overflow");
+                }
+                self.rsp -= operand as u64;
+            }
+            LoadLocal => {
+                let value = self.read(self.frame(operand), 8)?;
+                self.push(value)?;
+            }
+            StoreLocal => {
+                let value = self.pop()?;
+                self.write(self.frame(operand), 8, value)?;
+            }
+            Call => {
+                self.push(self.pc as u64)?;
+                self.pc = self.ir.functions[operand as usize].address;
+            }
+            // returns to right after the `Call`, which then drops the
+            // arguments and pushes the returned value like the native code
+            Return => {
+                let value = self.pop()?;
+                self.rsp = self.rbp;
+                self.rbp = self.pop()?;
+                self.pc = self.pop()? as usize;
+
+                let call = self.pc.checked_sub(1).and_then(|x| self.ir.operations.get(x));
+                let Some(call) = call.filter(|x| x.instruction_type == Call) else {
+                    return fail("error: `return` outside of a function");
+                };
+                self.rsp += 8 * self.ir.functions[call.operand as usize].parameters as u64;
+                self.push(value)?;
+            }
+            Exit => {
+                let status = self.pop()?;
+                return Err(SimulatorExit { message: None, status: (status & 0xff) as i32 });
+            }
+            Drop => {
+                self.pop()?;
+            }
+            LocalAddress => self.push(self.frame(operand))?,
+            Offset => {
+                let address = self.pop()?;
+                self.push(address.wrapping_add(operand as u64))?;
+            }
+            Load => {
+                let address = self.pop()?;
+                let value = self.read(address, operand as usize)?;
+                self.push(value)?;
+            }
+            Store => {
+                let value = self.pop()?;
+                let address = self.pop()?;
+                self.write(address, operand as usize, value)?;
+            }
+            Copy => {
+                let source = self.pop()?;
+                let destination = self.pop()?;
+                let from = self.index(source, operand as usize)?;
+                let to = self.index(destination, operand as usize)?;
+                // one byte at a time like `rep movsb`
+                for i in 0..operand as usize {
+                    self.memory[to + i] = self.memory[from + i];
+                }
+            }
+            BoundsCheck => {
+                if self.read(self.rsp, 8)? >= operand as u64 {
+                    return fail(BOUNDS_CHECK_MESSAGE);
+                }
+            }
+            Print => {
+                let value = self.pop()?;
+                let _ = writeln!(output, "{}", value);
+            }
+            PrintSigned => {
+                let value = self.pop()?;
+                let _ = writeln!(output, "{}", value as i64);
+            }
+            ReadInt => {
+                let _ = output.flush();
+                let value = self.read_int();
+                self.push(value)?;
+            }
+            ReadLine => {
+                let capacity = self.pop()?;
+                let buffer = self.pop()?;
+                let _ = output.flush();
+                let length = self.read_line(buffer, capacity)?;
+                self.push(length as u64)?;
+            }
+            Argc => {
+                let argc = self.read(self.arguments, 8)?;
+                self.push(argc)?;
+            }
+            Arg => {
+                let index = self.pop()?;
+                let argc = self.read(self.arguments, 8)?;
+                let pointer = if index < argc { self.read(self.arguments + 8 + 8 * index, 8)? } else { 0 };
+                self.push(pointer)?;
+            }
+            Env => {
+                let index = self.pop()?;
+                let argc = self.read(self.arguments, 8)?;
+                let mut envp = self.arguments + 16 + 8 * argc;
+                let mut pointer = self.read(envp, 8)?;
+                for _ in 0..index {
+                    if pointer == 0 {
+                        break;
+                    }
+                    envp += 8;
+                    pointer = self.read(envp, 8)?;
+                }
+                self.push(pointer)?;
+            }
+            Syscall => {
+                let mut arguments = [0; 6];
+                for i in (0..operand as usize).rev() {
+                    arguments[i] = self.pop()?;
+                }
+                let number = self.pop()?;
+                let result = self.syscall(output, number, arguments)?;
+                self.push(result as u64)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_byte(&mut self) -> Option<u8> {
+        let mut stdin = stdin().lock();
+        let byte = *stdin.fill_buf().ok()?.first()?;
+        stdin.consume(1);
+        Some(byte)
+    }
+
+    // the same as the `read_int` routine of the NASM runtime
+    fn read_int(&mut self) -> u64 {
+        let first = loop {
+            match self.read_byte() {
+                Some(byte) if byte <= b' ' => continue,
+                Some(byte) => break byte,
+                None => return 0
+            }
+        };
+
+        let negative = first == b'-';
+        let mut next = if negative { self.read_byte() } else { Some(first) };
+        let mut value: u64 = 0;
+        while let Some(digit @ b'0'..=b'9') = next {
+            value = value.wrapping_mul(10).wrapping_add((digit - b'0') as u64);
+            next = self.read_byte();
+        }
+
+        if negative { value.wrapping_neg() } else { value }
+    }
+
+    // the same as the `read_line` routine of the NASM runtime
+    fn read_line(&mut self, buffer: u64, capacity: u64) -> SimulatorResult<i64> {
+        let mut next = self.read_byte();
+        if next.is_none() {
+            return Ok(-1);
+        }
+
+        let mut length = 0;
+        while let Some(byte) = next.filter(|x| *x != b'\n') {
+            if length < capacity {
+                self.write(buffer + length, 1, byte as u64)?;
+                length += 1;
+            }
+            next = self.read_byte();
+        }
+        Ok(length as i64)
+    }
+
+    // only what programs need to talk to the terminal, anything else stops
+    // the simulation
+    fn syscall(&mut self, output: &mut impl Write, number: u64, arguments: [u64; 6]) -> SimulatorResult<i64> {
+        match number {
+            SYS_READ => {
+                let (fd, buffer, count) = (arguments[0], arguments[1], arguments[2] as usize);
+                let index = self.index(buffer, count)?;
+                if fd != 0 {
+                    return Ok(-EBADF);
+                }
+                let _ = output.flush();
+                match stdin().read(&mut self.memory[index..index + count]) {
+                    Ok(read)   => Ok(read as i64),
+                    Err(error) => Ok(-(error.raw_os_error().unwrap_or(EBADF as i32) as i64))
+                }
+            }
+            SYS_WRITE => {
+                let (fd, buffer, count) = (arguments[0], arguments[1], arguments[2] as usize);
+                let index = self.index(buffer, count)?;
+                let bytes = &self.memory[index..index + count];
+                let written = match fd {
+                    1 => output.write_all(bytes),
+                    2 => {
+                        let _ = output.flush();
+                        stderr().write_all(bytes)
+                    }
+                    _ => return Ok(-EBADF)
+                };
+                match written {
+                    Ok(())     => Ok(count as i64),
+                    Err(error) => Ok(-(error.raw_os_error().unwrap_or(EBADF as i32) as i64))
+                }
+            }
+            SYS_EXIT | SYS_EXIT_GROUP => Err(SimulatorExit { message: None, status: (arguments[0] & 0xff) as i32 }),
+            _ => fail(&format!("error: syscall {} is not supported by the simulator", number))
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::compile;
+
+    // what `ir` prints, it has to exit with 0
+    pub fn simulate(ir: &Ir) -> String {
+        let mut output = vec![];
+        assert_eq!(Simulator::new(ir, &[], &[]).run(&mut output), 0);
+        String::from_utf8(output).unwrap()
+    }
+
+    // how `ir` stops when it fails, its status and what it reports
+    pub fn simulate_failure(ir: &Ir) -> (i32, Option<String>) {
+        let exit = Simulator::new(ir, &[], &[]).run_until_exit(&mut vec![]).err().unwrap();
+        (exit.status, exit.message)
+    }
+
+    #[test]
+    fn arrays_are_copied_by_value() {
+        let ir = compile("let a = [1, 2, 3]; let b = a; b[0] = 5; print!(a[0]); print!(b[0])");
+        assert_eq!(simulate(&ir), "1\n5\n");
+    }
+
+    #[test]
+    fn nested_and_repeated_arrays_are_indexed() {
+        let ir = compile("let m = [[1, 2], [3, 4]]; m[1][0] = 7; print!(m[1][0] + m[0][1]); let z = [7 as u8; 4]; print!(z[3])");
+        assert_eq!(simulate(&ir), "9\n7\n");
+    }
+
+    #[test]
+    fn indexing_out_of_bounds_fails() {
+        let ir = compile("let a = [1, 2, 3]; let i = 3; print!(a[i])");
+        let mut output = vec![];
+        let exit = Simulator::new(&ir, &[], &[]).run_until_exit(&mut output).err().unwrap();
+        assert_eq!(exit.message.as_deref(), Some(BOUNDS_CHECK_MESSAGE));
+        assert_eq!(exit.status, 1);
+    }
+
+    #[test]
+    fn divisions_that_fault_fail() {
+        let ir = compile("let a = 7; let b = 0; print!(a / b)");
+        assert_eq!(simulate_failure(&ir), (1, Some(DIVISION_BY_ZERO_MESSAGE.to_string())));
+        let ir = compile("let a = -9223372036854775807 - 1; let b = -1; print!(a % b)");
+        assert_eq!(simulate_failure(&ir), (1, Some(DIVISION_OVERFLOW_MESSAGE.to_string())));
+        let ir = compile("let a: i8 = -128; let b: i8 = -1; print!(a / b)");
+        assert_eq!(simulate(&ir), "-128\n");
+    }
+
+    // an empty array takes no room, so it must not write over what is
+    // next to it in the frame
+    #[test]
+    fn empty_arrays_store_nothing() {
+        let ir = compile("let x = 5; let a = [7; 0]; print!(x)");
+        assert_eq!(simulate(&ir), "5\n");
+        let ir = compile("fn f() -> i64 { let x = 2; let a = [x + 1; 0]; return x } print!(f()); let b = [[1, 2]; 0]; print!(4)");
+        assert_eq!(simulate(&ir), "2\n4\n");
+    }
+
+    // adding to a pointer moves it by whole elements, `load!` and `store!`
+    // move exactly as many bytes as they are told
+    #[test]
+    fn pointers_step_by_their_element_size() {
+        let ir = compile("let a = [1 as u16, 2 as u16, 300 as u16]; let p = &a[0]; print!(*(p + 2)); *(p + 1) = 7 as u16; print!(a[1])");
+        assert_eq!(simulate(&ir), "300\n7\n");
+        let ir = compile("let a = [1 as u16, 2 as u16, 300 as u16]; store!(&a[0], 65535, 2); print!(a[0]); print!(load!(&a[2], 1))");
+        assert_eq!(simulate(&ir), "65535\n44\n");
+    }
+
+    // the place is evaluated once, so `next` is only called once
+    #[test]
+    fn compound_assignments_update_their_target() {
+        let ir = compile("let x: u8 = 250; x += 10; print!(x); let y: i8 = 127; y += 1; print!(y)");
+        assert_eq!(simulate(&ir), "4\n-128\n");
+        let ir = compile("let a = [1, 2, 3]; let i = 0; fn next(i: &i64) -> i64 { *i += 1; return *i } a[next(&i)] += 40; print!(a[1]); print!(i)");
+        assert_eq!(simulate(&ir), "42\n1\n");
+        let ir = compile("let a = [1 as u16, 2 as u16, 3 as u16]; let p = &a[0]; p += 2; print!(*p)");
+        assert_eq!(simulate(&ir), "3\n");
+    }
+
+    // a store through a pointer only writes the bytes of the pointee, the
+    // rest of the variable's slot still holds the old value
+    #[test]
+    fn sized_locals_written_through_pointers_read_back() {
+        let ir = compile("let a: i8 = -1; *(&a) = 1; print!(a); let b: u8 = 255; *(&b) = 3; print!(b); let c: i16 = -1; let p = &c; *p = -2; print!(c); let d: i32 = -1; *(&d) = 5; print!(d)");
+        assert_eq!(simulate(&ir), "1\n3\n-2\n5\n");
+        let ir = compile("let a: i8 = -1; store!(&a, 1, 1); print!(a); let b: u16 = 65535; store!(&b, 2, 1); print!(b)");
+        assert_eq!(simulate(&ir), "1\n65282\n");
+    }
+
+    #[test]
+    fn write_syscalls_go_to_the_output() {
+        let ir = compile("let buf = [104 as u8, 105 as u8, 10 as u8]; syscall!(1, 1, &buf[0], 3); print!(syscall!(1, 1, &buf[1], 2))");
+        assert_eq!(simulate(&ir), "hi\ni\n2\n");
+    }
+
+    // the status a program exits with and what it prints before that
+    fn exit_status(source: &str) -> (i32, String) {
+        let ir = compile(source);
+        let mut output = vec![];
+        let status = Simulator::new(&ir, &[], &[]).run(&mut output);
+        (status, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn programs_exit_with_what_they_return() {
+        assert_eq!(exit_status("print!(1); 3"), (3, "1\n".to_string()));
+        assert_eq!(exit_status("print!(1); 256 + 2"), (2, "1\n".to_string()));
+        assert_eq!(exit_status("fn main() -> u8 { print!(2); return 7 }"), (7, "2\n".to_string()));
+        assert_eq!(exit_status("exit!(4); print!(5)"), (4, "".to_string()));
+        assert_eq!(exit_status("fn main() { exit!(300) }"), (44, "".to_string()));
+    }
+
+    #[test]
+    fn arguments_and_environment_are_nul_terminated() {
+        let ir = compile("print!(argc!()); print!(load!(arg!(1), 1)); print!(load!(arg!(1) + 2, 1)); print!(load!(env!(0), 1))");
+        let arguments = ["dang".to_string(), "ab".to_string()];
+        let mut output = vec![];
+        assert_eq!(Simulator::new(&ir, &arguments, &["X=1".to_string()]).run(&mut output), 0);
+        assert_eq!(String::from_utf8(output).unwrap(), "2\n97\n0\n88\n");
+    }
+
+    #[test]
+    fn returning_outside_of_a_function_fails() {
+        // `Ir::verify` rejects this, the simulator should not crash on it
+        // either
+        let ir = Ir::parse("enter 0\npush_int 1\nreturn\n").unwrap();
+        let exit = Simulator::new(&ir, &[], &[]).run_until_exit(&mut vec![]).err().unwrap();
+        assert_eq!(exit.message.as_deref(), Some("error: `return` outside of a function"));
+    }
+}
@@ -0,0 +1,812 @@
+use crate::cfg::*;
+use crate::ir::*;
+use crate::peephole::*;
+use crate::regalloc::*;
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
+use std::io::Result;
+use std::io::Write;
+
+/// A virtual register, each one is assigned exactly once.
+pub type SsaRegister = usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SsaOperation {
+    // a stack instruction, the values it pops are the arguments in the
+    // order they were pushed and the value it pushes is the result
+    Instruction(IrInstructionType),
+    // the result is the argument
+    Move
+}
+
+impl SsaOperation {
+    // whether it does nothing but compute its result from its arguments, so
+    // it can go when nothing uses the result and be done once for the same
+    // arguments
+    pub fn is_pure(self) -> bool {
+        match self {
+            SsaOperation::Move                          => true,
+            SsaOperation::Instruction(instruction_type) => instruction_type.is_pure()
+        }
+    }
+
+    // reads cannot be shared because memory changes
+    pub fn is_removable(self) -> bool {
+        match self {
+            SsaOperation::Move                          => true,
+            SsaOperation::Instruction(instruction_type) => instruction_type.is_removable()
+        }
+    }
+
+    fn is_commutative(self) -> bool {
+        use IrInstructionType::*;
+        matches!(self, SsaOperation::Instruction(Plus | Multiplication | Equal | NotEqual))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SsaInstruction {
+    pub result: Option<SsaRegister>,
+    pub operation: SsaOperation,
+    pub operand: i64,
+    pub arguments: Vec<SsaRegister>
+}
+
+/// A jump to `block`, which gets `arguments` as its parameters.
+#[derive(Debug, Clone)]
+pub struct SsaTarget {
+    pub block: usize,
+    pub arguments: Vec<SsaRegister>
+}
+
+#[derive(Debug, Clone)]
+pub enum SsaTerminator {
+    Jump(SsaTarget),
+    // goes to `then` unless `condition` is 0
+    Branch { condition: SsaRegister, then: SsaTarget, otherwise: SsaTarget },
+    Return(SsaRegister),
+    // exits the process with the status in the register, the end of the
+    // program exits with 0
+    Exit(SsaRegister)
+}
+
+impl SsaTerminator {
+    pub fn targets(&self) -> Vec<&SsaTarget> {
+        match self {
+            SsaTerminator::Jump(target)                       => vec![target],
+            SsaTerminator::Branch { then, otherwise, .. }     => vec![then, otherwise],
+            SsaTerminator::Return(_) | SsaTerminator::Exit(_) => vec![]
+        }
+    }
+
+    fn targets_mut(&mut self) -> Vec<&mut SsaTarget> {
+        match self {
+            SsaTerminator::Jump(target)                       => vec![target],
+            SsaTerminator::Branch { then, otherwise, .. }     => vec![then, otherwise],
+            SsaTerminator::Return(_) | SsaTerminator::Exit(_) => vec![]
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SsaBlock {
+    // of its first instruction in the stack `Ir`, which names it
+    pub address: usize,
+    // the values on the stack when the block starts
+    pub parameters: Vec<SsaRegister>,
+    pub instructions: Vec<SsaInstruction>,
+    pub terminator: SsaTerminator
+}
+
+/// The top level statements or a function, the first block is where it
+/// starts.
+#[derive(Debug, Clone)]
+pub struct SsaFunction {
+    // index in `Ssa::ir_functions`, `None` for the top level statements
+    pub function: Option<usize>,
+    pub blocks: Vec<SsaBlock>,
+    pub registers: usize,
+    // bytes of locals its `Enter` sets up, spilled registers go after them
+    pub frame_size: i64
+}
+
+/// The stack `Ir` in static single assignment form, where values live in
+/// virtual registers instead of on the stack.
+#[derive(Debug, Clone)]
+pub struct Ssa {
+    // the top level statements and then every function in the order of
+    // `ir_functions`
+    pub functions: Vec<SsaFunction>,
+    pub ir_functions: Vec<IrFunction>
+}
+
+// -=-=-=-= begin lowering =-=-=-=-
+
+// `depths` are what `Ir::verify` computed
+fn lower_function(ir: &Ir, depths: &[Option<usize>], function: Option<usize>, entry: usize) -> std::result::Result<SsaFunction, String> {
+    let length = ir.operations.len();
+
+    // the registers go after the locals, so nothing can use them before the
+    // frame is set up
+    let mut address = entry;
+    let mut jumped = HashSet::new();
+    let frame_size = loop {
+        match ir.operations.get(address) {
+            None => break 0,
+            Some(operation) if operation.instruction_type == IrInstructionType::Enter => break operation.operand,
+            Some(operation) if operation.instruction_type == IrInstructionType::Jump && jumped.insert(address) => {
+                address = operation.operand as usize;
+            }
+            Some(_) => {
+                return Err(match function {
+                    Some(function) => format!("function `{}` does not start with `enter`", ir.functions[function].name),
+                    None           => "the program does not start with `enter`".to_string()
+                });
+            }
+        }
+    };
+
+    let mut reachable = vec![false; length + 1];
+    let mut work = vec![entry];
+    while let Some(address) = work.pop() {
+        if !reachable[address] {
+            reachable[address] = true;
+            if address < length {
+                work.extend(successors(ir, address));
+            }
+        }
+    }
+
+    // blocks start wherever control flow can come from somewhere else than
+    // the instruction before
+    let mut leaders = vec![false; length + 1];
+    leaders[entry] = true;
+    leaders[length] = true;
+    for address in (0..length).filter(|x| reachable[*x]) {
+        let operation = &ir.operations[address];
+        match operation.instruction_type {
+            IrInstructionType::Jump | IrInstructionType::JumpIfFalse => {
+                leaders[operation.operand as usize] = true;
+                leaders[address + 1] = true;
+            }
+            IrInstructionType::Return | IrInstructionType::Exit => leaders[address + 1] = true,
+            _ => {}
+        }
+    }
+
+    let mut registers = 0;
+    let mut fresh = || {
+        registers += 1;
+        registers - 1
+    };
+
+    let starts = std::iter::once(entry).chain((0..=length).filter(|x| *x != entry && reachable[*x] && leaders[*x]));
+    let mut blocks = vec![];
+    for start in starts {
+        let depth = if start == length { 0 } else { depths[start].unwrap() };
+        let parameters: Vec<SsaRegister> = (0..depth).map(|_| fresh()).collect();
+        let mut stack = parameters.clone();
+        let mut instructions = vec![];
+
+        let mut address = start;
+        let terminator = loop {
+            if address == length {
+                if start != length {
+                    break SsaTerminator::Jump(SsaTarget { block: address, arguments: stack });
+                }
+                let status = fresh();
+                instructions.push(SsaInstruction {
+                    result: Some(status),
+                    operation: SsaOperation::Instruction(IrInstructionType::PushInt),
+                    operand: 0,
+                    arguments: vec![]
+                });
+                break SsaTerminator::Exit(status);
+            }
+            if address != start && leaders[address] {
+                break SsaTerminator::Jump(SsaTarget { block: address, arguments: stack });
+            }
+
+            let operation = &ir.operations[address];
+            match operation.instruction_type {
+                IrInstructionType::Jump => {
+                    break SsaTerminator::Jump(SsaTarget { block: operation.operand as usize, arguments: stack });
+                }
+                IrInstructionType::JumpIfFalse => {
+                    let condition = stack.pop().unwrap();
+                    break SsaTerminator::Branch {
+                        condition,
+                        then: SsaTarget { block: address + 1, arguments: stack.clone() },
+                        otherwise: SsaTarget { block: operation.operand as usize, arguments: stack }
+                    };
+                }
+                IrInstructionType::Return => break SsaTerminator::Return(stack.pop().unwrap()),
+                IrInstructionType::Exit   => break SsaTerminator::Exit(stack.pop().unwrap()),
+                IrInstructionType::Drop => {
+                    stack.pop();
+                }
+                // the checked index stays where it is
+                IrInstructionType::BoundsCheck => instructions.push(SsaInstruction {
+                    result: None,
+                    operation: SsaOperation::Instruction(operation.instruction_type),
+                    operand: operation.operand,
+                    arguments: vec![*stack.last().unwrap()]
+                }),
+                instruction_type => {
+                    let (pops, pushes) = operation.stack_effect(&ir.functions);
+                    let arguments = stack.split_off(stack.len() - pops);
+                    let result = (pushes == 1).then(&mut fresh);
+                    stack.extend(result);
+                    instructions.push(SsaInstruction {
+                        result,
+                        operation: SsaOperation::Instruction(instruction_type),
+                        operand: operation.operand,
+                        arguments
+                    });
+                }
+            }
+            address += 1;
+        };
+
+        blocks.push(SsaBlock { address: start, parameters, instructions, terminator });
+    }
+
+    Ok(SsaFunction { function, blocks, registers, frame_size })
+}
+
+impl Ssa {
+    // the program and every function have to set up their frame with
+    // `Enter` before anything else
+    pub fn from_ir(ir: &Ir) -> std::result::Result<Ssa, String> {
+        let depths = ir.verify().map_err(|error| error.to_string())?;
+
+        let mut functions = vec![lower_function(ir, &depths, None, 0)?];
+        for (index, function) in ir.functions.iter().enumerate() {
+            functions.push(lower_function(ir, &depths, Some(index), function.address)?);
+        }
+        Ok(Ssa { functions, ir_functions: ir.functions.clone() })
+    }
+
+    pub fn optimize(&mut self) {
+        for function in &mut self.functions {
+            // every round of copies can make more operations the same
+            function.propagate_copies();
+            while function.eliminate_common_subexpressions() {
+                function.propagate_copies();
+            }
+            function.eliminate_dead_code();
+        }
+    }
+}
+
+// -=-=-=-= end lowering =-=-=-=-
+
+// -=-=-=-= begin passes =-=-=-=-
+
+impl SsaFunction {
+    pub fn block(&self, address: usize) -> &SsaBlock {
+        self.blocks.iter().find(|x| x.address == address).unwrap()
+    }
+
+    fn for_each_use_mut(&mut self, mut f: impl FnMut(&mut SsaRegister)) {
+        for block in &mut self.blocks {
+            for instruction in &mut block.instructions {
+                instruction.arguments.iter_mut().for_each(&mut f);
+            }
+            match &mut block.terminator {
+                SsaTerminator::Branch { condition, .. }                   => f(condition),
+                SsaTerminator::Return(value) | SsaTerminator::Exit(value) => f(value),
+                SsaTerminator::Jump(_)                                    => {}
+            }
+            for target in block.terminator.targets_mut() {
+                target.arguments.iter_mut().for_each(&mut f);
+            }
+        }
+    }
+
+    // every value a parameter gets, by block and index
+    fn incoming(&self) -> HashMap<(usize, usize), Vec<SsaRegister>> {
+        let mut incoming: HashMap<(usize, usize), Vec<SsaRegister>> = HashMap::new();
+        for block in &self.blocks {
+            for target in block.terminator.targets() {
+                for (index, argument) in target.arguments.iter().enumerate() {
+                    incoming.entry((target.block, index)).or_default().push(*argument);
+                }
+            }
+        }
+        incoming
+    }
+
+    // `removed` has the indices of the parameters to remove by block, the
+    // jumps to it stop passing them
+    fn remove_parameters(&mut self, removed: &HashMap<usize, Vec<usize>>) {
+        let keep = |block: usize, index: usize| !removed.get(&block).is_some_and(|x| x.contains(&index));
+        for block in &mut self.blocks {
+            let address = block.address;
+            let mut index = 0;
+            block.parameters.retain(|_| {
+                index += 1;
+                keep(address, index - 1)
+            });
+            for target in block.terminator.targets_mut() {
+                let mut index = 0;
+                target.arguments.retain(|_| {
+                    index += 1;
+                    keep(target.block, index - 1)
+                });
+            }
+        }
+    }
+
+    // uses the argument of every `Move` and the value of every parameter that
+    // only ever gets one directly instead
+    pub fn propagate_copies(&mut self) {
+        loop {
+            let mut copies = HashMap::new();
+            for instruction in self.blocks.iter().flat_map(|x| &x.instructions) {
+                if instruction.operation == SsaOperation::Move {
+                    copies.insert(instruction.result.unwrap(), instruction.arguments[0]);
+                }
+            }
+
+            let incoming = self.incoming();
+            let mut removed: HashMap<usize, Vec<usize>> = HashMap::new();
+            for block in &self.blocks {
+                for (index, parameter) in block.parameters.iter().enumerate() {
+                    // a loop can pass a parameter back to itself
+                    let values: HashSet<SsaRegister> = incoming.get(&(block.address, index)).into_iter().flatten()
+                        .copied()
+                        .filter(|x| x != parameter)
+                        .collect();
+                    if values.len() == 1 {
+                        copies.insert(*parameter, *values.iter().next().unwrap());
+                        removed.entry(block.address).or_default().push(index);
+                    }
+                }
+            }
+
+            if copies.is_empty() {
+                break;
+            }
+
+            for block in &mut self.blocks {
+                block.instructions.retain(|x| x.operation != SsaOperation::Move);
+            }
+            self.remove_parameters(&removed);
+            // copies of copies go all the way back, a cycle only happens in
+            // blocks nothing reaches
+            self.for_each_use_mut(|register| {
+                for _ in 0..copies.len() {
+                    match copies.get(register) {
+                        Some(copy) => *register = *copy,
+                        None       => break
+                    }
+                }
+            });
+        }
+    }
+
+    // within a block a pure operation on the same arguments gives the same
+    // result, so only the first one is computed and the others are moves,
+    // returns whether there were any
+    pub fn eliminate_common_subexpressions(&mut self) -> bool {
+        let mut changed = false;
+        for block in &mut self.blocks {
+            let mut computed: HashMap<(SsaOperation, i64, Vec<SsaRegister>), SsaRegister> = HashMap::new();
+            for instruction in &mut block.instructions {
+                let Some(result) = instruction.result else {
+                    continue;
+                };
+                if !instruction.operation.is_pure() || instruction.operation == SsaOperation::Move {
+                    continue;
+                }
+
+                let mut arguments = instruction.arguments.clone();
+                if instruction.operation.is_commutative() {
+                    arguments.sort();
+                }
+                match computed.get(&(instruction.operation, instruction.operand, arguments.clone())) {
+                    Some(&earlier) => {
+                        *instruction = SsaInstruction { result: Some(result), operation: SsaOperation::Move, operand: 0, arguments: vec![earlier] };
+                        changed = true;
+                    }
+                    None => {
+                        computed.insert((instruction.operation, instruction.operand, arguments), result);
+                    }
+                }
+            }
+        }
+        changed
+    }
+
+    // removes the blocks nothing jumps to and whatever only computes values
+    // nothing uses, parameters included
+    pub fn eliminate_dead_code(&mut self) {
+        let mut reachable = HashSet::new();
+        let mut work = vec![self.blocks[0].address];
+        while let Some(address) = work.pop() {
+            if reachable.insert(address) {
+                work.extend(self.block(address).terminator.targets().iter().map(|x| x.block));
+            }
+        }
+        self.blocks.retain(|x| reachable.contains(&x.address));
+
+        // what each register is computed from
+        let incoming = self.incoming();
+        let mut sources: HashMap<SsaRegister, Vec<SsaRegister>> = HashMap::new();
+        let mut work = vec![];
+        for block in &self.blocks {
+            for (index, parameter) in block.parameters.iter().enumerate() {
+                sources.insert(*parameter, incoming.get(&(block.address, index)).cloned().unwrap_or_default());
+            }
+            for instruction in &block.instructions {
+                match instruction.result {
+                    Some(result) if instruction.operation.is_removable() => {
+                        sources.insert(result, instruction.arguments.clone());
+                    }
+                    _ => work.extend(&instruction.arguments)
+                }
+            }
+            match block.terminator {
+                SsaTerminator::Branch { condition, .. }                   => work.push(condition),
+                SsaTerminator::Return(value) | SsaTerminator::Exit(value) => work.push(value),
+                SsaTerminator::Jump(_)                                    => {}
+            }
+        }
+
+        let mut live = HashSet::new();
+        while let Some(register) = work.pop() {
+            if live.insert(register) {
+                work.extend(sources.get(&register).into_iter().flatten());
+            }
+        }
+
+        let mut removed: HashMap<usize, Vec<usize>> = HashMap::new();
+        for block in &mut self.blocks {
+            block.instructions.retain(|x| !x.operation.is_removable() || x.result.is_some_and(|x| live.contains(&x)));
+            for (index, parameter) in block.parameters.iter().enumerate() {
+                if !live.contains(parameter) {
+                    removed.entry(block.address).or_default().push(index);
+                }
+            }
+        }
+        self.remove_parameters(&removed);
+    }
+}
+
+// -=-=-=-= end passes =-=-=-=-
+
+// -=-=-=-= begin x86_64 =-=-=-=-
+
+impl SsaFunction {
+    fn label(index: usize, address: usize) -> String {
+        format!("block_{}_{}", index, address)
+    }
+
+    // the arguments go through the stack because parameters can be passed
+    // to each other
+    fn jump_to_nasm_linux_x86_64_assembly(&self, f: &mut impl Write, allocation: &SsaAllocation, index: usize, target: &SsaTarget, next: Option<usize>) -> Result<()> {
+        for argument in &target.arguments {
+            writeln!(f, "push {}", allocation.operand(*argument))?;
+        }
+        for parameter in self.block(target.block).parameters.iter().rev() {
+            writeln!(f, "pop {}", allocation.operand(*parameter))?;
+        }
+        if next != Some(target.block) {
+            writeln!(f, "jmp {}", SsaFunction::label(index, target.block))?;
+        }
+        Ok(())
+    }
+
+    // registers live in the ones `SsaAllocation` gives them or in the frame,
+    // the `Ir` instructions compute through the stack as usual and
+    // `optimize_assembly` turns the pushes and pops around them into moves
+    fn to_nasm_linux_x86_64_assembly(&self, f: &mut impl Write, index: usize, functions: &[IrFunction]) -> Result<()> {
+        // the top level statements end with `exit`, so nobody needs their
+        // registers back
+        let allocation = SsaAllocation::allocate(self, self.function.is_some());
+
+        if let Some(function) = self.function {
+            writeln!(f, ";; -- fn {} --", functions[function].name)?;
+            writeln!(f, "addr_{}:", functions[function].address)?;
+        }
+
+        for (position, block) in self.blocks.iter().enumerate() {
+            let next = self.blocks.get(position + 1).map(|x| x.address);
+            writeln!(f, "{}:", SsaFunction::label(index, block.address))?;
+
+            for instruction in &block.instructions {
+                writeln!(f, ";; {}", instruction)?;
+                match instruction.operation {
+                    SsaOperation::Move => {
+                        let source = allocation.operand(instruction.arguments[0]);
+                        let destination = allocation.operand(instruction.result.unwrap());
+                        if source.contains('[') && destination.contains('[') {
+                            writeln!(f, "mov rax, {}", source)?;
+                            writeln!(f, "mov {}, rax", destination)?;
+                        } else if source != destination {
+                            writeln!(f, "mov {}, {}", destination, source)?;
+                        }
+                    }
+                    SsaOperation::Instruction(IrInstructionType::Enter) => {
+                        IrInstruction { instruction_type: IrInstructionType::Enter, operand: allocation.frame_size() }.to_nasm_linux_x86_64_assembly(f, functions)?;
+                        for (index, register) in allocation.saved.iter().enumerate() {
+                            writeln!(f, "mov {}, {}", allocation.save_operand(index), register)?;
+                        }
+                    }
+                    SsaOperation::Instruction(instruction_type) => {
+                        for argument in &instruction.arguments {
+                            writeln!(f, "push {}", allocation.operand(*argument))?;
+                        }
+                        IrInstruction { instruction_type, operand: instruction.operand }.to_nasm_linux_x86_64_assembly(f, functions)?;
+                        if let Some(result) = instruction.result {
+                            writeln!(f, "pop {}", allocation.operand(result))?;
+                        } else if instruction_type == IrInstructionType::BoundsCheck {
+                            writeln!(f, "add rsp, 8")?;
+                        }
+                    }
+                }
+            }
+
+            match &block.terminator {
+                SsaTerminator::Jump(target) => self.jump_to_nasm_linux_x86_64_assembly(f, &allocation, index, target, next)?,
+                SsaTerminator::Branch { condition, then, otherwise } => {
+                    let label = format!("{}_otherwise", SsaFunction::label(index, block.address));
+                    writeln!(f, "mov rax, {}", allocation.operand(*condition))?;
+                    writeln!(f, "test rax, rax")?;
+                    writeln!(f, "jz {}", label)?;
+                    self.jump_to_nasm_linux_x86_64_assembly(f, &allocation, index, then, None)?;
+                    writeln!(f, "{}:", label)?;
+                    self.jump_to_nasm_linux_x86_64_assembly(f, &allocation, index, otherwise, next)?;
+                }
+                SsaTerminator::Return(value) => {
+                    // the value can be in one of the saved registers
+                    writeln!(f, "push {}", allocation.operand(*value))?;
+                    for (index, register) in allocation.saved.iter().enumerate() {
+                        writeln!(f, "mov {}, {}", register, allocation.save_operand(index))?;
+                    }
+                    IrInstruction { instruction_type: IrInstructionType::Return, operand: 0 }.to_nasm_linux_x86_64_assembly(f, functions)?;
+                }
+                SsaTerminator::Exit(status) => {
+                    writeln!(f, "push {}", allocation.operand(*status))?;
+                    IrInstruction { instruction_type: IrInstructionType::Exit, operand: 0 }.to_nasm_linux_x86_64_assembly(f, functions)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Ssa {
+    pub fn to_nasm_linux_x86_64_assembly(&self, output: String) -> Result<()> {
+        let mut file = vec![];
+        write_nasm_runtime(&mut file)?;
+        // the top level statements come first so `_start` runs into them
+        for (index, function) in self.functions.iter().enumerate() {
+            function.to_nasm_linux_x86_64_assembly(&mut file, index, &self.ir_functions)?;
+        }
+        std::fs::write(output, optimize_assembly(&String::from_utf8(file).unwrap()))
+    }
+}
+
+// -=-=-=-= end x86_64 =-=-=-=-
+
+// -=-=-=-= begin text format =-=-=-=-
+
+fn write_registers(f: &mut fmt::Formatter, registers: &[SsaRegister]) -> fmt::Result {
+    for (index, register) in registers.iter().enumerate() {
+        let separator = if index == 0 { "" } else { ", " };
+        write!(f, "{}r{}", separator, register)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for SsaInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(result) = self.result {
+            write!(f, "r{} = ", result)?;
+        }
+        match self.operation {
+            SsaOperation::Move => write!(f, "move")?,
+            SsaOperation::Instruction(instruction_type) => {
+                write!(f, "{}", instruction_type.name())?;
+                if instruction_type.has_operand() {
+                    write!(f, " {}", self.operand)?;
+                }
+            }
+        }
+        if !self.arguments.is_empty() {
+            write!(f, " ")?;
+            write_registers(f, &self.arguments)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for SsaTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "block_{}(", self.block)?;
+        write_registers(f, &self.arguments)?;
+        write!(f, ")")
+    }
+}
+
+impl fmt::Display for SsaTerminator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SsaTerminator::Jump(target) => write!(f, "jump {}", target),
+            SsaTerminator::Branch { condition, then, otherwise } => write!(f, "branch r{} {} {}", condition, then, otherwise),
+            SsaTerminator::Return(value) => write!(f, "return r{}", value),
+            SsaTerminator::Exit(status) => write!(f, "exit r{}", status)
+        }
+    }
+}
+
+impl fmt::Display for Ssa {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for function in &self.functions {
+            match function.function {
+                Some(index) => writeln!(f, "fn {}:", self.ir_functions[index].name)?,
+                None        => writeln!(f, "program:")?
+            }
+            for block in &function.blocks {
+                write!(f, "  block_{}(", block.address)?;
+                write_registers(f, &block.parameters)?;
+                writeln!(f, "):")?;
+                for instruction in &block.instructions {
+                    writeln!(f, "    {}", instruction)?;
+                }
+                writeln!(f, "    {}", block.terminator)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// -=-=-=-= end text format =-=-=-=-
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    // `source` in optimized SSA form
+    fn optimize(source: &str) -> String {
+        let mut ssa = Ssa::from_ir(&Ir::parse(source).unwrap()).unwrap();
+        ssa.optimize();
+        ssa.to_string()
+    }
+
+    #[test]
+    fn common_subexpressions_are_computed_once() {
+        let source = "enter 0\npush_int 3\npush_int 4\nplus\nprint\npush_int 4\npush_int 3\nplus\nprint\n";
+        assert_eq!(optimize(source), "\
+program:
+  block_0():
+    enter 0
+    r0 = push_int 3
+    r1 = push_int 4
+    r2 = plus r0, r1
+    print r2
+    print r2
+    jump block_9()
+  block_9():
+    r6 = push_int 0
+    exit r6
+");
+    }
+
+    #[test]
+    fn copies_are_propagated_through_parameters() {
+        // both ways into `block_5` pass the same value
+        let source = "enter 8\npush_int 5\nload_local -8\njump_if_false 5\njump 5\nprint\n";
+        assert_eq!(optimize(source), "\
+program:
+  block_0():
+    enter 8
+    r0 = push_int 5
+    r1 = load_local -8
+    branch r1 block_4() block_5()
+  block_4():
+    jump block_5()
+  block_5():
+    print r0
+    jump block_6()
+  block_6():
+    r4 = push_int 0
+    exit r4
+");
+    }
+
+    fn instruction(result: Option<SsaRegister>, instruction_type: IrInstructionType, operand: i64, arguments: Vec<SsaRegister>) -> SsaInstruction {
+        SsaInstruction { result, operation: SsaOperation::Instruction(instruction_type), operand, arguments }
+    }
+
+    fn jump(block: usize, arguments: Vec<SsaRegister>) -> SsaTerminator {
+        SsaTerminator::Jump(SsaTarget { block, arguments })
+    }
+
+    // a loop that swaps its two parameters every time around, which the
+    // stack `Ir` cannot express
+    pub fn swapping_loop() -> SsaFunction {
+        use IrInstructionType::*;
+        let blocks = vec![
+            SsaBlock { address: 0, parameters: vec![], instructions: vec![
+                instruction(None, Enter, 0, vec![]),
+                instruction(Some(0), PushInt, 1, vec![]),
+                instruction(Some(1), PushInt, 2, vec![])
+            ], terminator: jump(1, vec![0, 1]) },
+            SsaBlock { address: 1, parameters: vec![2, 3], instructions: vec![
+                instruction(Some(4), Argc, 0, vec![])
+            ], terminator: SsaTerminator::Branch {
+                condition: 4,
+                then: SsaTarget { block: 2, arguments: vec![] },
+                otherwise: SsaTarget { block: 3, arguments: vec![] }
+            } },
+            SsaBlock { address: 2, parameters: vec![], instructions: vec![], terminator: jump(1, vec![3, 2]) },
+            SsaBlock { address: 3, parameters: vec![], instructions: vec![
+                instruction(None, Print, 0, vec![2]),
+                instruction(Some(5), PushInt, 0, vec![])
+            ], terminator: SsaTerminator::Exit(5) }
+        ];
+        SsaFunction { function: None, blocks, registers: 6, frame_size: 0 }
+    }
+
+    #[test]
+    fn parameters_passed_to_each_other_are_kept() {
+        let mut ssa = Ssa { functions: vec![swapping_loop()], ir_functions: vec![] };
+        let before = ssa.to_string();
+        ssa.optimize();
+        assert_eq!(ssa.to_string(), before);
+        assert_eq!(before, "\
+program:
+  block_0():
+    enter 0
+    r0 = push_int 1
+    r1 = push_int 2
+    jump block_1(r0, r1)
+  block_1(r2, r3):
+    r4 = argc
+    branch r4 block_2() block_3()
+  block_2():
+    jump block_1(r3, r2)
+  block_3():
+    print r2
+    r5 = push_int 0
+    exit r5
+");
+    }
+
+    #[test]
+    fn parameters_passed_to_each_other_are_swapped() {
+        let function = swapping_loop();
+        let allocation = SsaAllocation::allocate(&function, false);
+        let (first, second) = (allocation.operand(2), allocation.operand(3));
+        assert_ne!(first, second);
+
+        let mut assembly = vec![];
+        function.to_nasm_linux_x86_64_assembly(&mut assembly, 0, &[]).unwrap();
+        let assembly = optimize_assembly(&String::from_utf8(assembly).unwrap());
+        let swap = format!("block_0_2:\npush {}\nmov {}, {}\npop {}\n", second, second, first, first);
+        assert!(assembly.contains(&swap), "{}", assembly);
+    }
+
+    #[test]
+    fn unused_values_are_removed() {
+        let source = "enter 8\npush_int 1\npush_int 2\nplus\ndrop\nload_local -8\ndrop\nargc\nprint\npush_int 7\nload_local -8\nstore_local -8\ndrop\n";
+        assert_eq!(optimize(source), "\
+program:
+  block_0():
+    enter 8
+    r4 = argc
+    print r4
+    r6 = load_local -8
+    store_local -8 r6
+    jump block_13()
+  block_13():
+    r7 = push_int 0
+    exit r7
+");
+    }
+}
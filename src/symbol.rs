@@ -0,0 +1,131 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::*;
+
+/// Handle to a string stored in the interner, comparing two symbols never
+/// touches the strings themselves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
+
+// names the compiler looks up itself, interned up front so they can be
+// matched against as constants
+const PREDEFINED: &[&str] = &[
+    "print",
+    "true",
+    "false",
+    "if",
+    "else",
+    "as",
+    "let",
+    "fn",
+    "return",
+    "struct",
+    "load",
+    "store",
+    "syscall",
+    "exit",
+    "main",
+    "read_int",
+    "read_line",
+    "argc",
+    "arg",
+    "env",
+    "inline",
+    "noinline"
+];
+
+impl Symbol {
+    pub const PRINT: Symbol = Symbol(0);
+    pub const TRUE:  Symbol = Symbol(1);
+    pub const FALSE: Symbol = Symbol(2);
+    pub const IF:    Symbol = Symbol(3);
+    pub const ELSE:  Symbol = Symbol(4);
+    pub const AS:    Symbol = Symbol(5);
+    pub const LET:   Symbol = Symbol(6);
+    pub const FN:    Symbol = Symbol(7);
+    pub const RETURN: Symbol = Symbol(8);
+    pub const STRUCT: Symbol = Symbol(9);
+    pub const LOAD:  Symbol = Symbol(10);
+    pub const STORE: Symbol = Symbol(11);
+    pub const SYSCALL: Symbol = Symbol(12);
+    pub const EXIT:  Symbol = Symbol(13);
+    pub const MAIN:  Symbol = Symbol(14);
+    pub const READ_INT: Symbol = Symbol(15);
+    pub const READ_LINE: Symbol = Symbol(16);
+    pub const ARGC:  Symbol = Symbol(17);
+    pub const ARG:   Symbol = Symbol(18);
+    pub const ENV:   Symbol = Symbol(19);
+    pub const INLINE: Symbol = Symbol(20);
+    pub const NOINLINE: Symbol = Symbol(21);
+
+    pub fn intern(name: &str) -> Symbol {
+        INTERNER.with(|interner| interner.borrow_mut().intern(name))
+    }
+
+    pub fn as_str(self) -> &'static str {
+        INTERNER.with(|interner| interner.borrow().strings[self.0 as usize])
+    }
+}
+
+impl Display for Symbol {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+struct Interner {
+    names: HashMap<&'static str, Symbol>,
+    strings: Vec<&'static str>
+}
+
+impl Interner {
+    fn new() -> Interner {
+        let mut interner = Interner {
+            names: HashMap::new(),
+            strings: vec![]
+        };
+
+        for name in PREDEFINED {
+            interner.intern(name);
+        }
+
+        interner
+    }
+
+    fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(symbol) = self.names.get(name) {
+            return *symbol;
+        }
+
+        // interned strings live as long as the compiler does, leaking them
+        // is what lets `as_str` hand out plain `&'static str`s
+        let name: &'static str = Box::leak(name.to_string().into_boxed_str());
+        let symbol = Symbol(self.strings.len() as u32);
+        self.strings.push(name);
+        self.names.insert(name, symbol);
+        symbol
+    }
+}
+
+thread_local! {
+    static INTERNER: RefCell<Interner> = RefCell::new(Interner::new());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interning_the_same_name_gives_the_same_symbol() {
+        let foo = Symbol::intern("foo");
+        assert_eq!(Symbol::intern(&format!("f{}", "oo")), foo);
+        assert_ne!(Symbol::intern("bar"), foo);
+        assert_eq!(foo.as_str(), "foo");
+    }
+
+    #[test]
+    fn predefined_names_are_interned_up_front() {
+        assert_eq!(Symbol::intern("print"), Symbol::PRINT);
+        assert_eq!(Symbol::PRINT.to_string(), "print");
+    }
+}
@@ -0,0 +1,978 @@
+use crate::ast::*;
+use crate::diagnostic::*;
+use crate::layout::*;
+use crate::lexer::*;
+use crate::symbol::*;
+use crate::types::*;
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+struct DangSignature {
+    parameters: Vec<DangType>,
+    return_type: DangType
+}
+
+struct DangScopeVariable {
+    ty: DangType,
+    offset: i64
+}
+
+/// Assigns a `DangType` to every statement of a `DangAst` and reports the
+/// ones that are used where another type is expected, it runs before the
+/// AST is turned into `Ir`. It also resolves names, giving every variable
+/// its place in the frame and every call the function it calls.
+pub struct TypeChecker {
+    pub diagnostics: Vec<Diagnostic>,
+    signatures: Vec<DangSignature>,
+    functions: HashMap<DangName, usize>,
+    structs: HashSet<DangName>,
+    scopes: Vec<HashMap<DangName, DangScopeVariable>>,
+    frame_size: i64,
+    // `None` outside of functions
+    return_type: Option<DangType>
+}
+
+// integer literals, possibly negated or in parentheses, take the type of
+// whatever they are used with instead of forcing their own
+fn is_integer_literal(statement: &DangStatement) -> bool {
+    use DangStatementKind::*;
+    match &statement.kind {
+        Number(_) => true,
+        Expression(expression) => is_integer_literal(&expression.symbols[0]),
+        UnaryOperation(unary_operation) => {
+            unary_operation.unary_operation_type == DangUnaryOperationType::Negate
+                && is_integer_literal(&unary_operation.operand)
+        }
+        _ => false
+    }
+}
+
+// whether `statement` is somewhere in memory that can be assigned to, it
+// has to be checked already
+fn is_place(statement: &DangStatement) -> bool {
+    use DangStatementKind::*;
+    match &statement.kind {
+        Name(_) | Index(_) | Dereference(_) => true,
+        // a field of a struct behind a pointer is in memory even if the
+        // pointer is not
+        FieldAccess(field_access) => matches!(field_access.base.ty, DangType::Pointer(_)) || is_place(&field_access.base),
+        Expression(expression) => is_place(&expression.symbols[0]),
+        _ => false
+    }
+}
+
+// structs `ty` contains directly, which have to be laid out before it
+fn contained_structs(ty: DangType) -> Vec<DangName> {
+    match ty {
+        DangType::Struct(name)      => vec![name],
+        DangType::Array(element, _) => contained_structs(*element),
+        _                           => vec![]
+    }
+}
+
+// whether every path through `block` ends in a `return`
+fn block_returns(block: &DangBlock) -> bool {
+    block.symbols.iter().any(|symbol| match &symbol.kind {
+        DangStatementKind::Return(_) => true,
+        DangStatementKind::Block(block) => block_returns(block),
+        DangStatementKind::If(if_statement) => {
+            block_returns(&if_statement.then_block)
+                && if_statement.else_block.as_ref().is_some_and(block_returns)
+        }
+        _ => false
+    })
+}
+
+impl TypeChecker {
+    pub fn new() -> TypeChecker {
+        TypeChecker {
+            diagnostics: vec![],
+            signatures: vec![],
+            functions: HashMap::new(),
+            structs: HashSet::new(),
+            scopes: vec![],
+            frame_size: 0,
+            return_type: None
+        }
+    }
+
+    pub fn check_ast(ast: &mut DangAst) -> Result<(), Vec<Diagnostic>> {
+        let mut checker = TypeChecker::new();
+
+        for struct_declaration in &ast.structs {
+            if !checker.structs.insert(struct_declaration.name) {
+                let message = format!("struct `{}` is declared more than once", struct_declaration.name);
+                checker.diagnostics.push(Diagnostic::new(struct_declaration.span, message));
+            }
+        }
+        let mut laid_out = HashMap::new();
+        for i in 0..ast.structs.len() {
+            checker.lay_out_struct(&mut ast.structs, i, &mut laid_out);
+        }
+
+        // every signature is known before any body is checked, so functions
+        // can call the ones declared after them
+        for function in &mut ast.functions {
+            checker.declare_function(function);
+        }
+
+        for function in &mut ast.functions {
+            checker.check_function(function);
+        }
+
+        checker.scopes = vec![HashMap::new()];
+        checker.frame_size = 0;
+        checker.return_type = None;
+        let main = checker.check_main(ast);
+        let count = ast.ast.len();
+        for (i, node) in ast.ast.iter_mut().enumerate() {
+            if i + 1 == count && main.is_none() {
+                ast.exit_status = checker.check_last_statement(node);
+            } else {
+                checker.check_unused(node);
+            }
+        }
+        if let Some(main) = main {
+            ast.exit_status = DangExitStatus::Main(main);
+        }
+        ast.frame_size = checker.frame_size;
+
+        if checker.diagnostics.is_empty() {
+            Ok(())
+        } else {
+            Err(checker.diagnostics)
+        }
+    }
+
+    // every struct a type names has to be declared, the ones that are not
+    // are reported and the type becomes `Unknown`
+    fn check_type(&mut self, span: LexerSpan, ty: DangType) -> DangType {
+        match ty {
+            DangType::Struct(name) if !self.structs.contains(&name) => {
+                self.diagnostics.push(Diagnostic::new(span, format!("cannot find type `{}` in this scope", name)));
+                DangType::Unknown
+            }
+            DangType::Array(element, length) => match self.check_type(span, *element) {
+                DangType::Unknown => DangType::Unknown,
+                element           => DangType::array(element, length)
+            }
+            DangType::Pointer(pointee) => match self.check_type(span, *pointee) {
+                DangType::Unknown => DangType::Unknown,
+                pointee           => DangType::pointer(pointee)
+            }
+            _ => ty
+        }
+    }
+
+    // lays out the structs a struct contains before the struct itself,
+    // `laid_out` is false for the ones being laid out so a struct that
+    // contains itself can be told apart from one that is used twice
+    fn lay_out_struct(&mut self, structs: &mut [DangStructDeclaration], index: usize, laid_out: &mut HashMap<DangName, bool>) {
+        let name = structs[index].name;
+        if laid_out.contains_key(&name) {
+            return;
+        }
+        laid_out.insert(name, false);
+
+        let mut fields = vec![];
+        for i in 0..structs[index].fields.len() {
+            let field = structs[index].fields[i].clone();
+            let mut ty = self.check_type(field.span, field.annotation);
+
+            for contained in contained_structs(ty) {
+                match laid_out.get(&contained) {
+                    Some(false) => {
+                        let message = format!("struct `{}` contains itself, put it behind a pointer instead", contained);
+                        self.diagnostics.push(Diagnostic::new(field.span, message));
+                        ty = DangType::Unknown;
+                    }
+                    Some(true) => {}
+                    None => {
+                        let contained = structs.iter().position(|x| x.name == contained).unwrap();
+                        self.lay_out_struct(structs, contained, laid_out);
+                    }
+                }
+            }
+
+            if fields.iter().any(|&(x, _)| x == field.name) {
+                let message = format!("field `{}` is declared more than once", field.name);
+                self.diagnostics.push(Diagnostic::new(field.span, message));
+                continue;
+            }
+            structs[index].fields[i].annotation = ty;
+            fields.push((field.name, ty));
+        }
+
+        DangLayout::define_struct(name, &fields);
+        laid_out.insert(name, true);
+    }
+
+    fn declare_function(&mut self, function: &mut DangFunction) {
+        if self.functions.contains_key(&function.name) {
+            let message = format!("function `{}` is declared more than once", function.name);
+            self.diagnostics.push(Diagnostic::new(function.span, message));
+        }
+
+        let mut parameters = vec![];
+        for param in &function.parameters {
+            match param.annotation {
+                Some(annotation) => parameters.push(self.check_type(param.span, annotation)),
+                None => {
+                    let message = format!("parameter `{}` needs a type annotation", param.variable);
+                    self.diagnostics.push(Diagnostic::new(param.span, message));
+                    parameters.push(DangType::Unknown);
+                }
+            }
+        }
+
+        let return_type = self.check_type(function.span, function.return_type.unwrap_or(DangType::Unit));
+        if return_type.is_aggregate() {
+            let message = format!("function `{}` cannot return a value of type `{}`", function.name, return_type);
+            self.diagnostics.push(Diagnostic::new(function.span, message));
+        }
+
+        self.functions.insert(function.name, self.signatures.len());
+        self.signatures.push(DangSignature { parameters, return_type });
+    }
+
+    // arguments are pushed in order before the return address and the saved
+    // frame pointer, so the last one is the closest to the frame
+    fn check_function(&mut self, function: &mut DangFunction) {
+        let index = self.functions[&function.name];
+        let return_type = self.signatures[index].return_type;
+
+        self.scopes = vec![HashMap::new()];
+        self.frame_size = 0;
+        self.return_type = Some(return_type);
+
+        let count = function.parameters.len() as i64;
+        for (i, param) in function.parameters.iter_mut().enumerate() {
+            param.ty = self.signatures[index].parameters[i];
+            param.argument_offset = 16 + 8 * (count - 1 - i as i64);
+            // aggregates get copied into the frame by the function itself
+            param.variable.offset = if param.ty.is_aggregate() {
+                self.declare_variable(param.variable.name, param.ty)
+            } else {
+                let variable = DangScopeVariable { ty: param.ty, offset: param.argument_offset };
+                self.scopes[0].insert(param.variable.name, variable);
+                param.argument_offset
+            };
+        }
+
+        self.check_block(&mut function.body);
+        function.frame_size = self.frame_size;
+
+        if return_type != DangType::Unit && !block_returns(&function.body) {
+            let message = format!("function `{}` does not return a `{}` on every path", function.name, return_type);
+            self.diagnostics.push(Diagnostic::new(function.span, message));
+        }
+    }
+
+    // every region starts 8 bytes aligned, scalars always take a whole slot
+    fn allocate(&mut self, ty: DangType) -> i64 {
+        self.frame_size += if ty.is_aggregate() { (ty.size() + 7) / 8 * 8 } else { 8 };
+        -self.frame_size
+    }
+
+    // `main` gives the exit status, so it can only return an integer or
+    // nothing
+    fn check_main(&mut self, ast: &DangAst) -> Option<usize> {
+        let index = *self.functions.get(&Symbol::MAIN)?;
+        let function = ast.functions.iter().find(|x| x.name == Symbol::MAIN).unwrap();
+        let signature = &self.signatures[index];
+        if !signature.parameters.is_empty() || !matches!(signature.return_type, DangType::Integer(_) | DangType::Unit | DangType::Unknown) {
+            let message = "`main` can only be declared as `fn main()` or `fn main() -> T` with an integer `T`".to_string();
+            self.diagnostics.push(Diagnostic::new(function.span, message));
+        }
+        Some(index)
+    }
+
+    // the last top level statement is the exit status when it is an integer
+    fn check_last_statement(&mut self, statement: &mut DangStatement) -> DangExitStatus {
+        let found = self.check_statement(statement, None);
+        if let DangType::Integer(_) = found {
+            return DangExitStatus::LastStatement;
+        }
+        self.report_unused(statement, found);
+        DangExitStatus::Zero
+    }
+
+    fn declare_variable(&mut self, name: DangName, ty: DangType) -> i64 {
+        let offset = self.allocate(ty);
+        self.scopes.last_mut().unwrap().insert(name, DangScopeVariable { ty, offset });
+        offset
+    }
+
+    fn lookup_variable(&self, name: DangName) -> Option<&DangScopeVariable> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(&name))
+    }
+
+    fn report(&mut self, statement: &DangStatement, message: String) {
+        self.diagnostics.push(Diagnostic::new(statement.span, message));
+    }
+
+    fn expect_type(&mut self, statement: &mut DangStatement, expected: DangType) {
+        let found = self.check_statement(statement, Some(expected));
+        if !found.is_compatible(expected) {
+            self.report(statement, format!("mismatched types: expected `{}`, found `{}`", expected, found));
+        }
+    }
+
+    // statements whose value is thrown away, only `()` may be unless it
+    // comes from calling a function or a built-in other than `load!`, which
+    // are often done for their effects
+    fn check_unused(&mut self, statement: &mut DangStatement) {
+        let found = self.check_statement(statement, None);
+        self.report_unused(statement, found);
+    }
+
+    fn report_unused(&mut self, statement: &DangStatement, found: DangType) {
+        let is_call = matches!(&statement.kind, DangStatementKind::FunctionCall(function_call)
+            if !function_call.is_built_in || function_call.name != Symbol::LOAD);
+        if !found.is_compatible(DangType::Unit) && !is_call {
+            self.report(statement, format!("unused value of type `{}`", found));
+        }
+    }
+
+    fn check_block(&mut self, block: &mut DangBlock) {
+        self.scopes.push(HashMap::new());
+        for symbol in &mut block.symbols {
+            self.check_unused(symbol);
+        }
+        self.scopes.pop();
+    }
+
+    // `expected` is only a hint for integer literals, the caller still has
+    // to compare it with the returned type
+    pub fn check_statement(&mut self, statement: &mut DangStatement, expected: Option<DangType>) -> DangType {
+        let ty = self.check_statement_kind(statement, expected);
+        statement.ty = ty;
+        ty
+    }
+
+    fn check_statement_kind(&mut self, statement: &mut DangStatement, expected: Option<DangType>) -> DangType {
+        use DangStatementKind::*;
+        let span = statement.span;
+        match &mut statement.kind {
+            Name(variable) => match self.lookup_variable(variable.name) {
+                Some(found) => {
+                    variable.offset = found.offset;
+                    found.ty
+                }
+                None => {
+                    let message = format!("cannot find `{}` in this scope", variable.name);
+                    self.diagnostics.push(Diagnostic::new(span, message));
+                    DangType::Unknown
+                }
+            }
+            Let(let_statement) => {
+                let annotation = let_statement.annotation.map(|x| self.check_type(span, x));
+                let_statement.annotation = annotation;
+                let found = match annotation {
+                    Some(annotation) => {
+                        self.expect_type(&mut let_statement.value, annotation);
+                        annotation
+                    }
+                    None => self.check_statement(&mut let_statement.value, None)
+                };
+                if found == DangType::Unit {
+                    self.report(&let_statement.value, format!("cannot bind a value of type `{}`", found));
+                }
+                // declared after its value is checked, so `let x = x + 1`
+                // still sees the previous `x`
+                let_statement.variable.offset = self.declare_variable(let_statement.variable.name, found);
+                DangType::Unit
+            }
+            Assignment(assignment) => {
+                let target = self.check_statement(&mut assignment.target, None);
+                if !is_place(&assignment.target) {
+                    self.report(&assignment.target, "invalid left-hand side of assignment".to_string());
+                }
+                match assignment.operation {
+                    Some(operation) => self.check_compound_assignment(assignment, operation, target),
+                    None            => self.expect_type(&mut assignment.value, target)
+                }
+                DangType::Unit
+            }
+            Return(return_statement) => {
+                let Some(return_type) = self.return_type else {
+                    self.diagnostics.push(Diagnostic::new(span, "`return` outside of a function".to_string()));
+                    return DangType::Unit;
+                };
+                match &mut return_statement.value {
+                    Some(value) => self.expect_type(value, return_type),
+                    None if return_type != DangType::Unit => {
+                        let message = format!("mismatched types: expected `{}`, found `()`", return_type);
+                        self.diagnostics.push(Diagnostic::new(span, message));
+                    }
+                    None => {}
+                }
+                DangType::Unit
+            }
+            Expression(expression) => self.check_statement(&mut expression.symbols[0], expected),
+            FunctionCall(function_call) => self.check_function_call(span, function_call),
+            Block(block) => {
+                self.check_block(block);
+                DangType::Unit
+            }
+            If(if_statement) => {
+                self.expect_type(&mut if_statement.condition, DangType::Bool);
+                self.check_block(&mut if_statement.then_block);
+                if let Some(else_block) = &mut if_statement.else_block {
+                    self.check_block(else_block);
+                }
+                DangType::Unit
+            }
+            Operation(operation) => self.check_operation(operation, expected),
+            UnaryOperation(unary_operation) => self.check_unary_operation(unary_operation, expected),
+            Cast(cast) => {
+                cast.target = self.check_type(span, cast.target);
+                // like everywhere else a literal becomes the type it is used
+                // as, so `300 as u8` is out of range rather than truncated
+                let hint = if is_integer_literal(&cast.operand) { cast.target.as_integer().map(DangType::Integer) } else { None };
+                let found = self.check_statement(&mut cast.operand, hint);
+                // pointers are plain 64 bit addresses
+                let address = |ty: DangType| matches!(ty.as_integer(), Some(DangIntegerType::I64 | DangIntegerType::U64));
+                let castable = matches!((found, cast.target),
+                    (DangType::Integer(_) | DangType::Bool, DangType::Integer(_)) |
+                    (DangType::Bool, DangType::Bool) |
+                    (DangType::Pointer(_), DangType::Pointer(_)) |
+                    (DangType::Unknown, _)) ||
+                    (matches!(found, DangType::Pointer(_)) && address(cast.target)) ||
+                    (address(found) && matches!(cast.target, DangType::Pointer(_)));
+                if !castable {
+                    let message = format!("cannot cast `{}` as `{}`", found, cast.target);
+                    self.diagnostics.push(Diagnostic::new(span, message));
+                }
+                cast.target
+            }
+            Array(array) => self.check_array(array, expected),
+            Index(index) => self.check_index(index),
+            StructLiteral(struct_literal) => self.check_struct_literal(span, struct_literal),
+            FieldAccess(field_access) => self.check_field_access(field_access),
+            Dereference(dereference) => match self.check_statement(&mut dereference.operand, None) {
+                DangType::Pointer(pointee) => *pointee,
+                DangType::Unknown          => DangType::Unknown,
+                found => {
+                    self.report(&dereference.operand, format!("cannot dereference a value of type `{}`", found));
+                    DangType::Unknown
+                }
+            }
+            AddressOf(address_of) => {
+                let found = self.check_statement(&mut address_of.operand, None);
+                if !is_place(&address_of.operand) {
+                    self.report(&address_of.operand, "cannot take the address of a temporary value".to_string());
+                }
+                if found == DangType::Unknown { found } else { DangType::pointer(found) }
+            }
+            Number(number) => {
+                let ty = match expected {
+                    Some(DangType::Integer(integer_type)) => DangType::Integer(integer_type),
+                    _                                     => DangType::DEFAULT_INTEGER
+                };
+                self.check_literal_range(span, *number as i128, ty);
+                ty
+            }
+            Bool(_) => DangType::Bool
+        }
+    }
+
+    fn check_array(&mut self, array: &mut DangArray, expected: Option<DangType>) -> DangType {
+        let hint = match expected {
+            Some(DangType::Array(element, _)) => Some(*element),
+            _                                 => None
+        };
+
+        let (first, rest) = array.elements.split_first_mut().unwrap();
+        let element = self.check_statement(first, hint);
+        for symbol in rest {
+            self.expect_type(symbol, element);
+        }
+
+        if element == DangType::Unit {
+            self.report(&array.elements[0], format!("cannot put a value of type `{}` in an array", element));
+        }
+
+        let length = array.repeat.unwrap_or(array.elements.len() as u64);
+        let ty = DangType::array(element, length);
+        array.offset = self.allocate(ty);
+        ty
+    }
+
+    fn check_index(&mut self, index: &mut DangIndex) -> DangType {
+        let base = self.check_statement(&mut index.base, None);
+        let hint = if is_integer_literal(&index.index) { Some(DangType::Integer(DangIntegerType::U64)) } else { None };
+        let found = self.check_statement(&mut index.index, hint);
+
+        if !matches!(found, DangType::Integer(_) | DangType::Unknown) {
+            self.report(&index.index, format!("cannot index with a value of type `{}`", found));
+        }
+
+        match base {
+            DangType::Array(element, length) => {
+                if let DangStatementKind::Number(number) = index.index.kind {
+                    if number >= length {
+                        let message = format!("index out of bounds: the length is {} but the index is {}", length, number);
+                        self.report(&index.index, message);
+                    }
+                }
+                *element
+            }
+            DangType::Unknown => DangType::Unknown,
+            _ => {
+                self.report(&index.base, format!("cannot index into a value of type `{}`", base));
+                DangType::Unknown
+            }
+        }
+    }
+
+    fn check_struct_literal(&mut self, span: LexerSpan, struct_literal: &mut DangStructLiteral) -> DangType {
+        let Some(layout) = DangLayout::struct_layout(struct_literal.name) else {
+            let message = format!("cannot find struct `{}` in this scope", struct_literal.name);
+            self.diagnostics.push(Diagnostic::new(span, message));
+            for field in &mut struct_literal.fields {
+                self.check_statement(&mut field.value, None);
+            }
+            return DangType::Unknown;
+        };
+
+        let mut initialized = HashSet::new();
+        for field in &mut struct_literal.fields {
+            let Some(declared) = layout.fields.iter().find(|x| x.name == field.name) else {
+                self.report(&field.value, format!("struct `{}` has no field `{}`", struct_literal.name, field.name));
+                self.check_statement(&mut field.value, None);
+                continue;
+            };
+            if !initialized.insert(field.name) {
+                self.report(&field.value, format!("field `{}` is initialized more than once", field.name));
+            }
+            field.offset = declared.offset;
+            self.expect_type(&mut field.value, declared.ty);
+        }
+
+        for declared in &layout.fields {
+            if !initialized.contains(&declared.name) {
+                let message = format!("missing field `{}` in `{}`", declared.name, struct_literal.name);
+                self.diagnostics.push(Diagnostic::new(span, message));
+            }
+        }
+
+        let ty = DangType::Struct(struct_literal.name);
+        struct_literal.offset = self.allocate(ty);
+        ty
+    }
+
+    // fields are reached through pointers as well, `p.x` works for both a
+    // `Point` and a `&Point`
+    fn check_field_access(&mut self, field_access: &mut DangFieldAccess) -> DangType {
+        let base = self.check_statement(&mut field_access.base, None);
+        let name = match base {
+            DangType::Struct(name)                    => name,
+            DangType::Pointer(DangType::Struct(name)) => *name,
+            DangType::Unknown                         => return DangType::Unknown,
+            _ => {
+                self.report(&field_access.base, format!("no field `{}` on type `{}`", field_access.field, base));
+                return DangType::Unknown;
+            }
+        };
+
+        match DangLayout::field(name, field_access.field) {
+            Some(field) => {
+                field_access.offset = field.offset;
+                field.ty
+            }
+            None => {
+                self.report(&field_access.base, format!("no field `{}` on type `{}`", field_access.field, base));
+                DangType::Unknown
+            }
+        }
+    }
+
+    // a pointer of any type or a raw 64 bit integer
+    fn expect_address(&mut self, address: &mut DangStatement) {
+        let hint = if is_integer_literal(address) { Some(DangType::Integer(DangIntegerType::U64)) } else { None };
+        let found = self.check_statement(address, hint);
+        let is_address = match found {
+            DangType::Pointer(_) | DangType::Unknown => true,
+            _ => matches!(found.as_integer(), Some(DangIntegerType::I64 | DangIntegerType::U64))
+        };
+        if !is_address {
+            self.report(address, format!("expected an address, found `{}`", found));
+        }
+    }
+
+    // `load!(address, bytes)` and `store!(address, value, bytes)`, the
+    // address is a pointer of any type or a raw 64 bit integer
+    fn check_memory_access(&mut self, span: LexerSpan, function_call: &mut DangFunctionCall) -> DangType {
+        let store = function_call.name == Symbol::STORE;
+        let arguments = if store { 3 } else { 2 };
+        if function_call.parameters.len() != arguments {
+            let message = format!("`{}!` takes {} arguments but {} were supplied", function_call.name, arguments, function_call.parameters.len());
+            self.diagnostics.push(Diagnostic::new(span, message));
+            for param in &mut function_call.parameters {
+                self.check_statement(param, None);
+            }
+            return if store { DangType::Unit } else { DangType::Unknown };
+        }
+
+        let width = function_call.parameters.last().unwrap();
+        let integer_type = match width.kind {
+            DangStatementKind::Number(1) => Some(DangIntegerType::U8),
+            DangStatementKind::Number(2) => Some(DangIntegerType::U16),
+            DangStatementKind::Number(4) => Some(DangIntegerType::U32),
+            DangStatementKind::Number(8) => Some(DangIntegerType::U64),
+            _ => None
+        };
+        let Some(integer_type) = integer_type else {
+            self.report(width, format!("`{}!` can only access 1, 2, 4 or 8 bytes at once", function_call.name));
+            return if store { DangType::Unit } else { DangType::Unknown };
+        };
+        let ty = DangType::Integer(integer_type);
+        function_call.parameters.last_mut().unwrap().ty = ty;
+
+        self.expect_address(&mut function_call.parameters[0]);
+
+        if store {
+            // any integer is truncated to the width, literals have to fit it
+            let value = &mut function_call.parameters[1];
+            let hint = if is_integer_literal(value) { Some(ty) } else { None };
+            let found = self.check_statement(value, hint);
+            if !matches!(found, DangType::Integer(_) | DangType::Bool | DangType::Unknown) {
+                self.report(&function_call.parameters[1], format!("`store!` cannot store a value of type `{}`", found));
+            }
+            DangType::Unit
+        } else {
+            ty
+        }
+    }
+
+    fn check_literal_range(&mut self, span: LexerSpan, value: i128, ty: DangType) {
+        let integer_type = ty.as_integer().unwrap();
+        if value < integer_type.min() || value > integer_type.max() {
+            let message = format!("literal `{}` out of range for `{}`", value, ty);
+            self.diagnostics.push(Diagnostic::new(span, message));
+        }
+    }
+
+    fn check_unary_operation(&mut self, unary_operation: &mut DangUnaryOperation, expected: Option<DangType>) -> DangType {
+        match unary_operation.unary_operation_type {
+            DangUnaryOperationType::Not => {
+                self.expect_type(&mut unary_operation.operand, DangType::Bool);
+                DangType::Bool
+            }
+            DangUnaryOperationType::Negate => {
+                // `-128` is a valid `i8` even though `128` is not, so negated
+                // literals are checked as a whole
+                if let DangStatementKind::Number(number) = unary_operation.operand.kind {
+                    let ty = match expected {
+                        Some(DangType::Integer(integer_type)) => DangType::Integer(integer_type),
+                        _                                     => DangType::DEFAULT_INTEGER
+                    };
+                    unary_operation.operand.ty = ty;
+                    self.check_literal_range(unary_operation.operand.span, -(number as i128), ty);
+                    return ty;
+                }
+
+                let found = self.check_statement(&mut unary_operation.operand, expected);
+                match found.as_integer() {
+                    Some(integer_type) if !integer_type.is_signed() => {
+                        self.report(&unary_operation.operand, format!("cannot negate unsigned `{}`", found));
+                    }
+                    None if found != DangType::Unknown => {
+                        self.report(&unary_operation.operand, format!("cannot negate `{}`", found));
+                    }
+                    _ => {}
+                }
+                found
+            }
+        }
+    }
+
+    fn check_operation(&mut self, operation: &mut DangOperation, expected: Option<DangType>) -> DangType {
+        let binary_operation_type = operation.binary_operation_type;
+
+        if binary_operation_type.is_logical() {
+            self.expect_type(&mut operation.first_operand, DangType::Bool);
+            self.expect_type(&mut operation.second_operand, DangType::Bool);
+            return DangType::Bool;
+        }
+
+        // the result of a comparison says nothing about its operands
+        let hint = if binary_operation_type.is_comparison() { None } else { expected };
+
+        // a literal on the left takes its type from the right
+        let (first, second) = if is_integer_literal(&operation.first_operand) && !is_integer_literal(&operation.second_operand) {
+            let second = self.check_statement(&mut operation.second_operand, hint);
+            let first = self.check_statement(&mut operation.first_operand, Some(second));
+            (first, second)
+        } else {
+            let first = self.check_statement(&mut operation.first_operand, hint);
+            let second = self.check_statement(&mut operation.second_operand, Some(first));
+            (first, second)
+        };
+
+        // pointers move by integers and their difference is the count of
+        // pointees between them
+        if let DangType::Pointer(_) = first {
+            match binary_operation_type {
+                DangBinaryOperationType::Plus | DangBinaryOperationType::Minus if second.as_integer().is_some() => return first,
+                DangBinaryOperationType::Minus if second == first => return DangType::DEFAULT_INTEGER,
+                _ => {}
+            }
+        }
+
+        // `==` and `!=` work on any two values of the same type, pointers
+        // can also be ordered, everything else only works on integers
+        let equality = matches!(binary_operation_type, DangBinaryOperationType::Equal | DangBinaryOperationType::NotEqual);
+        let operand_ok = match first {
+            DangType::Integer(_) | DangType::Unknown                   => true,
+            DangType::Bool                                             => equality,
+            DangType::Pointer(_)                                       => binary_operation_type.is_comparison(),
+            DangType::Unit | DangType::Array(..) | DangType::Struct(_) => false
+        };
+
+        if !operand_ok {
+            self.report(&operation.first_operand, format!("cannot apply `{}` to `{}`", binary_operation_type.as_string(), first));
+        } else if !second.is_compatible(first) {
+            self.report(&operation.second_operand, format!("mismatched types: expected `{}`, found `{}`", first, second));
+        }
+
+        if binary_operation_type.is_comparison() {
+            DangType::Bool
+        } else if first == DangType::Unknown {
+            second
+        } else {
+            first
+        }
+    }
+
+    // `target += value` takes what `target + value` does and has to give
+    // back the type of `target`, pointers move by integers
+    fn check_compound_assignment(&mut self, assignment: &mut DangAssignment, operation: DangBinaryOperationType, target: DangType) {
+        if let DangType::Pointer(_) = target {
+            let found = self.check_statement(&mut assignment.value, None);
+            if found.as_integer().is_none() && found != DangType::Unknown {
+                self.report(&assignment.value, format!("mismatched types: expected an integer, found `{}`", found));
+            }
+        } else if matches!(target, DangType::Integer(_) | DangType::Unknown) {
+            self.expect_type(&mut assignment.value, target);
+        } else {
+            self.check_statement(&mut assignment.value, None);
+            self.report(&assignment.target, format!("cannot apply `{}=` to `{}`", operation.as_string(), target));
+        }
+
+        if !matches!(assignment.target.kind, DangStatementKind::Name(_)) {
+            assignment.offset = self.allocate(DangType::DEFAULT_INTEGER);
+        }
+    }
+
+    fn check_function_call(&mut self, span: LexerSpan, function_call: &mut DangFunctionCall) -> DangType {
+        if !function_call.is_built_in {
+            let Some(&index) = self.functions.get(&function_call.name) else {
+                let message = format!("cannot find function `{}` in this scope", function_call.name);
+                self.diagnostics.push(Diagnostic::new(span, message));
+                return DangType::Unknown;
+            };
+            function_call.function = Some(index);
+
+            let parameters = self.signatures[index].parameters.clone();
+            if function_call.parameters.len() != parameters.len() {
+                let message = format!("`{}` takes {} arguments but {} were supplied", function_call.name, parameters.len(), function_call.parameters.len());
+                self.diagnostics.push(Diagnostic::new(span, message));
+            }
+            for (param, expected) in function_call.parameters.iter_mut().zip(parameters) {
+                self.expect_type(param, expected);
+            }
+            return self.signatures[index].return_type;
+        }
+
+        match DangBuiltIn::from_symbol(function_call.name) {
+            DangBuiltIn::Print => {
+                if function_call.parameters.len() != 1 {
+                    let message = format!("`print!` takes 1 argument but {} were supplied", function_call.parameters.len());
+                    self.diagnostics.push(Diagnostic::new(span, message));
+                }
+                for param in &mut function_call.parameters {
+                    let found = self.check_statement(param, None);
+                    if !matches!(found, DangType::Integer(_) | DangType::Bool | DangType::Unknown) {
+                        self.report(param, format!("`print!` cannot print a value of type `{}`", found));
+                    }
+                }
+                DangType::Unit
+            }
+            DangBuiltIn::Load | DangBuiltIn::Store => self.check_memory_access(span, function_call),
+            DangBuiltIn::Exit => {
+                if function_call.parameters.len() != 1 {
+                    let message = format!("`exit!` takes 1 argument but {} were supplied", function_call.parameters.len());
+                    self.diagnostics.push(Diagnostic::new(span, message));
+                }
+                for param in &mut function_call.parameters {
+                    let found = self.check_statement(param, None);
+                    if !matches!(found, DangType::Integer(_) | DangType::Unknown) {
+                        self.report(param, format!("mismatched types: expected an integer, found `{}`", found));
+                    }
+                }
+                DangType::Unit
+            }
+            DangBuiltIn::ReadInt => {
+                if !function_call.parameters.is_empty() {
+                    let message = format!("`read_int!` takes 0 arguments but {} were supplied", function_call.parameters.len());
+                    self.diagnostics.push(Diagnostic::new(span, message));
+                }
+                DangType::DEFAULT_INTEGER
+            }
+            DangBuiltIn::ReadLine => {
+                if function_call.parameters.len() != 2 {
+                    let message = format!("`read_line!` takes 2 arguments but {} were supplied", function_call.parameters.len());
+                    self.diagnostics.push(Diagnostic::new(span, message));
+                    for param in &mut function_call.parameters {
+                        self.check_statement(param, None);
+                    }
+                    return DangType::DEFAULT_INTEGER;
+                }
+                self.expect_address(&mut function_call.parameters[0]);
+                let capacity = &mut function_call.parameters[1];
+                let hint = if is_integer_literal(capacity) { Some(DangType::Integer(DangIntegerType::U64)) } else { None };
+                let found = self.check_statement(capacity, hint);
+                if !matches!(found, DangType::Integer(_) | DangType::Unknown) {
+                    self.report(&function_call.parameters[1], format!("mismatched types: expected an integer, found `{}`", found));
+                }
+                DangType::DEFAULT_INTEGER
+            }
+            DangBuiltIn::Argc => {
+                if !function_call.parameters.is_empty() {
+                    let message = format!("`argc!` takes 0 arguments but {} were supplied", function_call.parameters.len());
+                    self.diagnostics.push(Diagnostic::new(span, message));
+                }
+                DangType::DEFAULT_INTEGER
+            }
+            DangBuiltIn::Arg | DangBuiltIn::Env => {
+                if function_call.parameters.len() != 1 {
+                    let message = format!("`{}!` takes 1 argument but {} were supplied", function_call.name, function_call.parameters.len());
+                    self.diagnostics.push(Diagnostic::new(span, message));
+                }
+                for param in &mut function_call.parameters {
+                    let hint = if is_integer_literal(param) { Some(DangType::Integer(DangIntegerType::U64)) } else { None };
+                    let found = self.check_statement(param, hint);
+                    if !matches!(found, DangType::Integer(_) | DangType::Unknown) {
+                        self.report(param, format!("mismatched types: expected an integer, found `{}`", found));
+                    }
+                }
+                DangType::pointer(DangType::Integer(DangIntegerType::U8))
+            }
+            DangBuiltIn::Syscall => {
+                if function_call.parameters.is_empty() || function_call.parameters.len() > 7 {
+                    let message = format!("`syscall!` takes 1 to 7 arguments but {} were supplied", function_call.parameters.len());
+                    self.diagnostics.push(Diagnostic::new(span, message));
+                }
+                // everything goes into a 64 bit register, so any scalar will do
+                for param in &mut function_call.parameters {
+                    let found = self.check_statement(param, None);
+                    if !matches!(found, DangType::Integer(_) | DangType::Bool | DangType::Pointer(_) | DangType::Unknown) {
+                        self.report(param, format!("`syscall!` cannot take a value of type `{}`", found));
+                    }
+                }
+                DangType::DEFAULT_INTEGER
+            }
+            DangBuiltIn::Unknown => {
+                let message = format!("cannot find built-in `{}!`", function_call.name);
+                self.diagnostics.push(Diagnostic::new(span, message));
+                DangType::Unknown
+            }
+            DangBuiltIn::Count => panic!("unreachable")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::*;
+
+    // the messages reported for `source`, each after the byte it points at
+    fn check(source: &str) -> Vec<String> {
+        let mut ast = Parser::new(Lexer::from_source(source)).parse_ast().unwrap();
+        match TypeChecker::check_ast(&mut ast) {
+            Ok(())           => vec![],
+            Err(diagnostics) => diagnostics.iter().map(|x| format!("{}: {}", x.span.start, x.message)).collect()
+        }
+    }
+
+    #[test]
+    fn well_typed_programs_pass() {
+        assert!(check("print!(34 + 35); if 10 * (2 + 3) > 1 { print!(1) } else { print!(0) }").is_empty());
+        assert!(check("print!(1 == 2 && !(3 < 4)); print!(true != false)").is_empty());
+    }
+
+    #[test]
+    fn mismatched_operands_are_reported() {
+        assert_eq!(check("print!(1 + true)"), vec!["11: mismatched types: expected `i64`, found `bool`"]);
+        assert_eq!(check("print!(true * 2)"), vec!["7: cannot apply `*` to `bool`"]);
+        assert_eq!(check("print!(1 && true)"), vec!["7: mismatched types: expected `bool`, found `i64`"]);
+        assert_eq!(check("if 1 { print!(1) }"), vec!["3: mismatched types: expected `bool`, found `i64`"]);
+    }
+
+    #[test]
+    fn compound_assignments_keep_the_type_of_their_target() {
+        assert!(check("let x: u8 = 1; x += 2; let p = &x; p += 1; *p += x").is_empty());
+        assert_eq!(check("let x: u8 = 1; x += 2 as i64"), vec!["20: mismatched types: expected `u8`, found `i64`"]);
+        assert_eq!(check("let b = true; b += true"), vec!["14: cannot apply `+=` to `bool`"]);
+        assert_eq!(check("let b = true; let p = &b; p += b"), vec!["31: mismatched types: expected an integer, found `bool`"]);
+    }
+
+    // one mistake is reported once, not again by everything around it
+    #[test]
+    fn unknown_types_are_not_reported_twice() {
+        assert_eq!(check("print!(x + 1)"), vec!["7: cannot find `x` in this scope"]);
+    }
+
+    #[test]
+    fn unused_values_and_bad_calls_are_reported() {
+        assert_eq!(check("1 + 2; print!(1)"), vec!["0: unused value of type `i64`"]);
+        assert_eq!(check("print!(1, 2)"), vec!["0: `print!` takes 1 argument but 2 were supplied"]);
+        assert_eq!(check("foo!(1)"), vec!["0: cannot find built-in `foo!`"]);
+        assert_eq!(check("print!(print!(1))"), vec!["7: `print!` cannot print a value of type `()`"]);
+    }
+
+    #[test]
+    fn literals_take_the_type_they_are_used_as() {
+        assert!(check("print!(255 as u8); print!(-128 as i8); print!(200 as u8 + 55 as u8)").is_empty());
+        assert_eq!(check("print!(300 as u8)"), vec!["7: literal `300` out of range for `u8`"]);
+        assert_eq!(check("print!(-129 as i8)"), vec!["8: literal `-129` out of range for `i8`"]);
+        assert_eq!(check("print!(1 as u8 + 256)"), vec!["17: literal `256` out of range for `u8`"]);
+    }
+
+    #[test]
+    fn sized_integers_do_not_mix() {
+        assert_eq!(check("print!(1 as u8 + 1 as i8)"), vec!["17: mismatched types: expected `u8`, found `i8`"]);
+        assert_eq!(check("print!(-(1 as u32))"), vec!["8: cannot negate unsigned `u32`"]);
+        assert!(check("print!((1 as u8) as i64 + 2)").is_empty());
+    }
+
+    #[test]
+    fn only_integers_and_bools_cast() {
+        assert!(check("print!(true as u8); print!(false as bool)").is_empty());
+        assert_eq!(check("print!(1 as bool)"), vec!["7: cannot cast `i64` as `bool`"]);
+    }
+
+    #[test]
+    fn let_bindings_take_their_annotation() {
+        assert!(check("let x: u8 = 200; print!(x + 55); let y = x; print!(y)").is_empty());
+        assert_eq!(check("let x: u8 = 256"), vec!["12: literal `256` out of range for `u8`"]);
+        assert_eq!(check("let x: bool = 1"), vec!["14: mismatched types: expected `bool`, found `i64`"]);
+        assert_eq!(check("let x = print!(1)"), vec!["8: cannot bind a value of type `()`"]);
+        assert_eq!(check("{ let x = 1 }; print!(x)"), vec!["22: cannot find `x` in this scope"]);
+    }
+
+    #[test]
+    fn functions_are_checked_against_their_signature() {
+        assert!(check("fn add(a: u8, b: u8) -> u8 { return a + b }; print!(add(1, 2))").is_empty());
+        assert_eq!(check("fn f(a) {}"), vec!["5: parameter `a` needs a type annotation"]);
+        assert_eq!(check("fn f() -> bool { return 1 }"), vec!["24: mismatched types: expected `bool`, found `i64`"]);
+        assert_eq!(check("fn f() -> i64 { if true { return 1 } }"), vec!["0: function `f` does not return a `i64` on every path"]);
+        assert_eq!(check("fn f(a: i64) {}; f(1, 2)"), vec!["17: `f` takes 1 arguments but 2 were supplied"]);
+        assert_eq!(check("fn f(a: u8) {}; f(true)"), vec!["18: mismatched types: expected `u8`, found `bool`"]);
+        assert_eq!(check("fn f() {}; fn f() {}"), vec!["11: function `f` is declared more than once"]);
+    }
+}
@@ -0,0 +1,162 @@
+use crate::layout::*;
+use crate::symbol::*;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DangIntegerType {
+    I8,
+    I16,
+    I32,
+    I64,
+    U8,
+    U16,
+    U32,
+    U64
+}
+
+impl DangIntegerType {
+    pub fn bits(self) -> u32 {
+        use DangIntegerType::*;
+        match self {
+            I8  | U8  => 8,
+            I16 | U16 => 16,
+            I32 | U32 => 32,
+            I64 | U64 => 64
+        }
+    }
+
+    pub fn is_signed(self) -> bool {
+        use DangIntegerType::*;
+        matches!(self, I8 | I16 | I32 | I64)
+    }
+
+    pub fn min(self) -> i128 {
+        if self.is_signed() {
+            -(1 << (self.bits() - 1))
+        } else {
+            0
+        }
+    }
+
+    pub fn max(self) -> i128 {
+        if self.is_signed() {
+            (1 << (self.bits() - 1)) - 1
+        } else {
+            (1 << self.bits()) - 1
+        }
+    }
+
+    pub fn as_string(self) -> String {
+        let prefix = if self.is_signed() { "i" } else { "u" };
+        format!("{}{}", prefix, self.bits())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DangType {
+    Integer(DangIntegerType),
+    Bool,
+    Unit,
+    Array(&'static DangType, u64),
+    // structs are known by name, `DangLayout` knows their fields
+    Struct(Symbol),
+    Pointer(&'static DangType),
+    // the type of something that already failed to check, it is compatible
+    // with everything so one mistake is only reported once
+    Unknown
+}
+
+impl DangType {
+    // the type of integer literals nothing else gave a type to
+    pub const DEFAULT_INTEGER: DangType = DangType::Integer(DangIntegerType::I64);
+
+    pub fn from_name(name: &str) -> Option<DangType> {
+        use DangIntegerType::*;
+        match name {
+            "i8"   => Some(DangType::Integer(I8)),
+            "i16"  => Some(DangType::Integer(I16)),
+            "i32"  => Some(DangType::Integer(I32)),
+            "i64"  => Some(DangType::Integer(I64)),
+            "u8"   => Some(DangType::Integer(U8)),
+            "u16"  => Some(DangType::Integer(U16)),
+            "u32"  => Some(DangType::Integer(U32)),
+            "u64"  => Some(DangType::Integer(U64)),
+            "bool" => Some(DangType::Bool),
+            _      => None
+        }
+    }
+
+    // element types are interned like symbols are, which keeps `DangType`
+    // `Copy`
+    pub fn array(element: DangType, length: u64) -> DangType {
+        DangType::Array(intern(element), length)
+    }
+
+    pub fn pointer(pointee: DangType) -> DangType {
+        DangType::Pointer(intern(pointee))
+    }
+
+    // bytes the value takes in memory, scalar variables still get a whole 8
+    // byte slot of their own
+    pub fn size(self) -> i64 {
+        DangLayout::of(self).size
+    }
+
+    // aggregates do not fit in a register, expressions of these types
+    // evaluate to the address of the value instead
+    pub fn is_aggregate(self) -> bool {
+        matches!(self, DangType::Array(..) | DangType::Struct(_))
+    }
+
+    pub fn is_compatible(self, other: DangType) -> bool {
+        self == other || self == DangType::Unknown || other == DangType::Unknown
+    }
+
+    pub fn as_integer(self) -> Option<DangIntegerType> {
+        match self {
+            DangType::Integer(integer_type) => Some(integer_type),
+            _                               => None
+        }
+    }
+}
+
+thread_local! {
+    static TYPES: RefCell<HashMap<DangType, &'static DangType>> = RefCell::new(HashMap::new());
+}
+
+// the one copy of `ty` every array and pointer of it shares, each type is
+// only leaked the first time it is seen
+fn intern(ty: DangType) -> &'static DangType {
+    TYPES.with(|types| *types.borrow_mut().entry(ty).or_insert_with(|| Box::leak(Box::new(ty))))
+}
+
+impl Display for DangType {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self {
+            DangType::Integer(integer_type)  => write!(f, "{}", integer_type.as_string()),
+            DangType::Bool                   => write!(f, "bool"),
+            DangType::Unit                   => write!(f, "()"),
+            DangType::Array(element, length) => write!(f, "[{}; {}]", element, length),
+            DangType::Struct(name)           => write!(f, "{}", name),
+            DangType::Pointer(pointee)       => write!(f, "&{}", pointee),
+            DangType::Unknown                => write!(f, "{{unknown}}")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn element_types_are_interned() {
+        let DangType::Pointer(a) = DangType::pointer(DangType::array(DangType::Bool, 3)) else { unreachable!() };
+        let DangType::Pointer(b) = DangType::pointer(DangType::array(DangType::Bool, 3)) else { unreachable!() };
+        assert!(std::ptr::eq(a, b));
+        assert_eq!(DangType::pointer(DangType::Bool).to_string(), "&bool");
+        assert_ne!(DangType::array(DangType::Bool, 3), DangType::array(DangType::Bool, 4));
+    }
+}
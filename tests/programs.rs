@@ -0,0 +1,73 @@
+// Runs every program in `tests/programs`, dang sources and hand written IR,
+// through `dang sim` and compares what it prints with the `.out` file next to
+// it, feeding it the `.in` file next to it when there is one, and then what
+// every optimization level prints with the same, simulated and compiled. The
+// compiled ones need `nasm` and `ld`, run them with `cargo test -- --ignored`.
+
+use std::fs;
+use std::fs::File;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Stdio;
+
+// the program is always the last argument
+fn dang(arguments: &[&str]) -> String {
+    let input = Path::new(arguments.last().unwrap()).with_extension("in");
+    let stdin = match File::open(input) {
+        Ok(file) => Stdio::from(file),
+        Err(_)   => Stdio::null()
+    };
+
+    let output = Command::new(env!("CARGO_BIN_EXE_dang"))
+        .args(arguments)
+        .stdin(stdin)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "`dang {}` failed:\n{}", arguments.join(" "), String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+// the programs with the output they are expected to print
+fn programs() -> Vec<(String, String)> {
+    let directory = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/programs");
+    let mut programs: Vec<(String, String)> = fs::read_dir(&directory).unwrap()
+        .map(|x| x.unwrap().path())
+        .filter(|x| x.extension().is_some_and(|x| x == "dang" || x == "ir"))
+        .map(|x| {
+            let expected = fs::read_to_string(x.with_extension("out")).unwrap();
+            (x.to_string_lossy().into_owned(), expected)
+        })
+        .collect();
+    programs.sort();
+    programs
+}
+
+#[test]
+fn simulated_programs_print_what_is_expected() {
+    for (path, expected) in programs() {
+        assert_eq!(dang(&["sim", &path]), expected, "{}", path);
+    }
+}
+
+#[test]
+fn optimized_programs_print_the_same() {
+    for (path, expected) in programs() {
+        for level in ["-O1", "-O2", "-O3"] {
+            assert_eq!(dang(&["sim", level, &path]), expected, "{} at {}", path, level);
+        }
+    }
+}
+
+#[test]
+#[ignore = "needs nasm and ld"]
+fn native_programs_print_the_same() {
+    for (path, expected) in programs() {
+        let name = Path::new(&path).file_stem().unwrap().to_string_lossy().into_owned();
+        for level in ["-O0", "-O1", "-O2", "-O3"] {
+            let executable = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("{}{}", name, level));
+            let executable = executable.to_string_lossy();
+            assert_eq!(dang(&["run", level, "-o", &executable, &path]), expected, "{} at {}", path, level);
+        }
+    }
+}